@@ -1,69 +1,226 @@
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-use axum::{Json, Router, routing::get};
+use axum::{Json, Router, routing::{delete, get, post}};
 use axum::body::Body;
-use axum::extract::{Query, State};
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderName, StatusCode};
 use axum::response::{Html, IntoResponse, Response};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use dashmap::DashMap;
+use futures_util::Stream;
+use futures_util::stream;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use tokio::fs::create_dir_all;
-use tracing::{error, info};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{debug, error, info, warn};
 use tracing_appender::non_blocking::NonBlocking;
 use tracing_subscriber::{Layer, registry};
 use tracing_subscriber::fmt::layer;
 use tracing_subscriber::layer::SubscriberExt;
 
-use lmpic_downloader::{AlbumSearcher, parser};
+use lmpic_downloader::{AlbumSearcher, ClientConfig, DownloadConfig, parser, PauseGate, util};
+
+// 转发过的图片：地址 -> (内容, Content-Type, 获取时间)
+type PictureCache = DashMap<String, (Vec<u8>, HeaderMap, Instant)>;
+
+// 搜索建议：解析器代码-前缀 -> (专辑名称, 获取时间)
+type SuggestCache = DashMap<String, (Vec<String>, Instant)>;
 
 #[derive(Clone)]
 struct WebState {
     client: Client,
-    parser_cache: Arc<DashMap<String, Arc<dyn parser::Parser>>>,
-    searcher_cache: Arc<DashMap<String, AlbumSearcher>>
+    parser_cache: Arc<DashMap<String, Arc<dyn lmpic_downloader::Parser>>>,
+    searcher_cache: Arc<DashMap<String, AlbumSearcher>>,
+    picture_cache: Arc<PictureCache>,
+    suggest_cache: Arc<SuggestCache>,
+    task_id: Arc<AtomicU64>,
+    // 所有下载任务的进度事件
+    progress: broadcast::Sender<(u64, ProgressEvent)>,
+    // 下载任务及其最近一次的进度，新建立的进度连接从这里读取
+    jobs: Arc<DashMap<u64, JobHandle>>,
+    // 结束的任务保留的时长，过期后清理
+    job_ttl: Duration,
+    // 正在运行的下载任务，服务关闭时等待它们结束
+    tasks: TaskTracker,
+    // 所有下载任务共用的暂停开关
+    pause: PauseGate,
+    // 转发图片时请求上游的超时时间
+    picture_timeout: Duration,
+    // 转发图片的最大字节数，超过时返回 413
+    max_picture_size: u64
 }
 
+impl WebState {
+    fn new(client: Client, job_ttl: Duration) -> Self {
+        Self {
+            client,
+            parser_cache: Arc::new(DashMap::new()),
+            searcher_cache: Arc::new(DashMap::new()),
+            picture_cache: Arc::new(DashMap::new()),
+            suggest_cache: Arc::new(DashMap::new()),
+            task_id: Arc::new(AtomicU64::new(0)),
+            progress: broadcast::channel(256).0,
+            jobs: Arc::new(DashMap::new()),
+            job_ttl,
+            tasks: TaskTracker::new(),
+            pause: PauseGate::default(),
+            picture_timeout: DEFAULT_PICTURE_TIMEOUT,
+            max_picture_size: DEFAULT_MAX_PICTURE_SIZE
+        }
+    }
+}
+
+const PICTURE_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+const PICTURE_CACHE_CAPACITY: usize = 200;
+
+// 可以通过环境变量 PICTURE_TIMEOUT_SECS、MAX_PICTURE_SIZE_MB 修改
+const DEFAULT_PICTURE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const DEFAULT_MAX_PICTURE_SIZE: u64 = 20 * 1024 * 1024;
+
+const SUGGEST_CACHE_TTL: Duration = Duration::from_secs(60);
+
+const MAX_SUGGESTIONS: usize = 10;
+
+// 可以通过环境变量 JOB_TTL_SECS 修改
+const DEFAULT_JOB_TTL: Duration = Duration::from_secs(30 * 60);
+
+const JOB_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
 #[tokio::main]
 async fn main() {
     create_dir_all("./log").await.unwrap();
+    create_dir_all(AlbumSearcher::DEFAULT_DOWNLOAD_ROOT).await.unwrap();
 
     let file_appender = tracing_appender::rolling::never("./log", "downloader.log");
     let (non_blocking_appender, _guard) = NonBlocking::new(file_appender);
     let file_layer = layer()
         .with_writer(non_blocking_appender)
         .with_ansi(false)
-        .with_filter(tracing_subscriber::filter::LevelFilter::INFO);
+        .with_filter(util::log_filter());
     let subscriber = registry().with(file_layer);
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
-    let state = WebState {
-        client: Client::new(),
-        parser_cache: Arc::new(DashMap::new()),
-        searcher_cache: Arc::new(DashMap::new())
+    let job_ttl = std::env::var("JOB_TTL_SECS").ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_JOB_TTL);
+    // 可以通过环境变量 SHUTDOWN_TIMEOUT_SECS 修改
+    let shutdown_timeout = std::env::var("SHUTDOWN_TIMEOUT_SECS").ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DownloadConfig::default().shutdown_timeout);
+    // 所有解析器共用的 Client，可以通过 TLS_ROOT_CERTS、IP_PREFERENCE 等环境变量配置
+    let client = match ClientConfig::default().build_client() {
+        Ok(client) => client,
+        Err(err) => {
+            error!("build http client error: {:?}", err);
+            println!("创建 HTTP 客户端失败: {:#}", err);
+            return;
+        }
     };
+    let mut state = WebState::new(client, job_ttl);
+    if let Some(timeout) = std::env::var("PICTURE_TIMEOUT_SECS").ok().and_then(|secs| secs.parse().ok()) {
+        state.picture_timeout = Duration::from_secs(timeout);
+    }
+    if let Some(size) = std::env::var("MAX_PICTURE_SIZE_MB").ok().and_then(|size| size.parse::<u64>().ok()) {
+        state.max_picture_size = size.saturating_mul(1024 * 1024);
+    }
+
+    let jobs = state.jobs.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(JOB_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            sweep_jobs(&jobs, job_ttl);
+        }
+    });
 
     let app = Router::new()
         .route("/album", get(album))
         .route("/album/parsers", get(get_parsers))
+        .route("/album/categories", get(get_categories))
         .route("/album/search", get(search_albums))
+        .route("/album/suggest", get(suggest_albums))
         .route("/album/picture", get(forward_picture))
         .route("/album/pictures", get(get_album_by_url))
-        .with_state(state);
+        .route("/album/download", post(download_album))
+        .route("/album/download/{task_id}", delete(cancel_job))
+        .route("/album/download/progress/{task_id}", get(download_progress))
+        .route("/album/jobs", get(list_jobs))
+        .route("/album/jobs/{task_id}", delete(cancel_job))
+        .route("/admin/pause", post(pause_downloads))
+        .route("/admin/resume", post(resume_downloads))
+        .with_state(state.clone());
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     info!("web server starting...");
-    axum::serve(listener, app).await.unwrap();
+    let shutdown = CancellationToken::new();
+    let server = axum::serve(listener, app).with_graceful_shutdown(shutdown.clone().cancelled_owned());
+    let mut server = tokio::spawn(async move { server.await });
+    tokio::select! {
+        ret = &mut server => {
+            ret.unwrap().unwrap();
+            return;
+        }
+        _ = shutdown_signal() => {}
+    }
+
+    info!("graceful shutdown initiated");
+    shutdown.cancel();
+    drain_jobs(&state, shutdown_timeout).await;
+    // 下载结束后进度连接随之关闭，仍未关闭的连接直接丢弃
+    if tokio::time::timeout(Duration::from_secs(1), server).await.is_err() {
+        warn!("connections still open, close them");
+    }
+    info!("shutdown complete");
 }
 
-async fn album() -> Html<&'static str> {
-    Html(include_str!("../../templates/index.html"))
+// 收到 Ctrl+C (SIGINT) 或 SIGTERM 时返回
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to listen for ctrl+c");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {}
+    }
 }
 
-#[derive(Serialize)]
-struct Parser {
-    code: String,
-    name: String
+// 不再接收新的下载，等待进行中的下载结束，超过 timeout 后取消剩余的下载，返回是否全部按时结束
+async fn drain_jobs(state: &WebState, timeout: Duration) -> bool {
+    state.tasks.close();
+    if tokio::time::timeout(timeout, state.tasks.wait()).await.is_ok() {
+        return true;
+    }
+
+    warn!("{} download tasks still running after {:?}, cancel them", state.tasks.len(), timeout);
+    for job in state.jobs.iter() {
+        job.cancel.cancel();
+    }
+    false
+}
+
+async fn album() -> Html<&'static str> {
+    Html(include_str!("../../templates/index.html"))
 }
 
 #[derive(Serialize)]
@@ -146,34 +303,81 @@ impl <T> PaginationResponse<T> {
 
 }
 
-async fn get_parsers() -> Json<CommonResponse<Vec<Parser>>> {
-    let parsers = parser::parsers();
-    let parsers = parsers.into_iter().map(|p| {
-        Parser {
-            code: p.0,
-            name: p.1
+// 请求中未指定解析器时使用默认解析器
+fn default_parser_code() -> String {
+    parser::default_parser_code().to_string()
+}
+
+async fn get_parsers() -> Json<CommonResponse<Vec<parser::ParserInfo>>> {
+    Json(CommonResponse::success(parser::parsers()))
+}
+
+#[derive(Deserialize)]
+pub struct CategoryQuery {
+    #[serde(default = "default_parser_code")]
+    pub parser_code: String
+}
+
+#[derive(Serialize)]
+struct Category {
+    code: String,
+    name: String
+}
+
+async fn get_categories(Query(query): Query<CategoryQuery>, State(state): State<WebState>) -> Json<CommonResponse<Vec<Category>>> {
+    let parser = match parser::parse(&query.parser_code, Some(state.client.clone())) {
+        Ok(p) => p,
+        Err(err) => {
+            error!("parse from {} to parser error: {:?}", query.parser_code, err);
+            let error = format!("unknown parser: {}", query.parser_code);
+            return Json(CommonResponse::failure(-1, error, vec![]));
+        }
+    };
+
+    let response = match parser.list_categories().await {
+        Ok(categories) => {
+            let categories = categories.into_iter().map(|(code, name)| {
+                Category {
+                    code,
+                    name
+                }
+            }).collect();
+            CommonResponse::success(categories)
         }
-    }).collect::<Vec<Parser>>();
-    Json(CommonResponse::success(parsers))
+        Err(err) => {
+            let error = format!("list categories error: {:?}", err);
+            CommonResponse::failure(-1, error, vec![])
+        }
+    };
+    Json(response)
 }
 
 #[derive(Deserialize)]
 pub struct SearchQuery {
+    #[serde(default = "default_parser_code")]
     pub parser_code: String,
     pub keyword: String,
     pub page: u32,
-    pub size: u32
+    pub size: u32,
+    // 只在 [page_from, page_to] 范围内翻页，超出范围的页码会被限制在范围内
+    pub page_from: Option<u32>,
+    pub page_to: Option<u32>
 }
 
 #[derive(Serialize)]
 struct Album {
     name: String,
     cover: String,
-    url: String
+    url: String,
+    picture_count_hint: Option<u32>
+}
+
+fn searcher_key(parser_code: &str, keyword: &str) -> String {
+    format!("{}-{}", parser_code, keyword)
 }
 
 async fn search_albums(Query(query): Query<SearchQuery>, State(state): State<WebState>) -> Json<PaginationResponse<Vec<Album>>> {
-    let parser = match parser::parse(&query.parser_code) {
+    let parser = match parser::parse(&query.parser_code, Some(state.client.clone())) {
         Ok(p) => p,
         Err(err) => {
             let error = format!("unknown parser: {}", query.parser_code);
@@ -181,15 +385,14 @@ async fn search_albums(Query(query): Query<SearchQuery>, State(state): State<Web
         }
     };
 
-    let searcher_key = format!("{}-{}", query.parser_code, query.keyword);
-    let mut searcher = match state.searcher_cache.get_mut(&searcher_key) {
-        Some(searcher) => searcher,
-        None => {
-            let searcher = AlbumSearcher::new(parser, &query.keyword, AlbumSearcher::DEFAULT_PAGE_SIZE);
-            state.searcher_cache.insert(searcher_key.clone(), searcher);
-            state.searcher_cache.get_mut(&searcher_key).unwrap()
-        }
+    // 请求中的解析器代码不区分大小写，使用解析器自身的代码作为缓存键
+    let key = searcher_key(&parser.parser_code(), &query.keyword);
+    // 克隆一份搜索器再请求，避免在等待网络响应期间一直持有缓存的写锁
+    let mut searcher = match state.searcher_cache.get(&key) {
+        Some(searcher) => searcher.clone(),
+        None => AlbumSearcher::new(parser, &query.keyword, AlbumSearcher::DEFAULT_PAGE_SIZE)
     };
+    searcher.set_page_range(query.page_from.unwrap_or(1), query.page_to);
 
     let result = searcher.jump(&query.page).await;
     let response = match result {
@@ -198,33 +401,51 @@ async fn search_albums(Query(query): Query<SearchQuery>, State(state): State<Web
                 Album {
                     name: album.name.clone(),
                     cover: album.cover.clone().unwrap_or("".to_string()),
-                    url: album.url.clone()
+                    url: album.url.clone(),
+                    picture_count_hint: album.picture_count_hint
                 }
             }).collect::<Vec<Album>>();
-            PaginationResponse::success(albums, Pagination::new(query.page, searcher.page_count()))
+            // 找到专辑时总页数至少为当前页，避免前端隐藏分页，有页码范围时不超过结束页
+            let page_total = if albums.is_empty() { searcher.last_page() } else { searcher.last_page().max(searcher.page()) };
+            PaginationResponse::success(albums, Pagination::new(searcher.page(), page_total))
         },
         Err(err) => {
             let error = format!("search error: {:?}", err);
             PaginationResponse::failure(-1, error, vec![], Pagination::new(query.page, searcher.page_count()))
         }
     };
+    state.searcher_cache.insert(searcher_key(searcher.current_parser_code(), searcher.current_keyword()), searcher);
     Json(response)
 }
 
 #[derive(Deserialize)]
-pub struct AlbumQuery {
+pub struct SuggestQuery {
+    #[serde(default = "default_parser_code")]
     pub parser_code: String,
-    pub url: String
+    pub prefix: String
 }
 
-async fn get_album_by_url(Query(query): Query<AlbumQuery>, State(state): State<WebState>) -> Json<CommonResponse<Vec<String>>> {
+// 用前缀搜索第一页，返回去重后的专辑名称作为建议，效果取决于站点的搜索
+async fn suggest_albums(Query(query): Query<SuggestQuery>, State(state): State<WebState>) -> Json<CommonResponse<Vec<String>>> {
+    let prefix = query.prefix.trim();
+    if prefix.is_empty() {
+        return Json(CommonResponse::success(vec![]));
+    }
+
+    let key = searcher_key(&query.parser_code, prefix);
+    if let Some(entry) = state.suggest_cache.get(&key) {
+        if entry.1.elapsed() < SUGGEST_CACHE_TTL {
+            return Json(CommonResponse::success(entry.0.clone()));
+        }
+    }
+
     let parser = match state.parser_cache.get(&query.parser_code) {
-        Some(p) => p,
+        Some(p) => p.clone(),
         None => {
-            match parser::parse(&query.parser_code) {
+            match parser::parse(&query.parser_code, Some(state.client.clone())) {
                 Ok(p) => {
-                    state.parser_cache.insert(query.parser_code.clone(), p);
-                    state.parser_cache.get(&query.parser_code).unwrap()
+                    state.parser_cache.insert(query.parser_code.clone(), p.clone());
+                    p
                 }
                 Err(err) => {
                     error!("parse from {} to parser error: {:?}", query.parser_code, err);
@@ -235,19 +456,420 @@ async fn get_album_by_url(Query(query): Query<AlbumQuery>, State(state): State<W
         }
     };
 
+    let response = match parser.parse_albums(prefix.to_string(), 1, AlbumSearcher::DEFAULT_PAGE_SIZE).await {
+        Ok((albums, _)) => {
+            let mut suggestions: Vec<String> = Vec::new();
+            for name in albums.into_iter().map(|album| album.name.trim().to_string()) {
+                if !name.is_empty() && !suggestions.contains(&name) {
+                    suggestions.push(name);
+                }
+            }
+            suggestions.truncate(MAX_SUGGESTIONS);
+            state.suggest_cache.retain(|_, (_, fetched)| fetched.elapsed() < SUGGEST_CACHE_TTL);
+            state.suggest_cache.insert(key, (suggestions.clone(), Instant::now()));
+            CommonResponse::success(suggestions)
+        }
+        Err(err) => {
+            let error = format!("suggest albums error: {:?}", err);
+            CommonResponse::failure(-1, error, vec![])
+        }
+    };
+    Json(response)
+}
+
+#[derive(Deserialize)]
+pub struct AlbumQuery {
+    // 未指定时根据专辑地址的域名选择解析器
+    pub parser_code: Option<String>,
+    pub url: String,
+    pub from: Option<usize>,
+    pub to: Option<usize>,
+    // 按 size 张图片分页返回，page 从 1 开始
+    pub page: Option<u32>,
+    pub size: Option<u32>,
+    // 只请求专辑在来源站点的第 source_page 个分页，不需要等待整个专辑
+    pub source_page: Option<u32>
+}
+
+const DEFAULT_PICTURE_PAGE_SIZE: u32 = 20;
+
+#[derive(Serialize)]
+struct PicturePage {
+    pictures: Vec<String>,
+    // 专辑的图片总数，按来源分页请求时未知
+    total: Option<usize>
+}
+
+fn forward_url(picture: &str) -> String {
+    format!("/album/picture?url={}", picture)
+}
+
+// 第 page 页（从 1 开始）在 total 张图片中的下标范围以及总页数，超出范围时为空
+fn picture_page(total: usize, page: u32, size: u32) -> (std::ops::Range<usize>, u32) {
+    let size = size.max(1) as usize;
+    let page_total = total.div_ceil(size) as u32;
+    let start = (page.max(1) as usize - 1).saturating_mul(size).min(total);
+    (start..(start + size).min(total), page_total)
+}
+
+async fn get_album_by_url(Query(query): Query<AlbumQuery>, State(state): State<WebState>) -> Response {
+    let parser_code = query.parser_code.clone()
+        .unwrap_or_else(|| parser::parser_code_for_url(&query.url).map(str::to_string).unwrap_or_else(default_parser_code));
+    let parser = match state.parser_cache.get(&parser_code) {
+        Some(p) => p,
+        None => {
+            match parser::parse(&parser_code, Some(state.client.clone())) {
+                Ok(p) => {
+                    state.parser_cache.insert(parser_code.clone(), p);
+                    state.parser_cache.get(&parser_code).unwrap()
+                }
+                Err(err) => {
+                    error!("parse from {} to parser error: {:?}", parser_code, err);
+                    let error = format!("unknown parser: {}", parser_code);
+                    return Json(CommonResponse::failure(-1, error, Vec::<String>::new())).into_response();
+                }
+            }
+        }
+    };
+    let parser = parser.clone();
+
+    if let Some(source_page) = query.source_page {
+        let response = match parser.get_album_page(query.url.clone(), source_page as usize).await {
+            Ok((pictures, page_total)) => {
+                let pictures = pictures.iter().map(|picture| forward_url(picture)).collect();
+                PaginationResponse::success(PicturePage { pictures, total: None }, Pagination::new(source_page, page_total as u32))
+            }
+            Err(err) => {
+                let error = format!("get album pictures error: {:?}", err);
+                PaginationResponse::failure(-1, error, PicturePage { pictures: vec![], total: None }, Pagination::new(source_page, 0))
+            }
+        };
+        return Json(response).into_response();
+    }
+
+    if query.page.is_some() || query.size.is_some() {
+        let page = query.page.unwrap_or(1);
+        let response = match parser.get_all_pictures(query.url.clone()).await {
+            Ok(pictures) => {
+                let (range, page_total) = picture_page(pictures.len(), page, query.size.unwrap_or(DEFAULT_PICTURE_PAGE_SIZE));
+                let data = PicturePage { pictures: pictures[range].iter().map(|picture| forward_url(picture)).collect(), total: Some(pictures.len()) };
+                PaginationResponse::success(data, Pagination::new(page, page_total))
+            }
+            Err(err) => {
+                let error = format!("get album pictures error: {:?}", err);
+                PaginationResponse::failure(-1, error, PicturePage { pictures: vec![], total: None }, Pagination::new(page, 0))
+            }
+        };
+        return Json(response).into_response();
+    }
+
     let response =  match parser.get_all_pictures(query.url.clone()).await {
         Ok(pictures) => {
-            let pictures = pictures.into_iter().map(|picture| {
-                format!("/album/picture?url={}", picture)
-            }).collect();
-            CommonResponse::success(pictures)
+            // 指定了 from/to 时只返回该范围内的图片
+            let range = match (query.from, query.to) {
+                (None, None) => Ok(0..pictures.len()),
+                (from, to) => lmpic_downloader::picture_range(pictures.len(), from.unwrap_or(1), to.unwrap_or(pictures.len()))
+            };
+            match range {
+                Ok(range) => {
+                    let pictures = pictures[range].iter().map(|picture| forward_url(picture)).collect();
+                    CommonResponse::success(pictures)
+                }
+                Err(err) => {
+                    let error = format!("get album pictures error: {:?}", err);
+                    CommonResponse::failure(-1, error, vec![])
+                }
+            }
         },
         Err(err) => {
             let error = format!("get album pictures error: {:?}", err);
             CommonResponse::failure(-1, error, vec![])
         }
     };
-    Json(response)
+    Json(response).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct DownloadRequest {
+    pub parser_code: String,
+    pub keyword: String,
+    pub page: u32,
+    // 专辑在页面中的序号，从 1 开始
+    pub index: usize
+}
+
+#[derive(Serialize)]
+struct DownloadTask {
+    task_id: u64
+}
+
+// 在后台下载搜索结果中的专辑，立即返回任务 ID
+async fn download_album(State(state): State<WebState>, Json(request): Json<DownloadRequest>) -> Json<CommonResponse<Option<DownloadTask>>> {
+    let parser = match parser::parse(&request.parser_code, Some(state.client.clone())) {
+        Ok(p) => p,
+        Err(err) => {
+            error!("parse from {} to parser error: {:?}", request.parser_code, err);
+            let error = format!("unknown parser: {}", request.parser_code);
+            return Json(CommonResponse::failure(-1, error, None));
+        }
+    };
+
+    // 复用搜索时缓存的专辑列表，没有缓存时重新搜索
+    let key = searcher_key(&parser.parser_code(), &request.keyword);
+    let mut searcher = match state.searcher_cache.get(&key) {
+        Some(searcher) => searcher.clone(),
+        None => AlbumSearcher::new(parser, &request.keyword, AlbumSearcher::DEFAULT_PAGE_SIZE)
+    };
+
+    let task_id = create_job(&state, &request.parser_code, &request.keyword);
+    let cancel = state.jobs.get(&task_id).map(|job| job.cancel.clone()).unwrap_or_default();
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    searcher.set_download_config(DownloadConfig {
+        progress_events: Some(sender),
+        cancel: cancel.clone(),
+        pause: state.pause.clone(),
+        ..searcher.download_config().clone()
+    });
+
+    // 将下载进度转发给所有订阅者
+    let forward_state = state.clone();
+    let forward = tokio::spawn(async move {
+        let mut last = (0, 0);
+        while let Some(progress) = receiver.recv().await {
+            last = (progress.done, progress.total);
+            publish_progress(&forward_state, task_id, ProgressEvent::new(progress.done, progress.total, ProgressEvent::PICTURES));
+        }
+        last
+    });
+
+    let job_state = state.clone();
+    state.tasks.spawn(async move {
+        let state = job_state;
+        info!("download task {} started, keyword: {}, page: {}, index: {}", task_id, request.keyword, request.page, request.index);
+        // 搜索阶段取消时直接结束，下载阶段由下载配置中的取消令牌处理
+        let albums = tokio::select! {
+            albums = searcher.jump(&request.page) => albums,
+            _ = cancel.cancelled() => Err(anyhow::anyhow!("download task {} cancelled", task_id))
+        };
+        let ret = match albums {
+            Ok(albums) => {
+                let album = albums.and_then(|albums| albums.get(request.index.wrapping_sub(1))).map(|album| album.name.clone());
+                if let (Some(album), Some(mut job)) = (album, state.jobs.get_mut(&task_id)) {
+                    job.album = album;
+                }
+                searcher.download(request.index).await
+            }
+            Err(err) => Err(err)
+        };
+        // 释放进度的发送端，等待转发结束后再发送最终状态
+        drop(searcher);
+        let (done, total) = forward.await.unwrap_or((0, 0));
+        let event = match ret {
+            Ok(result) if result.is_cancelled() => {
+                info!("download task {} cancelled: {:?}", task_id, result);
+                ProgressEvent::new(done, total, ProgressEvent::CANCELLED)
+            }
+            Ok(result) => {
+                info!("download task {} finished: {:?}", task_id, result);
+                ProgressEvent::new(result.total, result.total, ProgressEvent::COMPLETE)
+            }
+            Err(err) => {
+                error!("download task {} error: {:?}", task_id, err);
+                ProgressEvent::new(done, total, ProgressEvent::ERROR)
+            }
+        };
+        publish_progress(&state, task_id, event);
+    });
+    Json(CommonResponse::success(Some(DownloadTask { task_id })))
+}
+
+// 后台下载任务，cancel 用于取消仍在下载的任务，已下载的图片保留
+struct JobHandle {
+    parser_code: String,
+    keyword: String,
+    album: String,
+    progress: ProgressEvent,
+    finished_at: Option<Instant>,
+    cancel: CancellationToken
+}
+
+#[derive(Serialize)]
+struct JobInfo {
+    task_id: u64,
+    parser_code: String,
+    keyword: String,
+    album: String,
+    done: usize,
+    total: usize,
+    stage: &'static str
+}
+
+impl JobHandle {
+    fn info(&self, task_id: u64) -> JobInfo {
+        JobInfo {
+            task_id,
+            parser_code: self.parser_code.clone(),
+            keyword: self.keyword.clone(),
+            album: self.album.clone(),
+            done: self.progress.done,
+            total: self.progress.total,
+            stage: self.progress.stage
+        }
+    }
+}
+
+fn create_job(state: &WebState, parser_code: &str, keyword: &str) -> u64 {
+    let task_id = state.task_id.fetch_add(1, Ordering::SeqCst) + 1;
+    state.jobs.insert(task_id, JobHandle {
+        parser_code: parser_code.to_string(),
+        keyword: keyword.to_string(),
+        album: String::new(),
+        progress: ProgressEvent::new(0, 0, ProgressEvent::PICTURES),
+        finished_at: None,
+        cancel: CancellationToken::new()
+    });
+    task_id
+}
+
+// 删除结束时间超过 ttl 的任务，仍在下载的任务不删除
+fn sweep_jobs(jobs: &DashMap<u64, JobHandle>, ttl: Duration) {
+    jobs.retain(|_, job| !matches!(job.finished_at, Some(finished_at) if finished_at.elapsed() >= ttl));
+}
+
+async fn list_jobs(State(state): State<WebState>) -> Json<CommonResponse<Vec<JobInfo>>> {
+    sweep_jobs(&state.jobs, state.job_ttl);
+    let mut jobs: Vec<JobInfo> = state.jobs.iter().map(|job| job.info(*job.key())).collect();
+    jobs.sort_by_key(|job| job.task_id);
+    Json(CommonResponse::success(jobs))
+}
+
+async fn cancel_job(Path(task_id): Path<u64>, State(state): State<WebState>) -> Json<CommonResponse<Option<JobInfo>>> {
+    let progress = match state.jobs.get_mut(&task_id) {
+        Some(job) if !job.progress.is_finished() => {
+            job.cancel.cancel();
+            job.progress.clone()
+        }
+        Some(_) => return Json(CommonResponse::failure(-1, format!("job {} already finished", task_id), None)),
+        None => return Json(CommonResponse::failure(-1, format!("unknown job: {}", task_id), None))
+    };
+
+    info!("download task {} cancelled", task_id);
+    publish_progress(&state, task_id, ProgressEvent::new(progress.done, progress.total, ProgressEvent::CANCELLED));
+    let info = state.jobs.get(&task_id).map(|job| job.info(task_id));
+    Json(CommonResponse::success(info))
+}
+
+// 暂停所有进行中和之后的下载，已开始的图片请求继续完成，返回是否处于暂停状态
+async fn pause_downloads(State(state): State<WebState>) -> Json<CommonResponse<bool>> {
+    info!("pause all downloads");
+    state.pause.pause();
+    refresh_job_stages(&state);
+    Json(CommonResponse::success(true))
+}
+
+async fn resume_downloads(State(state): State<WebState>) -> Json<CommonResponse<bool>> {
+    info!("resume all downloads");
+    state.pause.resume();
+    refresh_job_stages(&state);
+    Json(CommonResponse::success(false))
+}
+
+// 暂停、恢复后重新发布未结束任务的进度，进度连接可以立即显示状态
+fn refresh_job_stages(state: &WebState) {
+    let jobs: Vec<(u64, ProgressEvent)> = state.jobs.iter()
+        .filter(|job| !job.progress.is_finished())
+        .map(|job| (*job.key(), job.progress.clone()))
+        .collect();
+    for (task_id, progress) in jobs {
+        publish_progress(state, task_id, ProgressEvent::new(progress.done, progress.total, ProgressEvent::PICTURES));
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct ProgressEvent {
+    done: usize,
+    total: usize,
+    stage: &'static str
+}
+
+impl ProgressEvent {
+    const PICTURES: &'static str = "pictures";
+
+    const COMPLETE: &'static str = "complete";
+
+    const ERROR: &'static str = "error";
+
+    const CANCELLED: &'static str = "cancelled";
+
+    // 暂停期间的进度
+    const PAUSED: &'static str = "paused";
+
+    fn new(done: usize, total: usize, stage: &'static str) -> Self {
+        Self {
+            done,
+            total,
+            stage
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.stage != Self::PICTURES && self.stage != Self::PAUSED
+    }
+
+    fn to_event(&self) -> Event {
+        Event::default()
+            .retry(Duration::from_secs(3))
+            .json_data(self)
+            .unwrap_or_else(|err| Event::default().comment(format!("serialize progress error: {err}")))
+    }
+}
+
+fn publish_progress(state: &WebState, task_id: u64, mut event: ProgressEvent) {
+    // 暂停期间仍可能有已开始的图片下载完成，进度显示为暂停
+    if event.stage == ProgressEvent::PICTURES && state.pause.is_paused() {
+        event.stage = ProgressEvent::PAUSED;
+    }
+    match state.jobs.get_mut(&task_id) {
+        // 任务已结束（如已被取消）时忽略之后的进度
+        Some(mut job) if !job.progress.is_finished() => {
+            if event.is_finished() {
+                job.finished_at = Some(Instant::now());
+            }
+            job.progress = event.clone();
+        }
+        _ => return
+    }
+    // 没有订阅者时发送失败，忽略即可
+    let _ = state.progress.send((task_id, event));
+}
+
+// 以 SSE 推送下载任务的进度，先推送最近一次的进度，任务结束后关闭连接，断开重连时同样从最近的进度开始
+async fn download_progress(Path(task_id): Path<u64>, State(state): State<WebState>)
+    -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    // 先订阅再读取最近的进度，避免两者之间的事件丢失
+    let receiver = state.progress.subscribe();
+    let latest = state.jobs.get(&task_id).map(|job| job.progress.clone()).ok_or(StatusCode::NOT_FOUND)?;
+
+    let stream = stream::unfold((receiver, Some(latest), false), move |(mut receiver, latest, finished)| async move {
+        if finished {
+            return None;
+        }
+
+        if let Some(progress) = latest {
+            return Some((Ok(progress.to_event()), (receiver, None, progress.is_finished())));
+        }
+
+        loop {
+            match receiver.recv().await {
+                Ok((id, progress)) if id == task_id => {
+                    return Some((Ok(progress.to_event()), (receiver, None, progress.is_finished())));
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None
+            }
+        }
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
 }
 
 #[derive(Deserialize)]
@@ -256,22 +878,307 @@ pub struct ForwardQuery {
 }
 
 async fn forward_picture(Query(query): Query<ForwardQuery>, State(state): State<WebState>) -> Response {
+    // 先取出缓存的内容再释放读锁，过期的缓存在这里删除
+    let cached = state.picture_cache.get(&query.url)
+        .map(|entry| (entry.0.clone(), entry.1.clone(), entry.2.elapsed() < PICTURE_CACHE_TTL));
+    match cached {
+        Some((bytes, headers, true)) => return picture_response(bytes, headers),
+        Some(_) => {
+            state.picture_cache.remove(&query.url);
+        }
+        None => {}
+    }
+
     let headers = lmpic_downloader::default_headers();
-    let request = state.client.get(query.url).headers(headers);
-    let response = match request.send().await {
+    // 超时包括读取响应内容，避免慢速的上游一直占用连接
+    let request = state.client.get(&query.url).headers(headers).timeout(state.picture_timeout);
+    let mut response = match request.send().await {
         Ok(resp) => resp,
+        Err(err) if err.is_timeout() => {
+            warn!("forward picture {} timed out after {:?}", query.url, state.picture_timeout);
+            return (StatusCode::GATEWAY_TIMEOUT, Body::empty()).into_response();
+        }
         Err(err) => {
             error!("get picture error: {:?}", err);
             return (StatusCode::BAD_REQUEST, Body::empty()).into_response();
         }
     };
 
-    if response.status().is_success() {
-        let mut response_builder = Response::builder().status(response.status());
-        *response_builder.headers_mut().unwrap() = response.headers().clone();
-        response_builder.body(Body::from_stream(response.bytes_stream())).unwrap()
-    } else {
+    // 图片无法显示时可以开启 debug 日志查看上游的响应
+    debug!("forward picture {}: status {}, content-type {:?}, content-length {:?}", query.url, response.status(),
+           response.headers().get(header::CONTENT_TYPE), response.headers().get(header::CONTENT_LENGTH));
+    if !response.status().is_success() {
         error!("forward picture request error: {:?}", response.status());
-        (StatusCode::INTERNAL_SERVER_ERROR, Body::empty()).into_response()
+        return (StatusCode::INTERNAL_SERVER_ERROR, Body::empty()).into_response();
+    }
+
+    let too_large = |size: u64| {
+        warn!("forward picture {} rejected, size {} exceeds {}", query.url, size, state.max_picture_size);
+        (StatusCode::PAYLOAD_TOO_LARGE, Body::empty()).into_response()
+    };
+    if let Some(size) = response.content_length().filter(|size| *size > state.max_picture_size) {
+        return too_large(size);
+    }
+
+    // 没有 Content-Length 时边读边检查大小
+    let headers = forwarded_headers(response.headers());
+    let mut bytes = Vec::new();
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                bytes.extend_from_slice(&chunk);
+                if bytes.len() as u64 > state.max_picture_size {
+                    return too_large(bytes.len() as u64);
+                }
+            }
+            Ok(None) => break,
+            Err(err) if err.is_timeout() => {
+                warn!("forward picture {} timed out after {:?} while reading body", query.url, state.picture_timeout);
+                return (StatusCode::GATEWAY_TIMEOUT, Body::empty()).into_response();
+            }
+            Err(err) => {
+                error!("read picture error: {:?}", err);
+                return (StatusCode::INTERNAL_SERVER_ERROR, Body::empty()).into_response();
+            }
+        }
+    }
+    cache_picture(&state.picture_cache, query.url, bytes.clone(), headers.clone());
+    picture_response(bytes, headers)
+}
+
+// 转发给浏览器的上游响应头，Connection、Transfer-Encoding 等逐跳的响应头不转发
+const FORWARDED_HEADERS: [HeaderName; 6] = [header::CONTENT_TYPE, header::CONTENT_LENGTH, header::CACHE_CONTROL,
+    header::ETAG, header::LAST_MODIFIED, header::ACCEPT_RANGES];
+
+fn forwarded_headers(upstream: &HeaderMap) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for name in FORWARDED_HEADERS {
+        if let Some(value) = upstream.get(&name) {
+            headers.insert(name, value.clone());
+        }
+    }
+    headers
+}
+
+fn picture_response(bytes: Vec<u8>, mut headers: HeaderMap) -> Response {
+    if !headers.contains_key(header::CONTENT_TYPE) {
+        headers.insert(header::CONTENT_TYPE, "application/octet-stream".parse().unwrap());
+    }
+    // 解压后的长度可能与上游的 Content-Length 不同，按实际转发的内容设置
+    headers.insert(header::CONTENT_LENGTH, bytes.len().into());
+    let mut response = Response::new(Body::from(bytes));
+    *response.headers_mut() = headers;
+    response
+}
+
+// 缓存超过容量时先清理过期的图片，仍然超过时删除最早获取的图片
+fn cache_picture(cache: &PictureCache, url: String, bytes: Vec<u8>, headers: HeaderMap) {
+    cache.insert(url, (bytes, headers, Instant::now()));
+    if cache.len() <= PICTURE_CACHE_CAPACITY {
+        return;
+    }
+
+    cache.retain(|_, (_, _, fetched)| fetched.elapsed() < PICTURE_CACHE_TTL);
+    while cache.len() > PICTURE_CACHE_CAPACITY {
+        let oldest = cache.iter().min_by_key(|entry| entry.2).map(|entry| entry.key().clone());
+        match oldest {
+            Some(url) => {
+                cache.remove(&url);
+            }
+            None => break
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::extract::{Path, Query, State};
+    use axum::http::{header, HeaderMap, StatusCode};
+    use dashmap::DashMap;
+    use reqwest::Client;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use wiremock::matchers::{method, path};
+
+    use lmpic_downloader::parser::ParserBuilder;
+
+    use crate::{cache_picture, cancel_job, create_job, drain_jobs, forward_picture, ForwardQuery, list_jobs, pause_downloads, PICTURE_CACHE_CAPACITY, picture_page, ProgressEvent, publish_progress, resume_downloads, suggest_albums, SuggestQuery, sweep_jobs, WebState};
+
+    #[test]
+    fn test_picture_cache_evicts_oldest() {
+        let cache = DashMap::new();
+        cache_picture(&cache, "http://localhost/0.jpg".to_string(), vec![0u8], HeaderMap::new());
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        for i in 1..=PICTURE_CACHE_CAPACITY {
+            cache_picture(&cache, format!("http://localhost/{i}.jpg"), vec![0u8], HeaderMap::new());
+        }
+        assert_eq!(cache.len(), PICTURE_CACHE_CAPACITY);
+        assert!(!cache.contains_key("http://localhost/0.jpg"));
+        assert!(cache.contains_key(&format!("http://localhost/{PICTURE_CACHE_CAPACITY}.jpg")));
+    }
+
+    #[tokio::test]
+    async fn test_forward_picture_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/1.jpg"))
+            .respond_with(ResponseTemplate::new(200)
+                .insert_header("Cache-Control", "max-age=3600")
+                .insert_header("ETag", "\"abc\"")
+                .insert_header("Connection", "keep-alive")
+                .insert_header("Keep-Alive", "timeout=5")
+                .insert_header("X-Upstream", "cdn")
+                .set_body_raw(vec![1u8, 2, 3], "image/jpeg"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let state = WebState::new(Client::new(), Duration::from_secs(60));
+        let url = format!("{}/1.jpg", server.uri());
+        // 第二次请求命中缓存，响应头相同
+        for _ in 0..2 {
+            let response = forward_picture(Query(ForwardQuery { url: url.clone() }), State(state.clone())).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let headers = response.headers();
+            assert_eq!(headers[header::CONTENT_TYPE], "image/jpeg");
+            assert_eq!(headers[header::CONTENT_LENGTH], "3");
+            assert_eq!(headers[header::CACHE_CONTROL], "max-age=3600");
+            assert_eq!(headers[header::ETAG], "\"abc\"");
+            assert!(!headers.contains_key(header::CONNECTION));
+            assert!(!headers.contains_key("keep-alive"));
+            assert!(!headers.contains_key("x-upstream"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forward_picture_limits() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/slow.jpg"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)).set_body_raw(vec![1u8], "image/jpeg"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/large.jpg"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(vec![0u8; 2048], "image/jpeg"))
+            .mount(&server)
+            .await;
+
+        let mut state = WebState::new(Client::new(), Duration::from_secs(60));
+        state.picture_timeout = Duration::from_millis(200);
+        state.max_picture_size = 1024;
+        let response = forward_picture(Query(ForwardQuery { url: format!("{}/slow.jpg", server.uri()) }), State(state.clone())).await;
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let response = forward_picture(Query(ForwardQuery { url: format!("{}/large.jpg", server.uri()) }), State(state.clone())).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(state.picture_cache.is_empty());
+
+        state.max_picture_size = 4096;
+        let response = forward_picture(Query(ForwardQuery { url: format!("{}/large.jpg", server.uri()) }), State(state)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_list_and_cancel_job() {
+        let state = WebState::new(Client::new(), Duration::from_secs(60));
+        let task_id = create_job(&state, "DILI360", "云南");
+        let cancel = state.jobs.get(&task_id).unwrap().cancel.clone();
+        let task = tokio::spawn(async move { cancel.cancelled().await });
+        publish_progress(&state, task_id, ProgressEvent::new(1, 5, ProgressEvent::PICTURES));
+
+        let jobs = list_jobs(State(state.clone())).await.0.data.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!((jobs[0].task_id, jobs[0].done, jobs[0].total, jobs[0].stage), (task_id, 1, 5, ProgressEvent::PICTURES));
+        assert_eq!(jobs[0].keyword, "云南");
+
+        let response = cancel_job(Path(task_id), State(state.clone())).await.0;
+        assert_eq!(response.code, 0);
+        assert_eq!(response.data.unwrap().unwrap().stage, ProgressEvent::CANCELLED);
+        tokio::time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+
+        // 取消后的进度被忽略，也不能再次取消
+        publish_progress(&state, task_id, ProgressEvent::new(2, 5, ProgressEvent::PICTURES));
+        assert_eq!(state.jobs.get(&task_id).unwrap().progress.stage, ProgressEvent::CANCELLED);
+        assert_eq!(cancel_job(Path(task_id), State(state.clone())).await.0.code, -1);
+        assert_eq!(cancel_job(Path(task_id + 1), State(state.clone())).await.0.code, -1);
+
+        sweep_jobs(&state.jobs, Duration::from_secs(60));
+        assert_eq!(state.jobs.len(), 1);
+        sweep_jobs(&state.jobs, Duration::ZERO);
+        assert!(state.jobs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume_downloads() {
+        let state = WebState::new(Client::new(), Duration::from_secs(60));
+        let task_id = create_job(&state, "DILI360", "云南");
+        publish_progress(&state, task_id, ProgressEvent::new(1, 5, ProgressEvent::PICTURES));
+
+        assert!(pause_downloads(State(state.clone())).await.0.data.unwrap());
+        assert!(state.pause.is_paused());
+        assert_eq!(state.jobs.get(&task_id).unwrap().progress.stage, ProgressEvent::PAUSED);
+        // 暂停期间完成的图片仍然更新进度
+        publish_progress(&state, task_id, ProgressEvent::new(2, 5, ProgressEvent::PICTURES));
+        let progress = state.jobs.get(&task_id).unwrap().progress.clone();
+        assert_eq!((progress.done, progress.stage), (2, ProgressEvent::PAUSED));
+        assert!(!progress.is_finished());
+
+        assert!(!resume_downloads(State(state.clone())).await.0.data.unwrap());
+        assert!(!state.pause.is_paused());
+        assert_eq!(state.jobs.get(&task_id).unwrap().progress.stage, ProgressEvent::PICTURES);
+    }
+
+    #[test]
+    fn test_picture_page() {
+        assert_eq!(picture_page(45, 1, 20), (0..20, 3));
+        assert_eq!(picture_page(45, 3, 20), (40..45, 3));
+        assert_eq!(picture_page(45, 4, 20), (45..45, 3));
+        assert_eq!(picture_page(45, 0, 20), (0..20, 3));
+        assert_eq!(picture_page(0, 1, 20), (0..0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_suggest_albums() {
+        let server = MockServer::start().await;
+        let album = |name: &str, id: u32| format!(
+            r#"<div class="result"><h3><a href="{}/travel/album/{id}.htm">{name}</a></h3></div>"#, server.uri());
+        let body = format!("<html><body><div id=\"results\">{}{}{}</div></body></html>",
+                           album("云南大理", 1), album(" 云南丽江 ", 2), album("云南大理", 3));
+        Mock::given(method("GET"))
+            .and(path("/cse/site"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/html; charset=utf-8"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let state = WebState::new(Client::new(), Duration::from_secs(60));
+        let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+        state.parser_cache.insert("DILI360".to_string(), parser);
+        let query = || Query(SuggestQuery { parser_code: "DILI360".to_string(), prefix: "云南".to_string() });
+
+        let suggestions = suggest_albums(query(), State(state.clone())).await.0.data.unwrap();
+        assert_eq!(suggestions, vec!["云南大理", "云南丽江"]);
+        // 相同前缀在缓存有效期内不再请求站点
+        assert_eq!(suggest_albums(query(), State(state.clone())).await.0.data.unwrap(), suggestions);
+
+        let empty = Query(SuggestQuery { parser_code: "DILI360".to_string(), prefix: " ".to_string() });
+        assert!(suggest_albums(empty, State(state)).await.0.data.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_drain_jobs() {
+        let state = WebState::new(Client::new(), Duration::from_secs(60));
+        state.tasks.spawn(tokio::time::sleep(Duration::from_millis(50)));
+        assert!(drain_jobs(&state, Duration::from_secs(5)).await);
+
+        // 超过等待时长的下载被取消
+        let state = WebState::new(Client::new(), Duration::from_secs(60));
+        let task_id = create_job(&state, "DILI360", "云南");
+        let cancel = state.jobs.get(&task_id).unwrap().cancel.clone();
+        state.tasks.spawn(async move { cancel.cancelled().await });
+        assert!(!drain_jobs(&state, Duration::from_millis(50)).await);
+        assert!(state.jobs.get(&task_id).unwrap().cancel.is_cancelled());
+        tokio::time::timeout(Duration::from_secs(5), state.tasks.wait()).await.unwrap();
     }
 }
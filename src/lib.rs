@@ -1,26 +1,269 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::string::ToString;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use dashmap::DashMap;
 use encoding::DecoderTrap;
-use indicatif::{ProgressBar, ProgressState, ProgressStyle};
+use futures_util::StreamExt;
+use futures_util::stream::FuturesUnordered;
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use is_terminal::IsTerminal;
 use lru::LruCache;
-use reqwest::{Client, header};
+use reqwest::{Client, ClientBuilder, header};
 use reqwest::header::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::Semaphore;
-use tracing::{error, info};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{Semaphore, watch};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, trace, warn};
 
-use crate::parser::Parser;
-use crate::util::filenamify;
+pub use crate::parser::Parser;
+
+use crate::parser::Media;
+use crate::retry::{retry_async, RetryPolicy};
+use crate::util::{filenamify, fit_file_name, format_duration, format_rate, format_size, free_space, looks_like_html, short_hash, url_slug};
+
+// 同时下载图片、请求分页的最大并发数
+pub const DEFAULT_CONCURRENCY: usize = 16;
+
+// 批量下载时同时下载的专辑数
+pub const DEFAULT_ALBUM_CONCURRENCY: usize = 2;
+
+// 连续失败多少张图片后中止专辑下载
+pub const DEFAULT_MAX_CONSECUTIVE_FAILURES: usize = 20;
+
+// 下载前磁盘上至少保留的可用空间
+pub const DEFAULT_MIN_FREE_SPACE: u64 = 500 * 1024 * 1024;
+
+// 缩略图的默认宽度（像素）
+pub const DEFAULT_THUMB_SIZE: u32 = 200;
+
+// 缩略图保存在专辑目录下的该子目录中
+pub const THUMBS_DIR: &str = ".thumbs";
+
+// 估算所需空间时每张图片的平均大小
+const ESTIMATED_PICTURE_SIZE: u64 = 1024 * 1024;
+
+// 常见浏览器的 User-Agent，第一个为默认值
+pub const DEFAULT_USER_AGENTS: [&str; 5] = [
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:121.0) Gecko/20100101 Firefox/121.0",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 Safari/605.1.15",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36 Edg/120.0.0.0",
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UserAgentRotation {
+    // 始终使用第一个
+    #[default]
+    Fixed,
+    RoundRobin,
+    Random
+}
+
+// 站点需要登录时的认证信息，只保存在内存中
+#[derive(Clone)]
+pub enum Auth {
+    Basic { username: String, password: Option<String> },
+    Bearer(String)
+}
+
+impl Auth {
+    fn header_value(&self) -> Option<HeaderValue> {
+        let value = match self {
+            Auth::Basic { username, password } => {
+                let credentials = format!("{}:{}", username, password.as_deref().unwrap_or(""));
+                format!("Basic {}", BASE64_STANDARD.encode(credentials))
+            }
+            Auth::Bearer(token) => format!("Bearer {}", token)
+        };
+        match HeaderValue::from_str(&value) {
+            Ok(mut value) => {
+                value.set_sensitive(true);
+                Some(value)
+            }
+            Err(err) => {
+                error!("invalid authorization header: {:?}", err);
+                None
+            }
+        }
+    }
+}
+
+// 日志中不输出密码和令牌
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Auth::Basic { username, .. } => f.debug_struct("Basic").field("username", username).finish_non_exhaustive(),
+            Auth::Bearer(_) => f.write_str("Bearer(***)")
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    pub user_agent_pool: Vec<String>,
+    pub rotation: UserAgentRotation,
+    // 是否遵守站点的 robots.txt，默认不检查
+    pub respect_robots: bool,
+    pub auth: Option<Auth>,
+    // 请求专辑页面和下载图片失败时的重试策略
+    pub retry_policy: RetryPolicy,
+    // 保存解析器获取的页面 HTML 的目录，反馈解析问题时可以附上页面，None 表示不保存
+    pub debug_html_dir: Option<PathBuf>,
+    // 下载图片时的 Accept 请求头，与请求页面时的不同，避免站点按内容协商返回 HTML
+    pub picture_accept: String,
+    // 下载图片时发送的 Referer，部分站点据此防盗链，None 表示不发送
+    pub referer: Option<String>,
+    pub tls: TlsConfig,
+    pub ip_preference: IpPreference
+}
+
+// 连接站点时使用的 IP 版本。双栈网络中站点解析出的 IPv6 地址不可达时，每次连接都要等到超时，
+// 这时可以只用 IPv4 连接。通过绑定对应版本的本地未指定地址实现，连接时会跳过另一版本的地址
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IpPreference {
+    // 按系统解析的顺序尝试所有地址
+    #[default]
+    Any,
+    V4,
+    V6
+}
+
+impl IpPreference {
+    // 环境变量 IP_PREFERENCE 为 ipv4 或 ipv6，没有设置或者无法识别时不限制
+    pub fn from_env() -> Self {
+        match std::env::var("IP_PREFERENCE") {
+            Ok(value) => value.parse().unwrap_or_else(|err| {
+                warn!("ignore IP_PREFERENCE: {}", err);
+                Self::Any
+            }),
+            Err(_) => Self::Any
+        }
+    }
+
+    pub fn local_address(&self) -> Option<IpAddr> {
+        match self {
+            Self::Any => None,
+            Self::V4 => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            Self::V6 => Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED))
+        }
+    }
+
+    pub fn apply(&self, builder: ClientBuilder) -> ClientBuilder {
+        match self.local_address() {
+            Some(address) => builder.local_address(address),
+            None => builder
+        }
+    }
+}
+
+impl std::str::FromStr for IpPreference {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "any" => Ok(Self::Any),
+            "4" | "v4" | "ipv4" => Ok(Self::V4),
+            "6" | "v6" | "ipv6" => Ok(Self::V6),
+            _ => Err(anyhow!("unknown ip preference: {}", s))
+        }
+    }
+}
+
+// HTTPS 证书校验的配置，默认严格校验，经过解密 HTTPS 的代理或者站点使用自签名证书时可以添加信任的根证书
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    // PEM 格式的根证书文件，一个文件中可以有多个证书
+    pub root_certificates: Vec<PathBuf>,
+    // 不校验证书，连接可能被劫持，只用于排查问题
+    pub accept_invalid_certs: bool
+}
+
+impl TlsConfig {
+    // 环境变量 TLS_ROOT_CERTS 为根证书文件列表（与 PATH 的分隔符相同），TLS_ACCEPT_INVALID_CERTS=1 时不校验证书
+    pub fn from_env() -> Self {
+        Self {
+            root_certificates: std::env::var_os("TLS_ROOT_CERTS").map(|paths| std::env::split_paths(&paths).collect()).unwrap_or_default(),
+            accept_invalid_certs: std::env::var("TLS_ACCEPT_INVALID_CERTS").is_ok_and(|value| matches!(value.trim(), "1" | "true"))
+        }
+    }
+
+    // 按配置创建 Client，证书文件无法读取或者没有证书时返回错误
+    pub fn build_client(&self) -> Result<Client> {
+        Ok(self.apply(Client::builder())?.build()?)
+    }
+
+    pub fn apply(&self, mut builder: ClientBuilder) -> Result<ClientBuilder> {
+        for path in &self.root_certificates {
+            let pem = std::fs::read(path).with_context(|| format!("failed to read root certificate {}", path.display()))?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem).with_context(|| format!("invalid root certificate {}", path.display()))?;
+            if certificates.is_empty() {
+                return Err(anyhow!("no certificate found in {}", path.display()));
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if self.accept_invalid_certs {
+            warn!("TLS certificate verification is DISABLED, connections can be intercepted, only use it for debugging");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        Ok(builder)
+    }
+}
+
+// 环境变量 DEBUG_HTML 不为空时默认将页面保存到 DEBUG_HTML_DIR
+pub const DEBUG_HTML_DIR: &str = "./log/debug";
+
+pub const DEFAULT_PICTURE_ACCEPT: &str = "image/avif,image/webp,image/*,*/*;q=0.8";
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            user_agent_pool: DEFAULT_USER_AGENTS.iter().map(|ua| ua.to_string()).collect(),
+            rotation: UserAgentRotation::Fixed,
+            respect_robots: false,
+            auth: None,
+            retry_policy: RetryPolicy::default(),
+            debug_html_dir: std::env::var_os("DEBUG_HTML").filter(|value| !value.is_empty()).map(|_| PathBuf::from(DEBUG_HTML_DIR)),
+            picture_accept: DEFAULT_PICTURE_ACCEPT.to_string(),
+            referer: None,
+            tls: TlsConfig::from_env(),
+            ip_preference: IpPreference::from_env()
+        }
+    }
+}
+
+impl ClientConfig {
+    // 按 TLS 和 IP 版本的配置创建 Client，请求头、认证等配置在每次请求时设置
+    pub fn build_client(&self) -> Result<Client> {
+        let builder = self.tls.apply(Client::builder())?;
+        Ok(self.ip_preference.apply(builder).build()?)
+    }
+}
 
 pub fn default_headers() -> HeaderMap {
     let mut default_headers = HeaderMap::new();
-    default_headers.insert(header::USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36"));
+    default_headers.insert(header::USER_AGENT, HeaderValue::from_static(DEFAULT_USER_AGENTS[0]));
     default_headers.insert(header::ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,image/apng,*/*;q=0.8"));
     default_headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("en-US,en;q=0.9"));
     default_headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate, br"));
@@ -30,680 +273,4161 @@ pub fn default_headers() -> HeaderMap {
     default_headers
 }
 
-async fn get_url_content(client: &Client, url: &str, encoding: Option<String>, headers: Option<HeaderMap>) -> Result<String> {
-    let mut default_headers = default_headers();
-    if let Some(headers) = headers {
-        for (n, v) in headers {
-            if let Some(name) = n {
-                default_headers.insert(name, v);
-            }
-        }
-    }
+// 请求和响应的详细信息记录到单独的 target，请求头中可能有认证信息，只在 RUST_LOG=lmpic_downloader::http=trace 时输出
+const HTTP_TRACE_TARGET: &str = "lmpic_downloader::http";
 
-    let response = client.get(url).headers(default_headers).send().await?;
-    let response = response.error_for_status()?;
+fn trace_request(request: &reqwest::Request) {
+    trace!(target: HTTP_TRACE_TARGET, method = %request.method(), url = %request.url(), headers = ?request.headers(), "http request");
+}
 
-    let content = match encoding {
-        Some(encode) => {
-            let bytes = response.bytes().await?;
-            let decoded_text = match encoding::label::encoding_from_whatwg_label(&encode) {
-                Some(encoder) => {
-                    encoder
-                        .decode(&bytes, DecoderTrap::Replace)
-                        .map_err(|e| {
-                            anyhow!("响应数据解码错误: {:?}", e)
-                        })
-                }
-                None => {
-                    Err(anyhow!("未识别的字符集编码: {}", encode))
-                }
-            }?;
-            decoded_text
-        },
-        None => response.text().await?
-    };
+fn trace_response(method: &reqwest::Method, url: &str, response: &reqwest::Response, started: Instant) {
+    trace!(target: HTTP_TRACE_TARGET, method = %method, url, final_url = %response.url(), status = %response.status(),
+        latency_ms = started.elapsed().as_millis() as u64, headers = ?response.headers(), "http response");
+}
 
-    Ok(content)
+// 发送请求，开启 HTTP 日志时记录请求和响应
+async fn execute_traced(client: &Client, request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+    let request = request.build()?;
+    trace_request(&request);
+    let (method, url) = (request.method().clone(), request.url().to_string());
+    let started = Instant::now();
+    let response = client.execute(request).await?;
+    trace_response(&method, &url, &response, started);
+    Ok(response)
 }
 
+// 解析器获取页面内容使用的 HTTP 客户端，随解析器一起克隆，共用同一个连接池
 #[derive(Clone)]
-pub struct Album {
-    pub name: String,
-    pub cover: Option<String>,
-    pub url: String
+struct HttpFetcher {
+    client: Client
 }
 
-impl Album {
+impl HttpFetcher {
+    fn new(client: Client) -> Self {
+        Self { client }
+    }
 
-    async fn download_picture(&self, client: &Client, parser: &dyn Parser, url: &str, save_to_path: PathBuf) -> Result<()> {
-        let response = client.get(url).headers(default_headers()).send().await.map_err(|e| {
-            anyhow!("Failed to send request for {}: {}", url, e)
-        })?;
+    fn client(&self) -> &Client {
+        &self.client
+    }
 
-        let picture_name = parser.get_picture_name(url)?;
-        let path = save_to_path.join(picture_name);
-        let bytes = response.bytes().await?;
-        let mut file = File::create(path).await?;
-        file.write_all(&bytes).await?;
+    // 同时返回响应头，便于调用方读取 X-Total-Count 等信息
+    async fn get_url_content(&self, url: &str, encoding: Option<String>, headers: Option<HeaderMap>) -> Result<(String, HeaderMap)> {
+        let (content, response_headers) = self.fetch_url_content(url, encoding.clone(), headers.clone()).await?;
+        if looks_like_html(&content) {
+            return Ok((content, response_headers));
+        }
 
-        Ok(())
+        // 服务端声明的压缩方式与实际不符时，解码后的内容是乱码，不压缩重新请求一次
+        warn!("content of {} does not look like html, retry without compression", url);
+        let mut headers = headers.unwrap_or_default();
+        headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+        self.fetch_url_content(url, encoding, Some(headers)).await
     }
 
-    async fn download_pictures(self: Arc<Self>, client: &Client, parser: Arc<dyn Parser>, save_to_path: &str) -> Result<()> {
-        let pictures = parser.get_all_pictures(self.url.clone()).await?;
-        let name = filenamify(&self.name, "");
-        let path = Path::new(save_to_path).join(name);
-        tokio::fs::create_dir_all(&path).await?;
-
-        let pb = Arc::new(ProgressBar::new(pictures.len() as u64));
-        pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} ({eta})")
-            .unwrap()
-            .with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-            .progress_chars("#>-"));
+    async fn fetch_url_content(&self, url: &str, encoding: Option<String>, headers: Option<HeaderMap>) -> Result<(String, HeaderMap)> {
+        let mut default_headers = default_headers();
+        if let Some(headers) = headers {
+            for (n, v) in headers {
+                if let Some(name) = n {
+                    default_headers.insert(name, v);
+                }
+            }
+        }
 
-        let semaphore = Arc::new(Semaphore::new(16));
-        let mut tasks = vec![];
-        for url in pictures {
-            let permit = semaphore.clone().acquire_owned().await?;
+        let response = execute_traced(&self.client, self.client.get(url).headers(default_headers)).await?;
+        // 保留状态码，便于调用方区分 404 等永久错误和 503 等临时错误
+        if !response.status().is_success() {
+            return Err(DownloaderError::HttpError { status: response.status(), url: url.to_string() }.into());
+        }
 
-            let base_path = path.clone();
-            let pb = pb.clone();
-            let client = client.clone();
-            let p = parser.clone();
-            let it = Arc::clone(&self);
-            let task = tokio::task::spawn(async move {
-                match it.download_picture(&client, &*p, &url, base_path).await {
-                    Ok(_) => {
-                        pb.inc(1);
-                        info!("picture {url} downloaded.");
-                    },
-                    Err(err) => {
-                        error!("download picture {} error: {:?}", url, err);
-                        println!("下载图片失败，详情请查看日志");
+        let response_headers = response.headers().clone();
+        let content = match encoding {
+            Some(encode) => {
+                let bytes = response.bytes().await?;
+                let decoded_text = match encoding::label::encoding_from_whatwg_label(&encode) {
+                    Some(encoder) => {
+                        encoder
+                            .decode(&bytes, DecoderTrap::Replace)
+                            .map_err(|e| {
+                                anyhow!("响应数据解码错误: {:?}", e)
+                            })
                     }
-                }
+                    None => {
+                        Err(anyhow!("未识别的字符集编码: {}", encode))
+                    }
+                }?;
+                decoded_text
+            },
+            None => response.text().await?
+        };
 
-                drop(permit);
-            });
+        Ok((content, response_headers))
+    }
+}
 
-            tasks.push(task);
-        }
+#[derive(Debug, thiserror::Error)]
+pub enum DownloaderError {
+    #[error("searcher is not initialized, call initialize or next first")]
+    NotInitialized,
+    #[error("磁盘空间不足: {}", .0.display())]
+    DiskFull(PathBuf),
+    #[error("没有写入权限: {}", .0.display())]
+    PermissionDenied(PathBuf),
+    #[error("write file {} error: {source}", path.display())]
+    Write { path: PathBuf, source: std::io::Error },
+    #[error("下载目录不可写: {} ({source})", path.display())]
+    NotWritable { path: PathBuf, source: std::io::Error },
+    #[error("robots.txt 不允许访问: {0}")]
+    Disallowed(String),
+    #[error("HTTP {status}: {url}")]
+    HttpError { status: reqwest::StatusCode, url: String },
+    #[error("与已下载的图片内容相同: {url} -> {}", existing.display())]
+    Duplicate { url: String, existing: PathBuf },
+    #[error("连续 {0} 张图片下载失败，已中止下载")]
+    TooManyFailures(usize),
+    #[error("磁盘可用空间不足: {} 可用 {}，至少需要 {}", path.display(), format_size(*available as f64), format_size(*required as f64))]
+    InsufficientSpace { path: PathBuf, available: u64, required: u64 },
+    #[error("无效的专辑地址: {url} ({reason})")]
+    InvalidUrl { url: String, reason: String },
+    #[error("解析 {url} 失败: {detail}")]
+    Parse { url: String, detail: String },
+    #[error("下载配置错误: {0}")]
+    InvalidConfig(String),
+    #[error("搜索结果页面 {url} 中没有 {selector}，站点页面结构可能已变化")]
+    MarkupChanged { url: String, selector: String },
+    #[error("Unsupported parser '{code}'. Available parsers: {}", describe_parsers(available))]
+    UnsupportedParser { code: String, available: Vec<&'static str> }
+}
 
-        for task in tasks {
-            if let Err(err) = task.await {
-                error!("download picture task error: {:?}", err);
-                println!("下载图片失败，详情请查看日志");
-            }
+// 解析器代码加上名称，如 DILI360 (中国地理)
+fn describe_parsers(codes: &[&str]) -> String {
+    let infos = parser::parsers();
+    codes.iter().map(|code| match infos.iter().find(|info| info.code == *code) {
+        Some(info) => format!("{} ({})", code, info.name),
+        None => code.to_string()
+    }).collect::<Vec<_>>().join(", ")
+}
+
+impl DownloaderError {
+    // 将写文件的 IO 错误区分为磁盘已满、没有权限以及其它错误
+    pub fn from_io(err: std::io::Error, path: &Path) -> Self {
+        const ENOSPC: i32 = 28;
+        match err.kind() {
+            ErrorKind::StorageFull | ErrorKind::QuotaExceeded => Self::DiskFull(path.to_path_buf()),
+            ErrorKind::PermissionDenied => Self::PermissionDenied(path.to_path_buf()),
+            _ if cfg!(unix) && err.raw_os_error() == Some(ENOSPC) => Self::DiskFull(path.to_path_buf()),
+            _ => Self::Write { path: path.to_path_buf(), source: err }
         }
+    }
 
-        pb.finish_with_message("下载完成");
-        Ok(())
+    // 存储相关的错误会导致后续所有图片都保存失败
+    pub fn is_storage_fatal(&self) -> bool {
+        matches!(self, Self::DiskFull(_) | Self::PermissionDenied(_) | Self::InsufficientSpace { .. })
+    }
+
+    // 中止整个专辑下载的错误
+    pub fn is_fatal(&self) -> bool {
+        self.is_storage_fatal() || matches!(self, Self::TooManyFailures(_))
     }
 }
 
-pub type AlbumResult<'a> = Result<Option<&'a Vec<Album>>>;
+// 在下载前检查下载目录是否存在并且可写：写入一个临时文件，避免请求完图片列表后才发现无法保存
+// 下载目录不会自动创建，由调用方在设置下载目录时创建
+pub async fn check_writable(path: &Path) -> Result<()> {
+    let absolute = std::path::absolute(path).unwrap_or(path.to_path_buf());
+    if !tokio::fs::metadata(path).await.is_ok_and(|metadata| metadata.is_dir()) {
+        return Err(DownloaderError::InvalidConfig(format!("output directory does not exist: {}", absolute.display())).into());
+    }
 
-pub mod parser {
-    use std::path::Path;
-    use std::sync::Arc;
+    let not_writable = |source| DownloaderError::NotWritable { path: absolute.clone(), source };
+    let marker = path.join(format!(".write_test_{}", std::process::id()));
+    tokio::fs::write(&marker, b"").await.map_err(not_writable)?;
+    let _ = tokio::fs::remove_file(&marker).await;
+    Ok(())
+}
 
-    use anyhow::{anyhow, Result};
-    use async_trait::async_trait;
-    use pinyin::ToPinyin;
-    use reqwest::{Client, header};
-    use reqwest::header::{HeaderMap, HeaderValue};
-    use scraper::{ElementRef, Html, Selector};
-    use tracing::error;
+// 专辑目录为 <下载目录>[/<解析器代码>]/<专辑名>，下载目录必须已存在，专辑目录在开始下载时才创建
+// 写入压缩包时只创建压缩包所在的目录
+async fn create_album_dir(path: &Path, config: &DownloadConfig) -> Result<()> {
+    let root = if config.parser_code_folder {
+        path.parent().and_then(Path::parent)
+    } else {
+        path.parent()
+    };
+    if let Some(root) = root.filter(|root| !root.as_os_str().is_empty()) {
+        if !tokio::fs::metadata(root).await.is_ok_and(|metadata| metadata.is_dir()) {
+            return Err(DownloaderError::InvalidConfig(format!("output directory does not exist: {}", root.display())).into());
+        }
+    }
 
-    use crate::{Album, get_url_content};
+    let dir = match config.archive_format {
+        ArchiveFormat::None => path,
+        _ => path.parent().unwrap_or(path)
+    };
+    tokio::fs::create_dir_all(dir).await
+        .map_err(|err| anyhow::Error::from(DownloaderError::from_io(err, dir)))
+        .with_context(|| format!("failed to create album directory {:?}", dir))
+}
 
-    #[derive(Clone)]
-    struct InnerParser {
-        client: Client,
-        page: u32,
-        page_count: u32
+// 可用空间不足以保存 count 张图片并保留 min_free_space 时返回错误，避免下载到一半磁盘写满
+fn check_free_space(config: &DownloadConfig, path: &Path, count: usize) -> Result<()> {
+    let Some(margin) = config.min_free_space else {
+        return Ok(());
+    };
+    if count == 0 {
+        return Ok(());
     }
 
-    impl InnerParser {
-        fn new() -> Self {
-            Self {
-                client: Client::new(),
-                page: 0,
-                page_count: 0
-            }
-        }
+    let Some(available) = (config.free_space)(path) else {
+        warn!("query free space of {:?} failed, skip free space check", path);
+        return Ok(());
+    };
+    let required = (count as u64).saturating_mul(ESTIMATED_PICTURE_SIZE).saturating_add(margin);
+    if available < required {
+        let path = std::path::absolute(path).unwrap_or(path.to_path_buf());
+        return Err(DownloaderError::InsufficientSpace { path, available, required }.into());
+    }
+    Ok(())
+}
 
-        async fn get_page_pictures(&self, url: String, selector: &str, encoding: Option<String>, headers: Option<HeaderMap>) -> Result<Vec<String>> {
-            let html = get_url_content(&self.client, &url, encoding, headers).await?;
-            let document = Html::parse_document(&html);
-            let selector = Selector::parse(selector).map_err(|err| {
-                anyhow!("parse page pictures selector error: {err:?}")
-            })?;
+// 为保存的图片生成宽度为 width 的 JPEG 缩略图，保存到同目录下的 .thumbs/<文件名>.jpg，返回缩略图路径
+pub fn generate_thumbnail(path: &Path, width: u32) -> Result<PathBuf> {
+    let file_name = path.file_name().ok_or(anyhow!("picture path {:?} has no file name", path))?;
+    let dir = path.parent().unwrap_or(Path::new("")).join(THUMBS_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let thumb_path = dir.join(format!("{}.jpg", file_name.to_string_lossy()));
 
-            let pictures: Vec<String> = document.select(&selector).into_iter().filter_map(|element| {
-                if let Some(url) = element.value().attr("src") {
-                    Some(url.to_string())
-                } else {
-                    None
-                }
-            }).collect();
-            Ok(pictures)
-        }
+    // 站点返回的格式可能与扩展名不符，按文件内容识别格式，image 未开启 AVIF、HEIC 的解码，这两种格式不生成缩略图
+    let bytes = std::fs::read(path)?;
+    if let Some(format) = ImageFormat::from_magic(&bytes) {
+        return Err(anyhow!("thumbnail of {:?} picture {:?} is not supported", format, path));
+    }
+    let picture = image::ImageReader::new(std::io::Cursor::new(bytes)).with_guessed_format()?.decode()
+        .with_context(|| format!("Failed to decode picture {:?}", path))?;
+    let width = width.max(1);
+    let height = ((picture.height() as u64 * width as u64) / picture.width().max(1) as u64).max(1) as u32;
+    // JPEG 不支持透明通道，转成 RGB 后再保存
+    let thumb = picture.resize_exact(width, height, image::imageops::FilterType::Triangle).to_rgb8();
+    thumb.save_with_format(&thumb_path, image::ImageFormat::Jpeg)
+        .with_context(|| format!("Failed to save thumbnail {:?}", thumb_path))?;
+    Ok(thumb_path)
+}
 
-        fn get_picture_name(&self,  url: &str) -> Result<String> {
-            let path = Path::new(url);
-            if let Some(file_name) = path.file_name() {
-                file_name.to_str().map(|s| {
-                    s.to_string()
-                }).ok_or(anyhow!("get file name error: {url}"))
-            } else {
-                Err(anyhow!("get file name error: {url}"))
-            }
-        }
+// 校验从 1 开始的闭区间 [start, end]，返回对应图片列表的下标范围
+pub fn picture_range(total: usize, start: usize, end: usize) -> Result<Range<usize>> {
+    if start == 0 || start > end {
+        return Err(anyhow!("error picture range: {}-{}", start, end));
+    }
 
-        fn default_get_name_and_url(&self, root_element: ElementRef, path: &str) -> (String, String) {
-            let selector = Selector::parse(path).unwrap();
-            let element = root_element.select(&selector).next();
-            element.and_then(|e| {
-                Some((e.text().collect::<Vec<_>>().join(""), e.value().attr("href").unwrap_or("").to_string()))
-            }).unwrap_or(("".to_string(), "".to_string()))
-        }
+    if end > total {
+        return Err(anyhow!("error picture range: {}-{}, max index: {}", start, end, total));
+    }
 
-        fn default_get_cover(&self, root_element: ElementRef, path: &str) -> Option<String> {
-            let selector = Selector::parse(path).unwrap();
-            let element = root_element.select(&selector).next();
-            element.and_then(|e| {
-                e.value().attr("src").map(|url| url.to_string())
-            })
-        }
+    Ok(start - 1..end)
+}
 
-        fn default_get_albums(&self, document: &Html, selector: Selector, name_path: &str, cover_path: &str) -> Vec<Album> {
-            document.select(&selector).into_iter().map(|element| {
-                let (name, url) = self.default_get_name_and_url(element, name_path);
-                let cover = self.default_get_cover(element, cover_path);
+// 按扩展名区分的图片格式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg, Png, WebP, Avif, Heic, Gif
+}
 
-                Album {
-                    name,
-                    cover,
-                    url
-                }
-            }).collect()
+impl ImageFormat {
+    pub const DEFAULT_PREFERENCE: [ImageFormat; 6] = [Self::Jpeg, Self::Png, Self::WebP, Self::Avif, Self::Heic, Self::Gif];
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            "webp" => Some(Self::WebP),
+            "avif" => Some(Self::Avif),
+            "heic" | "heif" => Some(Self::Heic),
+            "gif" => Some(Self::Gif),
+            _ => None
         }
     }
 
-    #[async_trait]
-    pub trait Parser: Send + Sync {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::WebP => "webp",
+            Self::Avif => "avif",
+            Self::Heic => "heic",
+            Self::Gif => "gif"
+        }
+    }
 
-        fn parser_name(&self) -> String;
+    // AVIF、HEIC 的文件头是 ISO BMFF 的 ftyp box：主品牌、4 字节的版本号，之后是兼容品牌
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.get(4..8)? != b"ftyp" {
+            return None;
+        }
+        let size = u32::from_be_bytes(bytes[0..4].try_into().ok()?) as usize;
+        let brands = bytes.get(8..size.min(bytes.len()))?;
+        brands.chunks_exact(4).enumerate().filter(|(i, _)| *i != 1).find_map(|(_, brand)| match brand {
+            b"avif" | b"avis" => Some(Self::Avif),
+            b"heic" | b"heix" | b"heim" | b"heis" => Some(Self::Heic),
+            _ => None
+        })
+    }
+}
 
-        fn client(&self) -> Arc<&Client>;
+impl std::str::FromStr for ImageFormat {
+    type Err = anyhow::Error;
 
-        fn parse_page_count(&self, document: &Html) -> Result<u32>;
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_extension(s.trim()).ok_or(anyhow!("unknown image format: {}", s))
+    }
+}
 
-        async fn parse_albums(&self, keyword: String, page: u32, size: u32) -> Result<(Vec<Album>, u32)>;
+// 图片的存储后端，默认保存到本地文件系统，可以替换为内存、对象存储等，下载逻辑不需要改变
+#[async_trait]
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    async fn put(&self, path: &Path, bytes: &[u8]) -> std::io::Result<()>;
 
-        fn get_pagination(&self, html: &str) -> usize;
+    async fn exists(&self, path: &Path) -> bool;
 
-        async fn get_page_pictures(&self, url: String) -> Result<Vec<String>>;
+    // 已保存对象的字节数，不存在时返回 None
+    async fn size(&self, path: &Path) -> Option<u64>;
 
-        async fn get_all_pictures(&self, url: String) -> Result<Vec<String>>;
+    // 创建目录、检查磁盘空间、生成缩略图等只对本地文件系统有意义
+    fn is_local(&self) -> bool {
+        false
+    }
+}
 
-        fn get_picture_name(&self, url: &str) -> Result<String>;
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalStorage;
 
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+        // Windows 下使用 \\?\ 前缀，路径长度不受 MAX_PATH 限制
+        #[cfg(windows)]
+        let path = &util::extended_path(path);
+        let ret = async {
+            let mut file = File::create(path).await?;
+            Album::write_to(&mut file, bytes).await
+        }.await;
+        if ret.is_err() {
+            // 删除写了一半的文件，相同内容的图片可以重新保存
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        ret
     }
 
-    #[derive(Clone)]
-    struct DiLi360Parser {
-        inner: InnerParser
+    async fn exists(&self, path: &Path) -> bool {
+        tokio::fs::try_exists(path).await.unwrap_or(false)
     }
 
-    impl DiLi360Parser {
+    async fn size(&self, path: &Path) -> Option<u64> {
+        tokio::fs::metadata(path).await.ok().map(|metadata| metadata.len())
+    }
 
-        const PARSER_CODE: &'static str = "DILI360";
+    fn is_local(&self) -> bool {
+        true
+    }
+}
 
-        const PARSER_NAME: &'static str = "中国地理";
+// 一张图片保存到哪里：存储后端或者压缩包，hashes 为去重使用的内容哈希
+#[derive(Clone, Copy)]
+struct SaveTarget<'a> {
+    storage: &'a dyn Storage,
+    hashes: Option<&'a Mutex<HashMap<[u8; 32], PathBuf>>>,
+    archive: Option<&'a tokio::sync::Mutex<ArchiveWriter>>
+}
 
-        fn new() -> Self {
-            Self {
-                inner: InnerParser::new()
-            }
+// 专辑的保存方式：None 保存为目录下的图片文件，Zip、Tar 直接写入与专辑目录同名的压缩包，不保存单独的图片文件
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    #[default]
+    None,
+    Zip,
+    Tar
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Zip => Some("zip"),
+            Self::Tar => Some("tar")
         }
     }
+}
 
-    #[async_trait]
-    impl Parser for DiLi360Parser {
+impl std::str::FromStr for ArchiveFormat {
+    type Err = anyhow::Error;
 
-        fn parser_name(&self) -> String {
-            DiLi360Parser::PARSER_NAME.to_string()
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "zip" => Ok(Self::Zip),
+            "tar" => Ok(Self::Tar),
+            _ => Err(anyhow!("unknown archive format: {}", s))
         }
+    }
+}
 
-        fn client(&self) -> Arc<&Client> {
-            Arc::new(&self.inner.client)
-        }
+// 图片按下载完成的顺序依次写入压缩包，同名的图片加上地址的短哈希前缀
+struct ArchiveWriter {
+    path: PathBuf,
+    writer: Option<ArchiveInner>,
+    names: HashSet<String>
+}
 
-        fn parse_page_count(&self, document: &Html) -> Result<u32> {
-            let selector = Selector::parse("#pageFooter .pager-normal-foot").map_err(|err| {
-                anyhow!("parse selector error: {err:?}")
-            })?;
+enum ArchiveInner {
+    Zip(async_zip::tokio::write::ZipFileWriter<File>),
+    Tar(tokio_tar::Builder<File>)
+}
 
-            let last_element = document.select(&selector).last();
-            if last_element.is_none() {
-                return Err(anyhow!("parse page count error: not found page element"));
-            }
+impl ArchiveWriter {
+    async fn create(path: PathBuf, format: ArchiveFormat) -> Result<Self> {
+        let file = File::create(&path).await.map_err(|err| DownloaderError::from_io(err, &path))?;
+        let writer = match format {
+            ArchiveFormat::Zip => ArchiveInner::Zip(async_zip::base::write::ZipFileWriter::with_tokio(file)),
+            ArchiveFormat::Tar => ArchiveInner::Tar(tokio_tar::Builder::new(file)),
+            ArchiveFormat::None => return Err(anyhow!("archive format is none"))
+        };
+        Ok(Self { path, writer: Some(writer), names: HashSet::new() })
+    }
 
-            let element = last_element.unwrap();
-            let text = element.text().next();
-            if text.is_none() {
-                return Err(anyhow!("parse page count error: not found page text"));
+    // 返回图片在压缩包中的名称
+    async fn add(&mut self, name: &str, url: &str, bytes: &[u8]) -> std::io::Result<String> {
+        let name = if self.names.contains(name) {
+            format!("{}_{}", short_hash(url), name)
+        } else {
+            name.to_string()
+        };
+        match self.writer.as_mut() {
+            // 图片已经是压缩过的格式，不再压缩
+            Some(ArchiveInner::Zip(writer)) => {
+                let entry = async_zip::ZipEntryBuilder::new(name.as_str().into(), async_zip::Compression::Stored)
+                    .unix_permissions(0o644);
+                writer.write_entry_whole(entry, bytes).await.map_err(std::io::Error::other)?;
             }
-
-            let text = text.unwrap();
-            let page_count = text.parse::<u32>().map_err(|e| {
-                anyhow!("parse page count error: {e:?}")
-            })?;
-            Ok(page_count)
+            Some(ArchiveInner::Tar(builder)) => {
+                let mut header = tokio_tar::Header::new_gnu();
+                header.set_size(bytes.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |time| time.as_secs()));
+                header.set_cksum();
+                builder.append_data(&mut header, &name, bytes).await?;
+            }
+            None => return Err(std::io::Error::other("archive already finished"))
         }
+        self.names.insert(name.clone());
+        Ok(name)
+    }
 
-        async fn parse_albums(&self, keyword: String, page: u32, size: u32) -> Result<(Vec<Album>, u32)> {
-            // 地理 360 搜索结果页面从 0 开始
-            let url = format!("https://zhannei.baidu.com/cse/site?q={}&p={}&nsid=&cc=www.dili360.com", &keyword, page - 1);
-            let html = get_url_content(&self.inner.client, &url, None, None).await?;
-            let document = Html::parse_document(&html);
-            let selector = Selector::parse("#results>.result").map_err(|err| {
-                anyhow!("parse selector error: {err:?}")
-            })?;
-            let albums = self.inner.default_get_albums(&document, selector, "h3>a", "div>.c-image img");
+    // 写入压缩包的目录，不调用时压缩包不完整
+    async fn finish(&mut self) -> Result<()> {
+        let mut file = match self.writer.take() {
+            Some(ArchiveInner::Zip(writer)) => writer.close().await?.into_inner(),
+            Some(ArchiveInner::Tar(builder)) => builder.into_inner().await?,
+            None => return Ok(())
+        };
+        file.flush().await.map_err(|err| DownloaderError::from_io(err, &self.path))?;
+        Ok(())
+    }
+}
+
+// 视频、AVIF、HEIC 的地址中往往没有扩展名，按文件头或响应的 Content-Type 补上，其它图片以及已有扩展名的文件保持原样
+// 按 Accept 协商格式的 CDN 可能为 .jpg 地址返回 AVIF、HEIC，这时按文件头改正扩展名
+fn with_media_extension(name: String, content_type: Option<&str>, bytes: &[u8]) -> String {
+    let current = Path::new(&name).extension().map(|extension| extension.to_string_lossy().to_string());
+    match (current, ImageFormat::from_magic(bytes)) {
+        (Some(current), Some(format)) => {
+            if ImageFormat::from_extension(&current).is_some_and(|existing| existing != format) {
+                return format!("{}.{}", &name[..name.len() - current.len() - 1], format.extension());
+            }
+            return name;
+        }
+        (Some(_), None) => return name,
+        (None, Some(format)) => return format!("{}.{}", name, format.extension()),
+        (None, None) => {}
+    }
+
+    let Some(content_type) = content_type.map(|value| value.split(';').next().unwrap_or("").trim().to_ascii_lowercase()) else {
+        return name;
+    };
+    let extension = match content_type.as_str() {
+        "video/mp4" => "mp4",
+        "video/webm" => "webm",
+        "video/ogg" => "ogv",
+        "video/quicktime" => "mov",
+        "video/x-matroska" => "mkv",
+        "video/mp2t" => "ts",
+        "image/avif" => "avif",
+        "image/heic" | "image/heif" => "heic",
+        _ => return name
+    };
+    format!("{}.{}", name, extension)
+}
+
+// 同一张图片有多种格式时（地址只有扩展名不同）只保留 preferred 中最靠前的格式，其它图片保持原来的顺序
+pub fn select_preferred_formats(pictures: Vec<String>, preferred: &[ImageFormat]) -> Vec<String> {
+    let rank = |format: ImageFormat| preferred.iter().position(|preferred| *preferred == format).unwrap_or(preferred.len());
+    let mut selected: Vec<(String, Option<ImageFormat>)> = Vec::with_capacity(pictures.len());
+    let mut variants: HashMap<String, usize> = HashMap::new();
+    for url in pictures {
+        let path = url.split(['?', '#']).next().unwrap_or(&url);
+        let variant = path.rsplit_once('.')
+            .filter(|(_, extension)| !extension.contains('/'))
+            .and_then(|(stem, extension)| Some((stem.to_string(), ImageFormat::from_extension(extension)?)));
+        let Some((stem, format)) = variant else {
+            selected.push((url, None));
+            continue;
+        };
+
+        match variants.get(&stem) {
+            Some(&i) => {
+                if selected[i].1.is_some_and(|existing| rank(format) < rank(existing)) {
+                    selected[i] = (url, Some(format));
+                }
+            }
+            None => {
+                variants.insert(stem, selected.len());
+                selected.push((url, Some(format)));
+            }
+        }
+    }
+    selected.into_iter().map(|(url, _)| url).collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct DownloadConfig {
+    // 保存的文件名是否加上图片在专辑中的序号前缀，如 0001_xxx.jpg
+    pub index_prefix: bool,
+    // 是否按解析器代码分目录保存，避免不同站点的同名专辑互相覆盖
+    pub parser_code_folder: bool,
+    // 整个专辑的下载时限，超过后取消剩余的图片下载，默认不限制
+    pub deadline: Option<Duration>,
+    // 批量下载时同时下载的专辑数
+    pub album_concurrency: usize,
+    // 单个专辑同时下载的图片数
+    pub picture_concurrency: usize,
+    // 所有专辑同时下载图片的连接总数
+    pub max_connections: usize,
+    // 不显示进度条，也不输出进度日志
+    pub quiet: bool,
+    // 自定义专辑进度条的模板，格式参考 indicatif 的 ProgressStyle
+    pub progress_template: Option<String>,
+    // 每下载完一张图片发送一次专辑的下载进度，供 Web 等界面显示
+    pub progress_events: Option<UnboundedSender<DownloadProgress>>,
+    // 每个域名每秒最多请求的图片数，默认不限制
+    pub requests_per_second_per_domain: Option<f64>,
+    // 按内容去重，专辑中内容相同的图片只保存一张
+    pub dedup: bool,
+    // 连续失败的图片数达到该值时中止专辑下载，如站点中途封禁了 IP，None 表示不限制
+    pub max_consecutive_failures: Option<usize>,
+    // 取消后不再开始新的图片下载，进行中的请求也会中断，已下载的图片保留
+    pub cancel: CancellationToken,
+    // 暂停后不再开始新的图片下载，恢复后继续，进行中的请求不受影响
+    pub pause: PauseGate,
+    // 下载前检查磁盘可用空间，按图片数估算所需空间后至少再保留的字节数，None 表示不检查
+    pub min_free_space: Option<u64>,
+    // 查询目录所在磁盘的可用空间，无法查询时返回 None 并跳过检查
+    pub free_space: fn(&Path) -> Option<u64>,
+    // 服务关闭时等待进行中的下载结束的时长，超过后取消下载
+    pub shutdown_timeout: Duration,
+    // 同一张图片有多种格式时按顺序优先下载的格式，如 [WebP, Avif, Jpeg] 优先下载体积更小的 WebP
+    pub preferred_formats: Vec<ImageFormat>,
+    // 保存为图片文件还是直接写入压缩包
+    pub archive_format: ArchiveFormat,
+    // 图片的存储后端，默认保存到本地文件系统，写入压缩包时不使用
+    pub storage: Arc<dyn Storage>,
+    // 每张图片保存后在专辑目录的 .thumbs 下生成 JPEG 缩略图，写入压缩包时不生成
+    pub generate_thumbnails: bool,
+    // 缩略图的宽度，高度按原图比例缩放
+    pub thumb_size: u32,
+    // 下载完成后上传到 S3 兼容的对象存储
+    #[cfg(feature = "s3")]
+    pub upload_s3: Option<s3::S3Config>,
+    // 上传后是否保留本地文件
+    #[cfg(feature = "s3")]
+    pub keep_local: bool
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            index_prefix: false,
+            parser_code_folder: false,
+            deadline: None,
+            album_concurrency: DEFAULT_ALBUM_CONCURRENCY,
+            picture_concurrency: DEFAULT_CONCURRENCY,
+            max_connections: DEFAULT_CONCURRENCY,
+            quiet: false,
+            progress_template: None,
+            progress_events: None,
+            requests_per_second_per_domain: None,
+            dedup: false,
+            max_consecutive_failures: Some(DEFAULT_MAX_CONSECUTIVE_FAILURES),
+            cancel: CancellationToken::new(),
+            pause: PauseGate::default(),
+            min_free_space: Some(DEFAULT_MIN_FREE_SPACE),
+            free_space,
+            shutdown_timeout: Duration::from_secs(30),
+            preferred_formats: ImageFormat::DEFAULT_PREFERENCE.to_vec(),
+            archive_format: ArchiveFormat::None,
+            storage: Arc::new(LocalStorage),
+            generate_thumbnails: false,
+            thumb_size: DEFAULT_THUMB_SIZE,
+            #[cfg(feature = "s3")]
+            upload_s3: None,
+            #[cfg(feature = "s3")]
+            keep_local: true
+        }
+    }
+}
+
+// 可以暂停、恢复的开关，克隆后共享同一个状态，多个下载可以同时暂停
+#[derive(Clone, Debug)]
+pub struct PauseGate {
+    paused: Arc<watch::Sender<bool>>
+}
+
+impl Default for PauseGate {
+    fn default() -> Self {
+        Self { paused: Arc::new(watch::Sender::new(false)) }
+    }
+}
+
+impl PauseGate {
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    // 暂停时等待恢复，未暂停时立即返回
+    pub async fn wait(&self) {
+        let mut paused = self.paused.subscribe();
+        let _ = paused.wait_for(|paused| !paused).await;
+    }
+}
+
+// 一次下载中各专辑共享的配置、连接数限制以及进度条
+struct DownloadContext {
+    config: DownloadConfig,
+    connections: Arc<Semaphore>,
+    progress: MultiProgress,
+    // 标准输出不是终端时不绘制进度条，改为输出进度日志
+    log_progress: bool,
+    // 按域名限制请求图片的速率，所有专辑共用
+    rate_limiters: Arc<DashMap<String, Arc<DefaultDirectRateLimiter>>>,
+    #[cfg(feature = "s3")]
+    uploader: Option<Arc<s3::Uploader>>
+}
+
+impl DownloadContext {
+    fn new(config: &DownloadConfig) -> Self {
+        Self::with_terminal(config, std::io::stdout().is_terminal())
+    }
+
+    fn with_terminal(config: &DownloadConfig, terminal: bool) -> Self {
+        let target = if terminal && !config.quiet {
+            ProgressDrawTarget::stdout()
+        } else {
+            ProgressDrawTarget::hidden()
+        };
+        Self {
+            config: config.clone(),
+            connections: Arc::new(Semaphore::new(config.max_connections.max(1))),
+            progress: MultiProgress::with_draw_target(target),
+            log_progress: !terminal && !config.quiet,
+            rate_limiters: Arc::new(DashMap::new()),
+            #[cfg(feature = "s3")]
+            uploader: config.upload_s3.as_ref().map(|s3_config| Arc::new(s3::Uploader::new(s3_config)))
+        }
+    }
+
+    fn progress_bar(&self, len: usize, style: ProgressStyle) -> AlbumProgress {
+        let bar = self.progress.add(ProgressBar::new(len as u64));
+        bar.set_style(style);
+        AlbumProgress { bar, log: self.log_progress, events: None }
+    }
+
+    // 未开启限速、速率无效或者地址中没有域名时返回 None
+    fn rate_limiter(&self, url: &str) -> Option<Arc<DefaultDirectRateLimiter>> {
+        let rate = self.config.requests_per_second_per_domain?;
+        if !(rate.is_finite() && rate > 0.0) {
+            warn!("invalid requests per second {}, rate limit disabled", rate);
+            return None;
+        }
+
+        let quota = Quota::with_period(Duration::try_from_secs_f64(1.0 / rate).ok()?)?;
+        let domain = reqwest::Url::parse(url).ok()?.host_str()?.to_string();
+        let limiter = self.rate_limiters.entry(domain).or_insert_with(|| Arc::new(RateLimiter::direct(quota)));
+        Some(limiter.clone())
+    }
+
+    fn picture_style(&self) -> ProgressStyle {
+        const TEMPLATE: &str = "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} ({eta})";
+        let style = match &self.config.progress_template {
+            Some(template) => ProgressStyle::with_template(template).unwrap_or_else(|err| {
+                warn!("invalid progress template {}: {:?}, use default", template, err);
+                ProgressStyle::with_template(TEMPLATE).unwrap()
+            }),
+            None => ProgressStyle::with_template(TEMPLATE).unwrap()
+        };
+        style.with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
+            .progress_chars("#>-")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DownloadProgress {
+    pub done: usize,
+    pub total: usize
+}
+
+// 进度条不绘制时，每完成约 10% 输出一行日志
+#[derive(Clone)]
+struct AlbumProgress {
+    bar: ProgressBar,
+    log: bool,
+    events: Option<UnboundedSender<DownloadProgress>>
+}
+
+impl AlbumProgress {
+    fn inc(&self, name: &str) {
+        self.bar.inc(1);
+        if let Some(events) = &self.events {
+            let progress = DownloadProgress { done: self.bar.position() as usize, total: self.bar.length().unwrap_or(0) as usize };
+            // 接收方关闭后不再需要进度
+            let _ = events.send(progress);
+        }
+        if !self.log {
+            return;
+        }
+
+        let (pos, len) = (self.bar.position(), self.bar.length().unwrap_or(0));
+        if pos == len || pos % (len / 10).max(1) == 0 {
+            info!("{} progress: {}/{}", name, pos, len);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DownloadResult {
+    pub total: usize,
+    // 专辑的图片总数，只下载部分图片时大于 total
+    pub album_total: usize,
+    pub downloaded: usize,
+    pub failed: usize,
+    // 下载失败的图片在专辑中的序号（从 1 开始）以及地址，用于重试
+    pub failed_urls: Vec<(usize, String)>,
+    // robots.txt 不允许下载而跳过的图片数
+    pub skipped: usize,
+    // 开启去重时，与专辑中已保存的图片内容相同而没有保存的图片数
+    pub duplicates: usize,
+    // 下载被取消而没有下载的图片数
+    pub cancelled: usize,
+    // 图片地址被重定向时的原地址以及最终地址
+    pub redirects: Vec<(String, String)>,
+    // 上传到对象存储成功、失败的图片数，上传失败不计入 failed
+    #[cfg(feature = "s3")]
+    pub uploaded: usize,
+    #[cfg(feature = "s3")]
+    pub upload_failed: usize,
+    // 写入压缩包时压缩包的路径
+    pub archive: Option<PathBuf>,
+    // 是否因超过下载时限而提前结束
+    pub deadline_exceeded: bool,
+    // 下载的字节数以及用时
+    pub bytes: u64,
+    pub elapsed: Duration
+}
+
+impl DownloadResult {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled > 0
+    }
+
+    // 平均下载速度，字节/秒
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    // 便于阅读的平均下载速度，如 1.50 MB/s
+    pub fn throughput_display(&self) -> String {
+        format_rate(self.throughput())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Album {
+    pub name: String,
+    pub cover: Option<String>,
+    pub url: String,
+    // 搜索结果中显示的图片数量，获取图片列表前用于提示
+    pub picture_count_hint: Option<u32>
+}
+
+// 只按地址判断是否为同一个专辑，名称、封面等可能因搜索来源不同而不同
+impl PartialEq for Album {
+    fn eq(&self, other: &Self) -> bool {
+        self.normalized_url() == other.normalized_url()
+    }
+}
+
+impl Eq for Album {}
+
+impl std::hash::Hash for Album {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.normalized_url().hash(state);
+    }
+}
+
+// 地址超过 60 个字符时只显示最后 20 个字符
+impl std::fmt::Display for Album {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let len = self.url.chars().count();
+        if len > 60 {
+            let tail: String = self.url.chars().skip(len - 20).collect();
+            write!(f, "{} (…{})", self.name, tail)
+        } else {
+            write!(f, "{} ({})", self.name, self.url)
+        }
+    }
+}
+
+impl Album {
+
+    // 只接受带有主机名的 HTTP/HTTPS 地址，地址中的用户名和密码会被去掉，避免出现在日志和目录名中
+    pub fn new(name: String, url: String) -> Result<Album> {
+        let invalid = || DownloaderError::InvalidUrl { url: url.clone(), reason: "expected http(s) url with a host".to_string() };
+        let mut parsed = reqwest::Url::parse(url.trim()).map_err(|_| invalid())?;
+        if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none_or(|host| host.is_empty()) {
+            return Err(invalid().into());
+        }
+        let _ = parsed.set_username("");
+        let _ = parsed.set_password(None);
+
+        Ok(Album { name, cover: None, url: parsed.to_string(), picture_count_hint: None })
+    }
+
+    // 用于比较专辑的地址：忽略 http 和 https 的区别，主机名不区分大小写，去掉末尾的斜杠和锚点
+    pub fn normalized_url(&self) -> String {
+        let Ok(parsed) = reqwest::Url::parse(self.url.trim()) else {
+            return self.url.trim().trim_end_matches('/').to_string();
+        };
+        let port = parsed.port().map(|port| format!(":{}", port)).unwrap_or_default();
+        let query = parsed.query().map(|query| format!("?{}", query)).unwrap_or_default();
+        format!("{}{}{}{}", parsed.host_str().unwrap_or(""), port, parsed.path().trim_end_matches('/'), query)
+    }
+
+    // 专辑保存的目录名，名称为空时根据 URL 生成，与其它专辑重名时追加 URL 的短哈希
+    pub fn folder_name(&self, duplicated: bool) -> String {
+        let name = filenamify(self.name.trim(), "");
+        let name = if name.trim().is_empty() {
+            url_slug(&self.url)
+        } else {
+            name
+        };
+
+        if duplicated {
+            format!("{}_{}", name, short_hash(&self.url))
+        } else {
+            name
+        }
+    }
+
+    // 预览使用的图片：专辑封面，没有封面时使用专辑的第一张图片
+    async fn preview_url(&self, parser: &dyn Parser) -> Result<String> {
+        match &self.cover {
+            Some(cover) if !cover.trim().is_empty() => Ok(cover.clone()),
+            _ => {
+                let pictures = parser.get_page_pictures(self.url.clone()).await?;
+                pictures.into_iter().next().ok_or(anyhow!("album {} has no pictures", self.url))
+            }
+        }
+    }
+
+    // 请求图片，返回图片内容、重定向后的地址（与原地址相同时为 None）以及响应的 Content-Type
+    async fn fetch_picture(client: &Client, parser: &dyn Parser, url: &str) -> Result<(bytes::Bytes, Option<String>, Option<String>)> {
+        if !parser.is_allowed(url).await {
+            warn!("picture {} is disallowed by robots.txt, skipped", url);
+            return Err(DownloaderError::Disallowed(url.to_string()).into());
+        }
+
+        let mut headers = default_headers();
+        headers.extend(parser.picture_headers());
+        if let Some(user_agent) = parser.user_agent() {
+            headers.insert(header::USER_AGENT, user_agent);
+        }
+        if let Some(authorization) = parser.authorization() {
+            headers.insert(header::AUTHORIZATION, authorization);
+        }
+        retry_async(&parser.retry_policy(), || async {
+            let response = execute_traced(client, client.get(url).headers(headers.clone())).await
+                .with_context(|| format!("Failed to send request for {}", url))?;
+            if !response.status().is_success() {
+                return Err(DownloaderError::HttpError { status: response.status(), url: url.to_string() }.into());
+            }
+            let final_url = (response.url().as_str() != url).then(|| response.url().to_string());
+            let content_type = response.headers().get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).map(|value| value.to_string());
+            Ok((response.bytes().await?, final_url, content_type))
+        }).await
+    }
+
+    // 下载图片写入任意的 sink，如内存、管道或者上传流，返回写入的字节数
+    pub async fn download_picture_to<W: AsyncWrite + Unpin>(&self, parser: &dyn Parser, url: &str, mut sink: W) -> Result<u64> {
+        let (bytes, _, _) = Self::fetch_picture(&parser.client(), parser, url).await?;
+        Self::write_to(&mut sink, &bytes).await.with_context(|| format!("failed to write picture {}", url))?;
+        Ok(bytes.len() as u64)
+    }
+
+    // 返回保存的路径以及图片的字节数，target.hashes 不为 None 时跳过内容重复的图片
+    // target.archive 不为 None 时写入压缩包，返回的路径为图片在压缩包中的名称
+    async fn download_picture(&self, client: &Client, parser: &dyn Parser, url: &str, save_to_path: PathBuf, prefix: &str,
+                              target: SaveTarget<'_>) -> Result<(PathBuf, u64, Option<String>)> {
+        let SaveTarget { storage, hashes, archive } = target;
+        let (bytes, final_url, content_type) = Self::fetch_picture(client, parser, url).await?;
+
+        // CDN 重定向后的地址更稳定，文件名使用最终地址中的名称，无法取得时使用原地址
+        let name = match &final_url {
+            Some(final_url) => {
+                debug!("picture {} redirected to {}", url, final_url);
+                parser.get_picture_name(final_url).or_else(|_| parser.get_picture_name(url))?
+            }
+            None => parser.get_picture_name(url)?
+        };
+        let name = with_media_extension(name, content_type.as_deref(), &bytes);
+        let picture_name = filenamify(format!("{}{}", prefix, name), "");
+        let path = match archive {
+            Some(_) => PathBuf::from(&picture_name),
+            None => save_to_path.join(fit_file_name(&save_to_path, &picture_name))
+        };
+        let hash = match hashes {
+            Some(hashes) => {
+                let hash: [u8; 32] = Sha256::digest(&bytes).into();
+                let mut hashes = hashes.lock().unwrap();
+                if let Some(existing) = hashes.get(&hash) {
+                    return Err(DownloaderError::Duplicate { url: url.to_string(), existing: existing.clone() }.into());
+                }
+                hashes.insert(hash, path.clone());
+                Some(hash)
+            }
+            None => None
+        };
+        if let Some(archive) = archive {
+            let mut archive = archive.lock().await;
+            return match archive.add(&picture_name, url, &bytes).await {
+                Ok(name) => Ok((PathBuf::from(name), bytes.len() as u64, final_url)),
+                Err(err) => {
+                    if let (Some(hashes), Some(hash)) = (hashes, hash) {
+                        hashes.lock().unwrap().remove(&hash);
+                    }
+                    Err(DownloaderError::from_io(err, &archive.path).into())
+                }
+            };
+        }
+        if let Err(err) = storage.put(&path, &bytes).await {
+            if let (Some(hashes), Some(hash)) = (hashes, hash) {
+                hashes.lock().unwrap().remove(&hash);
+            }
+            return Err(DownloaderError::from_io(err, &path).into());
+        }
+        // 确认保存的大小与下载的内容一致
+        if let Some(size) = storage.size(&path).await.filter(|size| *size != bytes.len() as u64) {
+            return Err(anyhow!("saved size {} of {} does not match downloaded size {}", size, path.display(), bytes.len()));
+        }
+
+        Ok((path, bytes.len() as u64, final_url))
+    }
+
+    async fn write_to<W: AsyncWrite + Unpin>(sink: &mut W, bytes: &[u8]) -> std::io::Result<()> {
+        sink.write_all(bytes).await?;
+        sink.flush().await
+    }
+
+    // 专辑中要下载的图片和视频，图片按偏好的格式筛选，视频排在图片之后
+    async fn get_media(&self, parser: &dyn Parser, config: &DownloadConfig) -> Result<Vec<String>> {
+        let (videos, pictures): (Vec<Media>, Vec<Media>) = parser.get_all_media(self.url.clone()).await?.into_iter().partition(Media::is_video);
+        let mut urls = select_preferred_formats(pictures.into_iter().map(Media::into_url).collect(), &config.preferred_formats);
+        urls.extend(videos.into_iter().map(Media::into_url));
+        Ok(urls)
+    }
+
+    async fn download_pictures(self: Arc<Self>, client: &Client, parser: Arc<dyn Parser>, save_to_path: &Path, context: &DownloadContext) -> Result<DownloadResult> {
+        parser.validate_url(&self.url)?;
+        let pictures = self.get_media(&*parser, &context.config).await?;
+        let total = pictures.len();
+        let pictures = pictures.into_iter().enumerate().map(|(i, url)| (i + 1, url)).collect();
+        self.save_pictures(client, parser, save_to_path, context, pictures, total).await
+    }
+
+    // 只下载专辑中第 start 到第 end 张图片（从 1 开始，包含两端）
+    async fn download_range(self: Arc<Self>, client: &Client, parser: Arc<dyn Parser>, save_to_path: &Path, context: &DownloadContext,
+                            start: usize, end: usize) -> Result<DownloadResult> {
+        parser.validate_url(&self.url)?;
+        let pictures = self.get_media(&*parser, &context.config).await?;
+        let total = pictures.len();
+        let range = picture_range(total, start, end)?;
+        info!("download pictures {}-{} of album {}, total: {}", start, end, self.name, total);
+        let pictures = pictures.into_iter().enumerate()
+            .map(|(i, url)| (i + 1, url))
+            .skip(range.start)
+            .take(range.len())
+            .collect();
+        self.save_pictures(client, parser, save_to_path, context, pictures, total).await
+    }
+
+    // 从当前专辑开始沿着 "下一篇" 链接下载整个系列，最多再跟随 max_depth 个专辑，每个专辑保存到各自的目录
+    async fn download_series(self: Arc<Self>, client: &Client, parser: Arc<dyn Parser>, save_to_path: &Path, context: &DownloadContext,
+                             max_depth: usize) -> Vec<Result<DownloadResult>> {
+        let root = save_to_path.parent().unwrap_or(save_to_path).to_path_buf();
+        let mut visited = HashSet::new();
+        let mut results = vec![];
+        let mut album = self;
+        let mut path = save_to_path.to_path_buf();
+        for depth in 0..=max_depth {
+            // 链接绕回已下载的专辑时结束，避免循环
+            if !visited.insert(album.url.clone()) {
+                info!("album {} already downloaded in series, stop", album.url);
+                break;
+            }
+
+            info!("download series album {}, depth: {}, path: {:?}", album.url, depth, path);
+            results.push(album.clone().download_pictures(client, parser.clone(), &path, context).await);
+            if depth == max_depth {
+                break;
+            }
+
+            let next = match parser.next_album(album.url.clone()).await {
+                Ok(Some(next)) => next,
+                Ok(None) => break,
+                Err(err) => {
+                    error!("get next album of {} error: {:?}", album.url, err);
+                    break;
+                }
+            };
+            // 只知道下一个专辑的地址，目录名根据地址生成
+            album = match Album::new("".to_string(), next) {
+                Ok(next) => Arc::new(next),
+                Err(err) => {
+                    error!("next album of {} error: {:?}", album.url, err);
+                    break;
+                }
+            };
+            path = root.join(album.folder_name(false));
+        }
+        results
+    }
+
+    // 重新下载上次失败的图片，保存到同一目录
+    async fn retry_pictures(self: Arc<Self>, client: &Client, parser: Arc<dyn Parser>, save_to_path: &Path, context: &DownloadContext,
+                            failed: &DownloadResult) -> Result<DownloadResult> {
+        info!("retry {} failed pictures of album {}", failed.failed_urls.len(), self.name);
+        self.save_pictures(client, parser, save_to_path, context, failed.failed_urls.clone(), failed.album_total).await
+    }
+
+    // pictures 中的序号是图片在整个专辑中的位置（从 1 开始），total 为专辑图片总数
+    async fn save_pictures(self: Arc<Self>, client: &Client, parser: Arc<dyn Parser>, save_to_path: &Path, context: &DownloadContext,
+                           pictures: Vec<(usize, String)>, total: usize) -> Result<DownloadResult> {
+        let config = &context.config;
+        let path = save_to_path.to_path_buf();
+        let local = config.storage.is_local();
+        let archiving = config.archive_format != ArchiveFormat::None;
+        if local || archiving {
+            create_album_dir(&path, config).await?;
+            // 在创建压缩包之前检查，避免空间不足时留下未完成的压缩包
+            check_free_space(config, path.parent().filter(|_| archiving).unwrap_or(&path), pictures.len())?;
+        }
+        let archive = match config.archive_format.extension() {
+            Some(extension) => {
+                // 已有同名的压缩包时（如重试失败的图片）另存为 <专辑名>_1.zip 等，不覆盖之前的压缩包
+                let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+                let mut archive_path = path.with_file_name(format!("{}.{}", file_name, extension));
+                let mut n = 0;
+                while tokio::fs::try_exists(&archive_path).await.unwrap_or(false) {
+                    n += 1;
+                    archive_path = path.with_file_name(format!("{}_{}.{}", file_name, n, extension));
+                }
+                info!("write pictures of album {} to archive {:?}", self.name, archive_path);
+                Some(Arc::new(tokio::sync::Mutex::new(ArchiveWriter::create(archive_path, config.archive_format).await?)))
+            }
+            None => None
+        };
+
+        let mut pb = context.progress_bar(pictures.len(), context.picture_style());
+        pb.events = config.progress_events.clone();
+
+        let mut result = DownloadResult {
+            total: pictures.len(),
+            album_total: total,
+            ..DownloadResult::default()
+        };
+        let start = Instant::now();
+        let downloaded = Arc::new(AtomicUsize::new(0));
+        let downloaded_bytes = Arc::new(AtomicU64::new(0));
+        let failed = Arc::new(AtomicUsize::new(0));
+        let failed_urls = Arc::new(Mutex::new(Vec::new()));
+        let redirects = Arc::new(Mutex::new(Vec::new()));
+        let skipped = Arc::new(AtomicUsize::new(0));
+        let duplicates = Arc::new(AtomicUsize::new(0));
+        let consecutive_failures = Arc::new(AtomicUsize::new(0));
+        let cancelled = Arc::new(AtomicUsize::new(0));
+        let cancel = config.cancel.clone();
+        let hashes = config.dedup.then(|| Arc::new(Mutex::new(HashMap::new())));
+        #[cfg(feature = "s3")]
+        let uploads = Arc::new(s3::UploadStats::default());
+        // 磁盘空间不足等存储错误，后续图片也无法保存，直接结束整个专辑的下载
+        let fatal: Arc<Mutex<Option<DownloaderError>>> = Arc::new(Mutex::new(None));
+        let width = total.to_string().len().max(4);
+        let semaphore = Arc::new(Semaphore::new(config.picture_concurrency.max(1)));
+        let mut tasks = JoinSet::new();
+        let run = async {
+            let mut pictures = pictures.into_iter();
+            while let Some((index, url)) = pictures.next() {
+                // 先占用专辑内的并发数，再占用所有专辑共享的连接数
+                let permits = tokio::select! {
+                    permits = async {
+                        let permit = semaphore.clone().acquire_owned().await?;
+                        let connection = context.connections.clone().acquire_owned().await?;
+                        Ok::<_, anyhow::Error>((permit, connection))
+                    } => Some(permits?),
+                    _ = cancel.cancelled() => None
+                };
+                let Some((permit, connection)) = permits else {
+                    // 取消后剩余的图片不再下载
+                    cancelled.fetch_add(1 + pictures.len(), Ordering::SeqCst);
+                    break;
+                };
+                if fatal.lock().unwrap().is_some() {
+                    break;
+                }
+
+                // 文件名加上序号前缀，按名称排序时与专辑中的顺序一致
+                let prefix = if config.index_prefix {
+                    format!("{:0width$}_", index, width = width)
+                } else {
+                    "".to_string()
+                };
+                let base_path = path.clone();
+                let pb = pb.clone();
+                let client = client.clone();
+                let p = parser.clone();
+                let it = Arc::clone(&self);
+                let downloaded = downloaded.clone();
+                let downloaded_bytes = downloaded_bytes.clone();
+                let failed = failed.clone();
+                let failed_urls = failed_urls.clone();
+                let redirects = redirects.clone();
+                let skipped = skipped.clone();
+                let duplicates = duplicates.clone();
+                let consecutive_failures = consecutive_failures.clone();
+                let max_consecutive_failures = config.max_consecutive_failures;
+                let cancelled = cancelled.clone();
+                let cancel = cancel.clone();
+                let pause = config.pause.clone();
+                let thumb_size = (config.generate_thumbnails && local && archive.is_none()).then_some(config.thumb_size);
+                let storage = config.storage.clone();
+                let archive = archive.clone();
+                let hashes = hashes.clone();
+                let fatal = fatal.clone();
+                #[cfg(feature = "s3")]
+                let upload = context.uploader.clone().filter(|_| local).map(|uploader| (uploader, uploads.clone(), config.keep_local));
+                let rate_limiter = context.rate_limiter(&url);
+                tasks.spawn(async move {
+                    if let Some(rate_limiter) = rate_limiter {
+                        rate_limiter.until_ready().await;
+                    }
+                    // 暂停期间也可以取消
+                    tokio::select! {
+                        _ = pause.wait() => {},
+                        _ = cancel.cancelled() => {}
+                    }
+                    let ret = if cancel.is_cancelled() {
+                        None
+                    } else {
+                        tokio::select! {
+                            ret = it.download_picture(&client, &*p, &url, base_path, &prefix,
+                                                      SaveTarget { storage: &*storage, hashes: hashes.as_deref(), archive: archive.as_deref() }) => Some(ret),
+                            _ = cancel.cancelled() => None
+                        }
+                    };
+                    let Some(ret) = ret else {
+                        cancelled.fetch_add(1, Ordering::SeqCst);
+                        return;
+                    };
+                    match ret {
+                        Ok((picture_path, bytes, final_url)) => {
+                            if let Some(final_url) = final_url {
+                                redirects.lock().unwrap().push((url.clone(), final_url));
+                            }
+                            pb.inc(&it.name);
+                            consecutive_failures.store(0, Ordering::SeqCst);
+                            downloaded.fetch_add(1, Ordering::SeqCst);
+                            downloaded_bytes.fetch_add(bytes, Ordering::SeqCst);
+                            info!("picture {url} downloaded.");
+                            // 缩略图生成失败不影响图片的下载结果
+                            if let Some(thumb_size) = thumb_size {
+                                let path = picture_path.clone();
+                                match tokio::task::spawn_blocking(move || generate_thumbnail(&path, thumb_size)).await {
+                                    Ok(Ok(thumb_path)) => debug!("thumbnail of {:?} saved to {:?}", picture_path, thumb_path),
+                                    Ok(Err(err)) => warn!("generate thumbnail of {:?} error: {:?}", picture_path, err),
+                                    Err(err) => warn!("generate thumbnail of {:?} task error: {:?}", picture_path, err)
+                                }
+                            }
+                            #[cfg(feature = "s3")]
+                            if let Some((uploader, uploads, keep_local)) = upload.filter(|_| archive.is_none()) {
+                                uploader.upload_picture(&picture_path, keep_local, &uploads).await;
+                            }
+                        },
+                        Err(err) => {
+                            if let Some(DownloaderError::Disallowed(_)) = err.downcast_ref::<DownloaderError>() {
+                                // robots.txt 不允许下载的图片跳过，不算作失败
+                                pb.inc(&it.name);
+                                skipped.fetch_add(1, Ordering::SeqCst);
+                            } else if let Some(DownloaderError::Duplicate { .. }) = err.downcast_ref::<DownloaderError>() {
+                                pb.inc(&it.name);
+                                duplicates.fetch_add(1, Ordering::SeqCst);
+                                info!("{}", err);
+                            } else {
+                                failed.fetch_add(1, Ordering::SeqCst);
+                                error!("download picture {} error: {:?}", url, err);
+                                failed_urls.lock().unwrap().push((index, url.clone()));
+                                match err.downcast::<DownloaderError>() {
+                                    Ok(err) if err.is_storage_fatal() => {
+                                        println!("{}", err);
+                                        fatal.lock().unwrap().get_or_insert(err);
+                                    }
+                                    _ => println!("下载图片失败，详情请查看日志")
+                                }
+                                let failures = consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+                                if max_consecutive_failures.is_some_and(|max| failures >= max) {
+                                    fatal.lock().unwrap().get_or_insert(DownloaderError::TooManyFailures(failures));
+                                }
+                            }
+                        }
+                    }
+
+                    drop(connection);
+                    drop(permit);
+                });
+            }
+
+            while let Some(ret) = tasks.join_next().await {
+                match ret {
+                    Err(err) if err.is_cancelled() => {}
+                    Err(err) => {
+                        failed.fetch_add(1, Ordering::SeqCst);
+                        error!("download picture task error: {:?}", err);
+                        println!("下载图片失败，详情请查看日志");
+                    }
+                    Ok(_) => {}
+                }
+
+                if fatal.lock().unwrap().is_some() {
+                    tasks.abort_all();
+                }
+            }
+            Ok::<(), anyhow::Error>(())
+        };
+
+        match config.deadline {
+            Some(deadline) => {
+                match tokio::time::timeout(deadline, run).await {
+                    Ok(ret) => ret?,
+                    Err(_) => result.deadline_exceeded = true
+                }
+            }
+            None => run.await?
+        }
+
+        // 中止、取消时也写完压缩包，保留已下载的图片
+        if let Some(archive) = &archive {
+            tasks.shutdown().await;
+            let mut archive = archive.lock().await;
+            archive.finish().await.with_context(|| format!("failed to finish archive {:?}", archive.path))?;
+            result.archive = Some(archive.path.clone());
+        }
+
+        let fatal = fatal.lock().unwrap().take();
+        if let Some(err) = fatal {
+            pb.bar.abandon_with_message("下载中止");
+            error!("download album {} aborted: {:?}", self.name, err);
+            return Err(err.into());
+        }
+
+        result.cancelled = cancelled.load(Ordering::SeqCst);
+        if result.deadline_exceeded {
+            // 超过时限后取消剩余的下载任务，并等待任务退出
+            tasks.shutdown().await;
+            warn!("download album {} exceeded deadline {:?}", self.name, config.deadline);
+            pb.bar.abandon_with_message("超过下载时限");
+        } else if result.is_cancelled() {
+            warn!("download album {} cancelled, {} pictures not downloaded", self.name, result.cancelled);
+            pb.bar.abandon_with_message("下载已取消");
+        } else {
+            pb.bar.finish_with_message("下载完成");
+        }
+
+        result.downloaded = downloaded.load(Ordering::SeqCst);
+        result.bytes = downloaded_bytes.load(Ordering::SeqCst);
+        result.elapsed = start.elapsed();
+        info!("download album {} {} bytes in {:?}, {}", self.name, result.bytes, result.elapsed, result.throughput_display());
+        result.failed = failed.load(Ordering::SeqCst);
+        result.failed_urls = std::mem::take(&mut *failed_urls.lock().unwrap());
+        result.failed_urls.sort();
+        result.redirects = std::mem::take(&mut *redirects.lock().unwrap());
+        result.redirects.sort();
+        result.skipped = skipped.load(Ordering::SeqCst);
+        result.duplicates = duplicates.load(Ordering::SeqCst);
+        #[cfg(feature = "s3")]
+        {
+            result.uploaded = uploads.uploaded.load(Ordering::SeqCst);
+            result.upload_failed = uploads.failed.load(Ordering::SeqCst);
+        }
+        Ok(result)
+    }
+}
+
+pub type AlbumResult<'a> = Result<Option<&'a Vec<Album>>>;
+
+pub mod parser {
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use anyhow::{anyhow, Context, Result};
+    use async_trait::async_trait;
+    use bitflags::bitflags;
+    use dashmap::DashMap;
+    use indexmap::IndexSet;
+    use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+    use pinyin::ToPinyin;
+    use regex::Regex;
+    use reqwest::{Client, header};
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use scraper::{ElementRef, Html, Selector};
+    use reqwest::Url;
+    use serde::Serialize;
+    use sha2::{Digest, Sha256};
+    use tokio::sync::{OnceCell, Semaphore};
+    use tokio::task::JoinSet;
+    use tracing::{error, info, trace, warn};
+
+    use crate::{Album, Auth, ClientConfig, DEFAULT_CONCURRENCY, DEFAULT_PICTURE_ACCEPT, DEFAULT_USER_AGENTS, default_headers, DownloaderError, HttpFetcher, UserAgentRotation};
+    use crate::retry::{retry_async, RetryPolicy};
+    use crate::util::{picture_count_hint, Robots, short_hash};
+
+    // 按优先级读取图片地址的属性，懒加载的站点 src 往往只是占位图
+    pub const DEFAULT_PICTURE_ATTRIBUTES: [&str; 4] = ["data-src", "data-original", "srcset", "src"];
+    // 比这更短的地址不可能是有效的图片地址，多为占位符
+    const MIN_PICTURE_URL_LEN: usize = 10;
+
+    #[derive(Clone)]
+    struct InnerParser {
+        fetcher: HttpFetcher,
+        base_url: String,
+        page: u32,
+        page_count: u32,
+        client_config: Arc<ClientConfig>,
+        // 轮换 User-Agent 的计数，克隆出的解析器共用同一个计数
+        user_agent_index: Arc<AtomicUsize>,
+        pagination_scheme: PaginationScheme,
+        // 替换内置的图片选择器，站点改版后不需要重新编译即可修复
+        picture_selector: Option<String>,
+        picture_attributes: Vec<String>,
+        // 替换内置的专辑链接选择器，相对于每个搜索结果
+        album_selector: Option<String>,
+        // 选择器找不到图片时，在页面源码中匹配图片地址，适用于图片列表写在内联脚本或 JSON 中的站点
+        picture_pattern: Option<Regex>,
+        // 按站点缓存的 robots.txt，每个站点只请求一次
+        robots: Arc<DashMap<String, Arc<OnceCell<Robots>>>>
+    }
+
+    impl InnerParser {
+        fn new(base_url: &str, client: Option<Client>, client_config: ClientConfig) -> Self {
+            match client {
+                Some(client) => Self::with_client(base_url, client, client_config),
+                None => Self::with_client(base_url, Client::new(), client_config)
+            }
+        }
+
+        // 多个解析器共用同一个 Client，可以共享连接池
+        fn with_client(base_url: &str, client: Client, client_config: ClientConfig) -> Self {
+            Self {
+                fetcher: HttpFetcher::new(client),
+                base_url: base_url.trim_end_matches('/').to_string(),
+                page: 0,
+                page_count: 0,
+                client_config: Arc::new(client_config),
+                user_agent_index: Arc::new(AtomicUsize::new(0)),
+                pagination_scheme: PaginationScheme::SuffixUnderscore,
+                picture_selector: None,
+                picture_attributes: DEFAULT_PICTURE_ATTRIBUTES.iter().map(|attr| attr.to_string()).collect(),
+                album_selector: None,
+                picture_pattern: None,
+                robots: Arc::new(DashMap::new())
+            }
+        }
+
+        // 按照轮换策略选择本次请求的 User-Agent，池为空时使用默认请求头中的值
+        fn user_agent(&self) -> Option<HeaderValue> {
+            let pool = &self.client_config.user_agent_pool;
+            if pool.is_empty() {
+                return None;
+            }
+
+            let index = match self.client_config.rotation {
+                UserAgentRotation::Fixed => 0,
+                UserAgentRotation::RoundRobin => self.user_agent_index.fetch_add(1, Ordering::Relaxed) % pool.len(),
+                UserAgentRotation::Random => RandomState::new().hash_one(self.user_agent_index.fetch_add(1, Ordering::Relaxed)) as usize % pool.len()
+            };
+            HeaderValue::from_str(&pool[index]).map_err(|err| {
+                error!("invalid user agent {}: {:?}", pool[index], err);
+            }).ok()
+        }
+
+        fn authorization(&self) -> Option<HeaderValue> {
+            self.client_config.auth.as_ref().and_then(|auth| auth.header_value())
+        }
+
+        fn picture_headers(&self) -> HeaderMap {
+            let mut headers = HeaderMap::new();
+            let accept = HeaderValue::from_str(&self.client_config.picture_accept).unwrap_or_else(|err| {
+                warn!("invalid picture accept header {}: {:?}", self.client_config.picture_accept, err);
+                HeaderValue::from_static(DEFAULT_PICTURE_ACCEPT)
+            });
+            headers.insert(header::ACCEPT, accept);
+            if let Some(referer) = &self.client_config.referer {
+                match HeaderValue::from_str(referer) {
+                    Ok(referer) => {
+                        headers.insert(header::REFERER, referer);
+                    }
+                    Err(err) => warn!("invalid referer {}: {:?}", referer, err)
+                }
+            }
+            headers
+        }
+
+        // 专辑地址应在站点的域名下，替换了站点地址时（如测试或镜像站）也允许该地址的域名
+        fn validate_url(&self, url: &str, site_hosts: &[&str], default_base_url: &str) -> Result<()> {
+            let mut hosts: Vec<String> = site_hosts.iter().map(|host| host.to_string()).collect();
+            if self.base_url != default_base_url.trim_end_matches('/') {
+                hosts.extend(Url::parse(&self.base_url).ok().and_then(|url| url.host_str().map(|host| host.to_string())));
+            }
+            validate_url_host(url, &hosts)
+        }
+
+        // 开启 robots.txt 检查时，判断是否允许访问 url
+        async fn is_allowed(&self, url: &str) -> bool {
+            if !self.client_config.respect_robots {
+                return true;
+            }
+
+            let Ok(parsed) = Url::parse(url) else {
+                return true;
+            };
+            let origin = parsed.origin().ascii_serialization();
+            let cell = self.robots.entry(origin.clone()).or_default().clone();
+            let robots = cell.get_or_init(|| self.fetch_robots(origin)).await;
+            let path = match parsed.query() {
+                Some(query) => format!("{}?{}", parsed.path(), query),
+                None => parsed.path().to_string()
+            };
+            robots.is_allowed(&path)
+        }
+
+        // 获取 robots.txt 失败时视为允许访问所有地址
+        async fn fetch_robots(&self, origin: String) -> Robots {
+            let url = format!("{}/robots.txt", origin);
+            let user_agent = self.client_config.user_agent_pool.first().map(|ua| ua.as_str()).unwrap_or(DEFAULT_USER_AGENTS[0]);
+            let response = match self.fetcher.client().get(&url).headers(default_headers()).send().await {
+                Ok(response) => response.error_for_status(),
+                Err(err) => Err(err)
+            };
+            match response {
+                Ok(response) => match response.text().await {
+                    Ok(content) => Robots::parse(&content, user_agent),
+                    Err(err) => {
+                        warn!("read {} error: {:?}", url, err);
+                        Robots::default()
+                    }
+                },
+                Err(err) => {
+                    warn!("fetch {} error: {:?}", url, err);
+                    Robots::default()
+                }
+            }
+        }
+
+        async fn get_url_content(&self, url: &str, encoding: Option<String>, headers: Option<HeaderMap>) -> Result<(String, HeaderMap)> {
+            if !self.is_allowed(url).await {
+                warn!("{} is disallowed by robots.txt, skipped", url);
+                return Err(DownloaderError::Disallowed(url.to_string()).into());
+            }
+
+            let mut headers = headers.unwrap_or_default();
+            if let Some(user_agent) = self.user_agent() {
+                headers.insert(header::USER_AGENT, user_agent);
+            }
+            if let Some(authorization) = self.authorization() {
+                headers.insert(header::AUTHORIZATION, authorization);
+            }
+            let (content, headers) = retry_async(&self.client_config.retry_policy, || {
+                self.fetcher.get_url_content(url, encoding.clone(), Some(headers.clone()))
+            }).await?;
+            if let Some(dir) = &self.client_config.debug_html_dir {
+                save_debug_html(dir, url, &content).await;
+            }
+            Ok((content, headers))
+        }
+
+        // selector 为解析器内置的选择器，构建解析器时指定了选择器则使用指定的
+        async fn get_page_pictures(&self, url: String, selector: &str, encoding: Option<String>, headers: Option<HeaderMap>) -> Result<Vec<String>> {
+            let (html, _) = self.get_url_content(&url, encoding, headers).await?;
+            self.pictures_from_html(&html, selector, &url)
+        }
+
+        // 从页面源码中提取图片地址，不发出请求，url 只用于日志
+        fn pictures_from_html(&self, html: &str, selector: &str, url: &str) -> Result<Vec<String>> {
+            let selector = self.picture_selector.as_deref().unwrap_or(selector);
+            let document = Html::parse_document(html);
+            let selector = Selector::parse(selector).map_err(|err| {
+                anyhow!("parse page pictures selector error: {err:?}")
+            })?;
+
+            // 去重的同时保持图片在页面中的顺序
+            let pictures: IndexSet<String> = document.select(&selector).filter_map(|element| {
+                self.picture_attributes.iter().find_map(|attr| {
+                    let value = element.value().attr(attr)?.trim();
+                    let url = if attr == "srcset" {
+                        best_srcset_candidate(value)?
+                    } else {
+                        value
+                    };
+                    // 跳过过短的地址和内联的占位图
+                    (url.len() >= MIN_PICTURE_URL_LEN && !url.starts_with("data:")).then(|| url.to_string())
+                })
+            }).collect();
+            if pictures.is_empty() {
+                if let Some(pattern) = &self.picture_pattern {
+                    let pictures = inline_pictures(html, pattern);
+                    info!("no pictures matched selector in {}, {} pictures found by pattern", url, pictures.len());
+                    return Ok(pictures);
+                }
+            }
+            Ok(pictures.into_iter().collect())
+        }
+
+        // 页面中 <video src> 以及 <video><source src> 的视频地址，相对地址以页面地址补全
+        fn videos_from_html(&self, html: &str, url: &str) -> Vec<String> {
+            let document = Html::parse_document(html);
+            let selector = Selector::parse("video[src], video source[src]").unwrap();
+            let base = Url::parse(url).ok();
+            let videos: IndexSet<String> = document.select(&selector).filter_map(|element| {
+                let src = element.value().attr("src")?.trim();
+                if src.is_empty() || src.starts_with("data:") || src.starts_with("blob:") {
+                    return None;
+                }
+                Some(base.as_ref().and_then(|base| base.join(src).ok()).map_or(src.to_string(), |url| url.to_string()))
+            }).collect();
+            videos.into_iter().collect()
+        }
+
+        // 只请求一次页面，同时返回其中的图片和视频
+        async fn get_page_media(&self, url: String, selector: &str, encoding: Option<String>, headers: Option<HeaderMap>) -> Result<(Vec<String>, Vec<String>)> {
+            let (html, _) = self.get_url_content(&url, encoding, headers).await?;
+            let pictures = self.pictures_from_html(&html, selector, &url)?;
+            Ok((pictures, self.videos_from_html(&html, &url)))
+        }
+
+        // 本地保存的页面中可能是相对地址，以 base_url 补全，base_url 为空时保持原样
+        fn parse_pictures_from_html(&self, html: &str, selector: &str, base_url: &str) -> Result<Vec<String>> {
+            let pictures = self.pictures_from_html(html, selector, base_url)?;
+            if base_url.is_empty() {
+                return Ok(pictures);
+            }
+
+            let base = Url::parse(base_url).with_context(|| format!("invalid base url {}", base_url))?;
+            Ok(pictures.into_iter().map(|picture| match base.join(&picture) {
+                Ok(url) => url.to_string(),
+                Err(_) => picture
+            }).collect())
+        }
+
+        // 取地址路径的最后一段作为文件名，去掉查询参数以及 CDN 的 @ 处理参数，如 01.jpg@!rw9 -> 01.jpg
+        // 地址中没有文件名时使用地址的 SHA-256 哈希作为文件名
+        fn get_picture_name(&self,  url: &str) -> Result<String> {
+            let path = url.split(['?', '#']).next().unwrap_or("");
+            let path = match path.split_once("://") {
+                Some((_, rest)) => rest.split_once('/').map(|(_, path)| path).unwrap_or(""),
+                None => path
+            };
+            let name = path.rsplit('/').next().unwrap_or("").split('@').next().unwrap_or("");
+            if name.is_empty() || name == "." || name == ".." {
+                let digest = Sha256::digest(url.as_bytes());
+                return Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect());
+            }
+            Ok(name.to_string())
+        }
+
+        fn default_get_name_and_url(&self, root_element: ElementRef, path: &str) -> (String, String) {
+            let selector = Selector::parse(path).unwrap();
+            let element = root_element.select(&selector).next();
+            element.and_then(|e| {
+                Some((e.text().collect::<Vec<_>>().join(""), e.value().attr("href").unwrap_or("").to_string()))
+            }).unwrap_or(("".to_string(), "".to_string()))
+        }
+
+        fn default_get_cover(&self, root_element: ElementRef, path: &str) -> Option<String> {
+            let selector = Selector::parse(path).unwrap();
+            let element = root_element.select(&selector).next();
+            element.and_then(|e| {
+                e.value().attr("src").map(|url| url.to_string())
+            })
+        }
+
+        // 查找文本为 "下一篇"、"下一组" 等的链接，或者紧跟在这些文字之后的链接，如 下一篇：<a href="...">标题</a>
+        fn default_next_album_url(&self, document: &Html) -> Option<String> {
+            const MARKERS: [&str; 3] = ["下一篇", "下一组", "下一套"];
+            let selector = Selector::parse("a[href]").unwrap();
+            let is_next = |text: &str| MARKERS.iter().any(|marker| text.contains(marker));
+            document.select(&selector).find(|element| {
+                let previous = element.prev_sibling().and_then(|node| node.value().as_text().map(|text| text.to_string()));
+                is_next(&element.text().collect::<String>()) || previous.is_some_and(|text| is_next(&text))
+            }).and_then(|element| element.value().attr("href")).map(|href| href.to_string())
+        }
+
+        // 以专辑地址为基准解析下一个专辑的链接
+        fn resolve_next_album(&self, url: &str, next: Option<String>) -> Option<String> {
+            let next = next.filter(|next| !next.trim().is_empty() && !next.starts_with('#') && !next.starts_with("javascript"))?;
+            match Url::parse(url).and_then(|url| url.join(next.trim())) {
+                Ok(next) => Some(next.to_string()),
+                Err(err) => {
+                    warn!("resolve next album url {} of {} error: {:?}", next, url, err);
+                    None
+                }
+            }
+        }
+
+        // 链接为相对地址时传入 url_prefix 补全，地址无效的专辑被跳过
+        fn default_get_albums(&self, document: &Html, selector: Selector, name_path: &str, cover_path: &str, url_prefix: &str) -> Vec<Album> {
+            let name_path = self.album_selector.as_deref().unwrap_or(name_path);
+            let elements: Vec<ElementRef> = document.select(&selector).collect();
+            // 选择器失效时搜索结果为空，与没有搜索到专辑难以区分，记录日志便于排查
+            if elements.is_empty() {
+                warn!("album selector {:?} matched no elements, the page may have changed", selector);
+            } else if let Ok(link) = Selector::parse(name_path) {
+                if elements.iter().all(|element| element.select(&link).next().is_none()) {
+                    warn!("album link selector {} matched no elements in {} results", name_path, elements.len());
+                }
+            }
+
+            elements.into_iter().filter_map(|element| {
+                let (name, url) = self.default_get_name_and_url(element, name_path);
+                let cover = self.default_get_cover(element, cover_path);
+                let picture_count_hint = picture_count_hint(&element.text().collect::<String>());
+
+                match Album::new(name, format!("{}{}", url_prefix, url)) {
+                    Ok(album) => Some(Album { cover, picture_count_hint, ..album }),
+                    Err(err) => {
+                        warn!("skip album: {}", err);
+                        None
+                    }
+                }
+            }).collect()
+        }
+    }
+
+    // 专辑中的媒体资源，部分专辑在图片之外还有短视频
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum Media {
+        Image(String),
+        Video(String)
+    }
+
+    impl Media {
+        pub fn url(&self) -> &str {
+            match self {
+                Self::Image(url) | Self::Video(url) => url
+            }
+        }
+
+        pub fn into_url(self) -> String {
+            match self {
+                Self::Image(url) | Self::Video(url) => url
+            }
+        }
+
+        pub fn is_video(&self) -> bool {
+            matches!(self, Self::Video(_))
+        }
+    }
+
+    // 专辑分页地址的生成方式
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum PaginationScheme {
+        // 第 n 页为 xxx_n.html
+        SuffixUnderscore,
+        // 第 n 页为 xxx?参数名=n
+        QueryParam(String)
+    }
+
+    impl PaginationScheme {
+        // 专辑第 page 页的地址，第一页为专辑地址本身
+        pub fn page_url(&self, url: &str, page: usize) -> String {
+            if page <= 1 {
+                return url.to_string();
+            }
+
+            match self {
+                Self::SuffixUnderscore => {
+                    let base_url = url.strip_suffix(".html").unwrap_or(url);
+                    format!("{}_{}.html", base_url, page)
+                }
+                Self::QueryParam(name) => {
+                    let separator = if url.contains('?') { '&' } else { '?' };
+                    format!("{}{}{}={}", url, separator, name, page)
+                }
+            }
+        }
+    }
+
+    #[async_trait]
+    pub trait Parser: Send + Sync {
+
+        fn parser_code(&self) -> String;
+
+        fn parser_name(&self) -> String;
+
+        // 一句话介绍解析的站点
+        fn description(&self) -> &'static str;
+
+        // 站点首页地址
+        fn homepage(&self) -> &'static str;
+
+        fn client(&self) -> Client;
+
+        // 下载图片时使用的 User-Agent，None 表示使用默认请求头
+        fn user_agent(&self) -> Option<HeaderValue> {
+            None
+        }
+
+        // 下载图片时使用的认证信息
+        fn authorization(&self) -> Option<HeaderValue> {
+            None
+        }
+
+        // 下载图片时额外的请求头，如图片的 Accept 以及 Referer
+        fn picture_headers(&self) -> HeaderMap {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, HeaderValue::from_static(DEFAULT_PICTURE_ACCEPT));
+            headers
+        }
+
+        // 下载图片失败时的重试策略
+        fn retry_policy(&self) -> RetryPolicy {
+            RetryPolicy::default()
+        }
+
+        // 下载前检查专辑地址是否属于该站点，避免用错解析器时请求到无关的页面，默认接受任意 http(s) 地址
+        fn validate_url(&self, url: &str) -> Result<()> {
+            validate_url_host(url, &[])
+        }
+
+        // 是否允许访问 url，开启 robots.txt 检查时由解析器判断
+        async fn is_allowed(&self, _url: &str) -> bool {
+            true
+        }
+
+        fn parse_page_count(&self, document: &Html) -> Result<u32>;
+
+        // 搜索结果页面中是否有结果列表的容器，容器存在但为空时表示没有搜索到专辑，不存在时可能是页面结构变化导致解析失败
+        fn has_results_container(&self, _document: &Html) -> bool {
+            true
+        }
+
+        // 搜索关键字第 page 页（从 1 开始）请求的地址，不发出请求
+        fn search_url(&self, keyword: &str, page: u32) -> Result<String>;
+
+        async fn parse_albums(&self, keyword: String, page: u32, size: u32) -> Result<(Vec<Album>, u32)>;
+
+        // 站点列表页默认每页返回的专辑数
+        fn native_page_size(&self) -> u32;
+
+        // 搜索时允许请求的最大每页数量，不支持指定数量的站点与 native_page_size 相同
+        fn max_page_size(&self) -> u32 {
+            self.native_page_size()
+        }
+
+        // 请求 requested 个专辑时每页实际返回的数量：不能指定数量的站点固定使用站点的每页数量，
+        // 超过上限时使用上限，0 表示使用站点的每页数量
+        fn effective_page_size(&self, requested: u32) -> u32 {
+            let native = self.native_page_size().max(1);
+            let max = self.max_page_size().max(native);
+            if requested == 0 || max == native {
+                native
+            } else {
+                requested.min(max)
+            }
+        }
+
+        // 站点的分类列表 (代码, 名称)，不支持分类的站点返回空列表
+        async fn list_categories(&self) -> Result<Vec<(String, String)>> {
+            Ok(vec![])
+        }
+
+        async fn parse_albums_by_category(&self, category: String, _page: u32, _size: u32) -> Result<(Vec<Album>, u32)> {
+            Err(anyhow!("parser {} does not support categories: {}", self.parser_code(), category))
+        }
+
+        fn get_pagination(&self, html: &str) -> usize;
+
+        fn pagination_scheme(&self) -> PaginationScheme {
+            PaginationScheme::SuffixUnderscore
+        }
+
+        async fn get_page_pictures(&self, url: String) -> Result<Vec<String>>;
+
+        // 从本地保存的专辑页面中提取图片地址，不发出请求，用于离线解析和排查问题，相对地址以 base_url 补全
+        fn parse_pictures_from_html(&self, html: &str, base_url: &str) -> Result<Vec<String>>;
+
+        async fn get_all_pictures(&self, url: String) -> Result<Vec<String>>;
+
+        // 专辑中的图片和视频，默认只返回图片
+        async fn get_all_media(&self, url: String) -> Result<Vec<Media>> {
+            Ok(self.get_all_pictures(url).await?.into_iter().map(Media::Image).collect())
+        }
+
+        // 专辑第 page 个分页（从 1 开始）中的图片以及分页总数，不需要请求所有分页即可逐页显示
+        async fn get_album_page(&self, url: String, page: usize) -> Result<(Vec<String>, usize)> {
+            let pictures = if page == 1 {
+                self.get_all_pictures(url).await?
+            } else {
+                vec![]
+            };
+            Ok((pictures, 1))
+        }
+
+        fn get_picture_name(&self, url: &str) -> Result<String>;
+
+        // 专辑页面中指向同一系列下一个专辑的链接，可以是相对地址，不支持时返回 None
+        fn next_album_url(&self, _document: &Html) -> Option<String> {
+            None
+        }
+
+        // 获取专辑页面，返回系列中下一个专辑的完整地址
+        async fn next_album(&self, _url: String) -> Result<Option<String>> {
+            Ok(None)
+        }
+
+    }
+
+    #[derive(Clone)]
+    struct DiLi360Parser {
+        inner: InnerParser
+    }
+
+    impl DiLi360Parser {
+
+        const PARSER_CODE: &'static str = "DILI360";
+
+        const PARSER_NAME: &'static str = "中国地理";
+
+        const DESCRIPTION: &'static str = "中国国家地理图片专辑，通过百度站内搜索查找";
+
+        const HOMEPAGE: &'static str = "https://www.dili360.com";
+        const HOSTS: &'static [&'static str] = &["www.dili360.com", "dili360.com"];
+
+        const RESULTS_CONTAINER: &'static str = "#results";
+
+        // 地理 360 的搜索使用百度站内搜索
+        const BASE_URL: &'static str = "https://zhannei.baidu.com";
+
+        const DEFAULT_RESULT_COUNT: u32 = 10;
+
+        // 百度站内搜索 rn 参数的上限
+        const MAX_RESULT_COUNT: u32 = 50;
+
+        const PICTURE_SELECTOR: &'static str = ".imgbox>.img>img";
+
+        fn info() -> ParserInfo {
+            ParserInfo {
+                code: Self::PARSER_CODE,
+                name: Self::PARSER_NAME,
+                description: Self::DESCRIPTION,
+                homepage: Self::HOMEPAGE,
+                hosts: Self::HOSTS,
+                supported_features: ParserFeatures::SEARCH | ParserFeatures::PAGINATION | ParserFeatures::DIRECT_URL | ParserFeatures::COVER_IMAGE
+            }
+        }
+
+        fn new(base_url: &str, client: Option<Client>, client_config: ClientConfig) -> Self {
+            Self {
+                inner: InnerParser::new(base_url, client, client_config)
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Parser for DiLi360Parser {
+
+        fn parser_code(&self) -> String {
+            DiLi360Parser::PARSER_CODE.to_string()
+        }
+
+        fn parser_name(&self) -> String {
+            DiLi360Parser::PARSER_NAME.to_string()
+        }
+
+        fn description(&self) -> &'static str {
+            DiLi360Parser::DESCRIPTION
+        }
+
+        fn homepage(&self) -> &'static str {
+            DiLi360Parser::HOMEPAGE
+        }
+
+        fn client(&self) -> Client {
+            self.inner.fetcher.client().clone()
+        }
+
+        fn user_agent(&self) -> Option<HeaderValue> {
+            self.inner.user_agent()
+        }
+
+        fn authorization(&self) -> Option<HeaderValue> {
+            self.inner.authorization()
+        }
+
+        fn picture_headers(&self) -> HeaderMap {
+            self.inner.picture_headers()
+        }
+
+        fn retry_policy(&self) -> RetryPolicy {
+            self.inner.client_config.retry_policy
+        }
+
+        fn validate_url(&self, url: &str) -> Result<()> {
+            self.inner.validate_url(url, Self::HOSTS, Self::BASE_URL)
+        }
+
+        async fn is_allowed(&self, url: &str) -> bool {
+            self.inner.is_allowed(url).await
+        }
+
+        fn has_results_container(&self, document: &Html) -> bool {
+            document.select(&Selector::parse(Self::RESULTS_CONTAINER).unwrap()).next().is_some()
+        }
+
+        fn parse_page_count(&self, document: &Html) -> Result<u32> {
+            let selector = Selector::parse("#pageFooter .pager-normal-foot").map_err(|err| {
+                anyhow!("parse selector error: {err:?}")
+            })?;
+
+            let last_element = document.select(&selector).last();
+            if last_element.is_none() {
+                return Err(anyhow!("parse page count error: not found page element"));
+            }
+
+            let element = last_element.unwrap();
+            let text = element.text().next();
+            if text.is_none() {
+                return Err(anyhow!("parse page count error: not found page text"));
+            }
+
+            let text = text.unwrap();
+            let page_count = text.parse::<u32>().map_err(|e| {
+                anyhow!("parse page count error: {e:?}")
+            })?;
+            Ok(page_count)
+        }
+
+        fn search_url(&self, keyword: &str, page: u32) -> Result<String> {
+            // 地理 360 搜索结果页面从 0 开始，页码为 0 时按第一页处理
+            Ok(format!("{}/cse/site?q={}&p={}&nsid=&cc=www.dili360.com", &self.inner.base_url, encode_keyword(keyword), page.saturating_sub(1)))
+        }
+
+        fn native_page_size(&self) -> u32 {
+            Self::DEFAULT_RESULT_COUNT
+        }
+
+        fn max_page_size(&self) -> u32 {
+            Self::MAX_RESULT_COUNT
+        }
+
+        async fn parse_albums(&self, keyword: String, page: u32, size: u32) -> Result<(Vec<Album>, u32)> {
+            let mut url = self.search_url(&keyword, page)?;
+            // 百度站内搜索默认每页 10 条，其它数量需要通过 rn 参数指定
+            if size != Self::DEFAULT_RESULT_COUNT {
+                url.push_str(&format!("&rn={}", size));
+            }
+            let (html, headers) = self.inner.get_url_content(&url, None, None).await?;
+            let document = Html::parse_document(&html);
+            let selector = Selector::parse("#results>.result").map_err(|err| {
+                anyhow!("parse selector error: {err:?}")
+            })?;
+            let albums = self.inner.default_get_albums(&document, selector, "h3>a", "div>.c-image img", "");
+            if albums.is_empty() && !self.has_results_container(&document) {
+                return Err(DownloaderError::MarkupChanged { url, selector: Self::RESULTS_CONTAINER.to_string() }.into());
+            }
+            let page_count = if self.inner.page_count == 0 {
+                // 只有一页结果时没有分页，至少有当前页
+                let page_count = match page_count_from_headers(&headers, size) {
+                    Some(page_count) => Ok(page_count),
+                    None => self.parse_page_count(&document)
+                };
+                page_count.unwrap_or_else(|err| {
+                    warn!("{:?}, use current page {} as page count", err, page);
+                    if albums.is_empty() { 0 } else { page }
+                })
+            } else {
+                self.inner.page_count
+            };
+
+            Ok((albums, page_count))
+        }
+
+        fn get_pagination(&self, html: &str) -> usize {
+            1
+        }
+
+        async fn get_page_pictures(&self, url: String) -> Result<Vec<String>> {
+            self.inner.get_page_pictures(url, Self::PICTURE_SELECTOR, None, None).await
+        }
+
+        fn parse_pictures_from_html(&self, html: &str, base_url: &str) -> Result<Vec<String>> {
+            let pictures = self.inner.parse_pictures_from_html(html, Self::PICTURE_SELECTOR, base_url)?;
+            Ok(pictures.into_iter().map(|picture| picture.split("@").next().unwrap_or("").to_string()).collect())
+        }
+
+        async fn get_all_pictures(&self, url: String) -> Result<Vec<String>> {
+            let pictures = self.get_page_pictures(url).await?;
+            let pictures = pictures.into_iter().map(|picture| {
+                picture.split("@").next().unwrap_or("").to_string()
+            }).collect();
+            Ok(pictures)
+        }
+
+        // 视频排在图片之后
+        async fn get_all_media(&self, url: String) -> Result<Vec<Media>> {
+            let (pictures, videos) = self.inner.get_page_media(url, Self::PICTURE_SELECTOR, None, None).await?;
+            let pictures = pictures.into_iter().map(|picture| Media::Image(picture.split("@").next().unwrap_or("").to_string()));
+            Ok(pictures.chain(videos.into_iter().map(Media::Video)).collect())
+        }
+
+        fn get_picture_name(&self,  url: &str) -> Result<String> {
+            self.inner.get_picture_name(url)
+        }
+
+    }
+
+    #[derive(Clone)]
+    struct SFTKParser {
+        inner: InnerParser
+    }
+
+    impl SFTKParser {
+
+        const PARSER_CODE: &'static str = "SFTK";
+
+        const PARSER_NAME: &'static str = "私房图库";
+
+        const DESCRIPTION: &'static str = "私房图库风景图片，关键字按拼音匹配栏目";
+
+        const BASE_URL: &'static str = "http://www.sftuku.com";
+        const HOSTS: &'static [&'static str] = &["www.sftuku.com", "sftuku.com"];
+
+        const RESULTS_CONTAINER: &'static str = "#list";
+
+        // 列表页固定每页 8 个专辑，不能指定数量
+        const PAGE_SIZE: u32 = 8;
+
+        const PICTURE_SELECTOR: &'static str = "#picg>.slide>a>img";
+
+        fn info() -> ParserInfo {
+            ParserInfo {
+                code: Self::PARSER_CODE,
+                name: Self::PARSER_NAME,
+                description: Self::DESCRIPTION,
+                homepage: Self::BASE_URL,
+                hosts: Self::HOSTS,
+                supported_features: ParserFeatures::all()
+            }
+        }
+
+        fn new(base_url: &str, client: Option<Client>, client_config: ClientConfig) -> Self {
+            Self {
+                inner: InnerParser::new(base_url, client, client_config)
+            }
+        }
+
+        // 拼音作为路径的一段，去掉关键字中的空白，其它字符编码后保留
+        fn keyword_to_pinyin(keyword: &str) -> String {
+            let pinyin: String = normalize_keyword(keyword).chars()
+                .filter(|c| !c.is_whitespace())
+                .map(|c| c.to_pinyin().map(|p| p.plain().to_string()).unwrap_or(c.to_string()))
+                .collect::<Vec<String>>()
+                .join("");
+            utf8_percent_encode(&pinyin, KEYWORD_ENCODE_SET).to_string()
+        }
+
+        fn default_headers() -> HeaderMap {
+            let mut default_headers = HeaderMap::new();
+            default_headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("zh-CN,zh-Hans;q=0.9"));
+            default_headers.insert(header::HOST, HeaderValue::from_static("www.sftuku.com"));
+            default_headers
+        }
+
+        // 按 xxx_n.html 生成分页地址时专辑地址必须以 .html 结尾，否则得到的分页地址都是错误的
+        fn check_album_url(&self, url: &str) -> Result<()> {
+            if self.pagination_scheme() == PaginationScheme::SuffixUnderscore && url.strip_suffix(".html").is_none() {
+                return Err(DownloaderError::Parse { url: url.to_string(), detail: "expected .html suffix".to_string() }.into());
+            }
+            Ok(())
+        }
+
+        // 搜索结果和分类列表页面结构相同，每页数量固定，总页数按站点实际的每页数量计算
+        async fn parse_album_list(&self, url: &str) -> Result<(Vec<Album>, u32)> {
+            let (html, headers) = self.inner.get_url_content(url, Some("GBK".to_string()), Some(Self::default_headers())).await?;
+            // 只记录地址，页面内容可以通过 debug_html_dir 保存
+            trace!(url = %url, "fetched HTML for album search");
+            let document = Html::parse_document(&html);
+            let selector = Selector::parse("#list>ul>li").map_err(|err| {
+                anyhow!("parse selector error: {err:?}")
+            })?;
+            let albums = self.inner.default_get_albums(&document, selector, ".Title>a", "a>img", &self.inner.base_url);
+            if albums.is_empty() && !self.has_results_container(&document) {
+                return Err(DownloaderError::MarkupChanged { url: url.to_string(), selector: Self::RESULTS_CONTAINER.to_string() }.into());
+            }
             let page_count = if self.inner.page_count == 0 {
-                self.parse_page_count(&document)?
+                match page_count_from_headers(&headers, Self::PAGE_SIZE) {
+                    Some(page_count) => page_count,
+                    None => self.parse_page_count(&document)?
+                }
             } else {
                 self.inner.page_count
             };
 
-            Ok((albums, page_count))
-        }
+            Ok((albums, page_count))
+        }
+    }
+
+    #[async_trait]
+    impl Parser for SFTKParser {
+
+        fn parser_code(&self) -> String {
+            SFTKParser::PARSER_CODE.to_string()
+        }
+
+        fn parser_name(&self) -> String {
+            SFTKParser::PARSER_NAME.to_string()
+        }
+
+        fn description(&self) -> &'static str {
+            SFTKParser::DESCRIPTION
+        }
+
+        fn homepage(&self) -> &'static str {
+            SFTKParser::BASE_URL
+        }
+
+        fn client(&self) -> Client {
+            self.inner.fetcher.client().clone()
+        }
+
+        fn user_agent(&self) -> Option<HeaderValue> {
+            self.inner.user_agent()
+        }
+
+        fn authorization(&self) -> Option<HeaderValue> {
+            self.inner.authorization()
+        }
+
+        fn picture_headers(&self) -> HeaderMap {
+            self.inner.picture_headers()
+        }
+
+        fn retry_policy(&self) -> RetryPolicy {
+            self.inner.client_config.retry_policy
+        }
+
+        fn validate_url(&self, url: &str) -> Result<()> {
+            self.inner.validate_url(url, Self::HOSTS, Self::BASE_URL)
+        }
+
+        async fn is_allowed(&self, url: &str) -> bool {
+            self.inner.is_allowed(url).await
+        }
+
+        fn has_results_container(&self, document: &Html) -> bool {
+            document.select(&Selector::parse(Self::RESULTS_CONTAINER).unwrap()).next().is_some()
+        }
+
+        fn parse_page_count(&self, document: &Html) -> Result<u32> {
+            let selector = Selector::parse(".pagelist a").map_err(|err| {
+                anyhow!("parse selector error: {err:?}")
+            })?;
+
+            // 分页链接中最后一个数字链接即为最后一页，首页、上一页、尾页等文字链接跳过
+            let last_page = document.select(&selector)
+                .filter_map(|element| element.text().collect::<String>().trim().parse::<u32>().ok())
+                .next_back();
+            // 只有一页时没有分页链接
+            Ok(last_page.unwrap_or(1).max(1))
+        }
+
+        fn search_url(&self, keyword: &str, page: u32) -> Result<String> {
+            let pinyin = Self::keyword_to_pinyin(keyword);
+            Ok(format!("{}/chis/{}/{}.html", &self.inner.base_url, &pinyin, page.max(1)))
+        }
+
+        fn native_page_size(&self) -> u32 {
+            Self::PAGE_SIZE
+        }
+
+        async fn parse_albums(&self, keyword: String, page: u32, _size: u32) -> Result<(Vec<Album>, u32)> {
+            let url = self.search_url(&keyword, page)?;
+            self.parse_album_list(&url).await
+        }
+
+        async fn list_categories(&self) -> Result<Vec<(String, String)>> {
+            let url = format!("{}/", &self.inner.base_url);
+            let (html, _) = self.inner.get_url_content(&url, Some("GBK".to_string()), Some(Self::default_headers())).await?;
+            let document = Html::parse_document(&html);
+            let selector = Selector::parse("#nav a").map_err(|err| {
+                anyhow!("parse selector error: {err:?}")
+            })?;
+
+            // 分类链接形如 /xinggan/，取路径作为分类代码，首页等链接跳过
+            let categories = document.select(&selector).filter_map(|element| {
+                let href = element.value().attr("href")?;
+                let code = href.trim_matches('/');
+                if code.is_empty() || code.contains('/') || code.contains('.') {
+                    return None;
+                }
+
+                let name = element.text().collect::<String>().trim().to_string();
+                Some((code.to_string(), name))
+            }).collect();
+            Ok(categories)
+        }
+
+        async fn parse_albums_by_category(&self, category: String, page: u32, _size: u32) -> Result<(Vec<Album>, u32)> {
+            let url = format!("{}/{}/{}.html", &self.inner.base_url, category.to_lowercase(), page);
+            self.parse_album_list(&url).await
+        }
+
+        fn pagination_scheme(&self) -> PaginationScheme {
+            self.inner.pagination_scheme.clone()
+        }
+
+        fn get_pagination(&self, html: &str) -> usize {
+            let ret = Selector::parse(".pagelist>a");
+            if ret.is_err() {
+                error!("parse selector error: {:?}", ret.err());
+                return 0;
+            }
+
+            let selector = ret.unwrap();
+            let document = Html::parse_document(&html);
+            let elements: Vec<ElementRef> = document.select(&selector).into_iter().collect();
+            elements.len()
+        }
+
+        async fn get_page_pictures(&self, url: String) -> Result<Vec<String>> {
+            self.inner.get_page_pictures(url, Self::PICTURE_SELECTOR, Some("GBK".to_string()), Some(Self::default_headers())).await
+        }
+
+        fn parse_pictures_from_html(&self, html: &str, base_url: &str) -> Result<Vec<String>> {
+            self.inner.parse_pictures_from_html(html, Self::PICTURE_SELECTOR, base_url)
+        }
+
+        async fn get_all_pictures(&self, url: String) -> Result<Vec<String>> {
+            self.check_album_url(&url)?;
+            let (html, _) = self.inner.get_url_content(&url, Some("GBK".to_string()), Some(Self::default_headers())).await?;
+            let page_count = self.get_pagination(&html);
+            let scheme = self.pagination_scheme();
+            // 并发请求所有分页，并发数与图片下载一致
+            let semaphore = Arc::new(Semaphore::new(DEFAULT_CONCURRENCY));
+            let mut tasks = JoinSet::new();
+            for i in 1..=page_count {
+                let page_url = scheme.page_url(&url, i);
+                let parser = self.clone();
+                let semaphore = semaphore.clone();
+                tasks.spawn(async move {
+                    let _permit = semaphore.acquire_owned().await?;
+                    let pictures = parser.get_page_pictures(page_url).await?;
+                    Ok::<(usize, Vec<String>), anyhow::Error>((i, pictures))
+                });
+            }
+
+            let mut pages = Vec::with_capacity(page_count);
+            while let Some(ret) = tasks.join_next().await {
+                pages.push(ret??);
+            }
+
+            // 按分页顺序合并，保持图片在专辑中的顺序
+            pages.sort_by_key(|(i, _)| *i);
+            Ok(pages.into_iter().flat_map(|(_, pictures)| pictures).collect())
+        }
+
+        async fn get_album_page(&self, url: String, page: usize) -> Result<(Vec<String>, usize)> {
+            self.check_album_url(&url)?;
+            let (html, _) = self.inner.get_url_content(&url, Some("GBK".to_string()), Some(Self::default_headers())).await?;
+            let page_count = self.get_pagination(&html);
+            if page == 0 || page > page_count {
+                return Ok((vec![], page_count));
+            }
+
+            let pictures = self.get_page_pictures(self.pagination_scheme().page_url(&url, page)).await?;
+            Ok((pictures, page_count))
+        }
+
+        fn get_picture_name(&self, url: &str) -> Result<String> {
+            self.inner.get_picture_name(url)
+        }
+
+        fn next_album_url(&self, document: &Html) -> Option<String> {
+            self.inner.default_next_album_url(document)
+        }
+
+        async fn next_album(&self, url: String) -> Result<Option<String>> {
+            let (html, _) = self.inner.get_url_content(&url, Some("GBK".to_string()), Some(Self::default_headers())).await?;
+            let next = self.next_album_url(&Html::parse_document(&html));
+            Ok(self.inner.resolve_next_album(&url, next))
+        }
+    }
+
+    // 除了 RFC 3986 中的非保留字符，关键字中的其它字符都需要编码
+    const KEYWORD_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+    // 关键字可能是从浏览器地址栏复制的已编码文本，先解码再去掉首尾空白，避免重复编码
+    fn normalize_keyword(keyword: &str) -> String {
+        percent_decode_str(keyword).decode_utf8_lossy().trim().to_string()
+    }
+
+    fn encode_keyword(keyword: &str) -> String {
+        utf8_percent_encode(&normalize_keyword(keyword), KEYWORD_ENCODE_SET).to_string()
+    }
+
+    // hosts 为空时只检查是否为 http(s) 地址
+    fn validate_url_host(url: &str, hosts: &[String]) -> Result<()> {
+        let invalid = |reason: String| DownloaderError::InvalidUrl { url: url.to_string(), reason };
+        let parsed = Url::parse(url).map_err(|err| invalid(err.to_string()))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(invalid(format!("unsupported scheme {}", parsed.scheme())).into());
+        }
+        let host = parsed.host_str().unwrap_or("");
+        if host.is_empty() {
+            return Err(invalid("missing host".to_string()).into());
+        }
+        if !hosts.is_empty() && !hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host)) {
+            return Err(invalid(format!("host {} is not {}", host, hosts.join(" or "))).into());
+        }
+        Ok(())
+    }
+
+    // 响应头 X-Total-Count 给出结果总数时直接计算总页数，不用再解析页面
+    fn page_count_from_headers(headers: &HeaderMap, size: u32) -> Option<u32> {
+        if size == 0 {
+            return None;
+        }
+
+        let total = headers.get("x-total-count")?.to_str().ok()?.trim().parse::<u32>().ok()?;
+        Some(total.div_ceil(size))
+    }
+
+    // 页面保存为 <地址哈希>.html，同一地址覆盖之前保存的页面，保存失败不影响解析
+    async fn save_debug_html(dir: &Path, url: &str, content: &str) {
+        let path = dir.join(format!("{}.html", short_hash(url)));
+        let ret = match tokio::fs::create_dir_all(dir).await {
+            Ok(_) => tokio::fs::write(&path, content).await,
+            Err(err) => Err(err)
+        };
+        match ret {
+            Ok(_) => info!("saved html of {} to {:?}", url, path),
+            Err(err) => warn!("save html of {} to {:?} error: {:?}", url, path, err)
+        }
+    }
+
+    // 选出 srcset 中分辨率最高的地址，没有描述符的候选项视为 1x
+    fn best_srcset_candidate(srcset: &str) -> Option<&str> {
+        srcset.split(',').filter_map(|candidate| {
+            let mut parts = candidate.split_whitespace();
+            let url = parts.next()?;
+            let size = parts.next().and_then(|descriptor| {
+                descriptor.strip_suffix('w').or(descriptor.strip_suffix('x'))?.parse::<f64>().ok()
+            }).unwrap_or(1.0);
+            Some((url, size))
+        }).fold(None, |best: Option<(&str, f64)>, (url, size)| {
+            match best {
+                Some((_, best_size)) if best_size >= size => best,
+                _ => Some((url, size))
+            }
+        }).map(|(url, _)| url)
+    }
+
+    // 在页面源码中匹配图片地址，有捕获组时取第一个捕获组，否则取整个匹配
+    // JSON 中的地址可能转义了斜杠，如 https:\/\/example.com\/01.jpg
+    fn inline_pictures(html: &str, pattern: &Regex) -> Vec<String> {
+        let pictures: IndexSet<String> = pattern.captures_iter(html).filter_map(|captures| {
+            let url = captures.get(1).or(captures.get(0))?.as_str().trim().replace("\\/", "/");
+            (url.len() >= MIN_PICTURE_URL_LEN && !url.starts_with("data:")).then_some(url)
+        }).collect();
+        pictures.into_iter().collect()
+    }
+
+    pub struct ParserBuilder {
+        parser_code: String,
+        base_url: Option<String>,
+        client: Option<Client>,
+        client_config: ClientConfig,
+        pagination_scheme: Option<PaginationScheme>,
+        picture_selector: Option<String>,
+        picture_attributes: Option<Vec<String>>,
+        album_selector: Option<String>,
+        picture_pattern: Option<String>
+    }
+
+    impl ParserBuilder {
+        pub fn new(parser_code: &str) -> Self {
+            Self {
+                parser_code: parser_code.to_string(),
+                base_url: None,
+                client: None,
+                client_config: ClientConfig::default(),
+                pagination_scheme: None,
+                picture_selector: None,
+                picture_attributes: None,
+                album_selector: None,
+                picture_pattern: None
+            }
+        }
+
+        pub fn client(mut self, client: Client) -> Self {
+            self.client = Some(client);
+            self
+        }
+
+        pub fn client_config(mut self, client_config: ClientConfig) -> Self {
+            self.client_config = client_config;
+            self
+        }
+
+        pub fn auth(mut self, auth: Auth) -> Self {
+            self.client_config.auth = Some(auth);
+            self
+        }
+
+        // 将获取的页面 HTML 保存到 dir，用于排查解析问题
+        pub fn debug_html_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+            self.client_config.debug_html_dir = Some(dir.as_ref().to_path_buf());
+            self
+        }
+
+        // 专辑分页地址的生成方式，不设置时使用解析器默认的方式
+        pub fn pagination_scheme(mut self, scheme: PaginationScheme) -> Self {
+            self.pagination_scheme = Some(scheme);
+            self
+        }
+
+        // 专辑页面中图片的 CSS 选择器，不设置时使用解析器内置的选择器
+        pub fn picture_selector(mut self, selector: &str) -> Self {
+            self.picture_selector = Some(selector.to_string());
+            self
+        }
+
+        // 搜索结果中专辑链接的 CSS 选择器，相对于每个搜索结果，不设置时使用解析器内置的选择器
+        pub fn album_selector(mut self, selector: &str) -> Self {
+            self.album_selector = Some(selector.to_string());
+            self
+        }
+
+        // 图片选择器没有匹配到图片时，用该正则在页面源码中查找图片地址，如 var imgs = [...] 中的地址
+        pub fn picture_pattern(mut self, pattern: &str) -> Self {
+            self.picture_pattern = Some(pattern.to_string());
+            self
+        }
+
+        // 按优先级读取图片地址的属性，不设置时使用 DEFAULT_PICTURE_ATTRIBUTES
+        pub fn picture_attributes(mut self, attributes: &[&str]) -> Self {
+            self.picture_attributes = Some(attributes.iter().map(|attr| attr.to_string()).collect());
+            self
+        }
+
+        // 替换站点地址，测试时可指向本地的模拟服务
+        pub fn base_url(mut self, base_url: &str) -> Self {
+            self.base_url = Some(base_url.to_string());
+            self
+        }
+
+        pub fn build(self) -> Result<Arc<dyn Parser>> {
+            if let Some(selector) = &self.picture_selector {
+                Selector::parse(selector).map_err(|err| anyhow!("图片选择器格式错误: {}, {:?}", selector, err))?;
+            }
+            if let Some(selector) = &self.album_selector {
+                Selector::parse(selector).map_err(|err| anyhow!("专辑选择器格式错误: {}, {:?}", selector, err))?;
+            }
+            let picture_pattern = match &self.picture_pattern {
+                Some(pattern) => Some(Regex::new(pattern).map_err(|err| anyhow!("图片地址正则格式错误: {}, {:?}", pattern, err))?),
+                None => None
+            };
+
+            let base_url = self.base_url.as_deref();
+            // 没有指定共用的 Client 时按 TLS 配置创建
+            let client = match self.client {
+                Some(client) => client,
+                None => self.client_config.build_client()?
+            };
+            match self.parser_code.to_uppercase().as_str() {
+                DiLi360Parser::PARSER_CODE => {
+                    let mut parser = DiLi360Parser::new(base_url.unwrap_or(DiLi360Parser::BASE_URL), Some(client), self.client_config);
+                    parser.inner.picture_selector = self.picture_selector;
+                    parser.inner.album_selector = self.album_selector;
+                    parser.inner.picture_pattern = picture_pattern;
+                    if let Some(attributes) = self.picture_attributes {
+                        parser.inner.picture_attributes = attributes;
+                    }
+                    Ok(Arc::new(parser))
+                }
+                SFTKParser::PARSER_CODE => {
+                    let mut parser = SFTKParser::new(base_url.unwrap_or(SFTKParser::BASE_URL), Some(client), self.client_config);
+                    if let Some(scheme) = self.pagination_scheme {
+                        parser.inner.pagination_scheme = scheme;
+                    }
+                    parser.inner.picture_selector = self.picture_selector;
+                    parser.inner.album_selector = self.album_selector;
+                    parser.inner.picture_pattern = picture_pattern;
+                    if let Some(attributes) = self.picture_attributes {
+                        parser.inner.picture_attributes = attributes;
+                    }
+                    Ok(Arc::new(parser))
+                }
+                _ => Err(DownloaderError::UnsupportedParser {
+                    code: self.parser_code,
+                    available: parsers().iter().map(|info| info.code).collect()
+                }.into())
+            }
+        }
+    }
+
+    pub fn parse(parser_code: &str, client: Option<Client>) -> Result<Arc<dyn Parser>> {
+        let builder = ParserBuilder::new(parser_code);
+        match client {
+            Some(client) => builder.client(client).build(),
+            None => builder.build()
+        }
+    }
+
+    // 注册表中的第一个解析器即为默认解析器
+    pub fn default_parser_code() -> &'static str {
+        parsers()[0].code
+    }
+
+    pub fn default_parser() -> Arc<dyn Parser> {
+        parse(default_parser_code(), None).expect("default parser must be registered")
+    }
+
+    bitflags! {
+        // 解析器支持的功能
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+        #[serde(transparent)]
+        pub struct ParserFeatures: u8 {
+            // 按关键字搜索专辑
+            const SEARCH = 1;
+            // 搜索结果分页
+            const PAGINATION = 1 << 1;
+            // 直接通过专辑地址获取图片
+            const DIRECT_URL = 1 << 2;
+            // 搜索结果带有专辑封面
+            const COVER_IMAGE = 1 << 3;
+            // 按分类浏览专辑
+            const CATEGORIES = 1 << 4;
+            // 跟随专辑页面中的 "下一篇" 链接下载整个系列
+            const SERIES = 1 << 5;
+        }
+    }
+
+    #[derive(Clone, Debug, Serialize)]
+    pub struct ParserInfo {
+        pub code: &'static str,
+        pub name: &'static str,
+        pub description: &'static str,
+        pub homepage: &'static str,
+        // 站点的域名，用于根据专辑地址选择解析器
+        pub hosts: &'static [&'static str],
+        pub supported_features: ParserFeatures
+    }
+
+    pub fn parsers() -> Vec<ParserInfo> {
+        vec![DiLi360Parser::info(), SFTKParser::info()]
+    }
+
+    // 根据地址的域名找到对应的解析器代码，不属于任何已知站点时返回 None
+    pub fn parser_code_for_url(url: &str) -> Option<&'static str> {
+        let url = Url::parse(url).ok()?;
+        let host = url.host_str()?;
+        parsers().into_iter()
+            .find(|info| info.hosts.iter().any(|known| known.eq_ignore_ascii_case(host)))
+            .map(|info| info.code)
+    }
+
+    // 直接粘贴专辑地址下载时，根据域名自动选择解析器
+    pub fn parser_for_url(url: &str) -> Option<Arc<dyn Parser>> {
+        parse(parser_code_for_url(url)?, None).ok()
+    }
+
+}
+
+// 搜索结果的磁盘缓存，每页保存为一个 JSON 文件，重启后不需要重新请求，超过 ttl 的缓存视为失效
+#[derive(Clone, Debug)]
+pub struct AlbumCache {
+    dir: PathBuf,
+    ttl: Duration
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedPage {
+    // 文件名是键的哈希，读取时比较完整的键避免冲突
+    key: String,
+    // 保存时间，自 UNIX 纪元起的秒数
+    saved_at: u64,
+    page_count: u32,
+    albums: Vec<Album>
+}
+
+impl AlbumCache {
+
+    pub const DEFAULT_DIR: &'static str = "./cache/albums/";
+
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+    pub fn new(dir: impl AsRef<Path>, ttl: Duration) -> Self {
+        Self { dir: dir.as_ref().to_path_buf(), ttl }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", short_hash(key)))
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |time| time.as_secs())
+    }
+
+    // 没有缓存、缓存已过期或者无法读取时返回 None
+    async fn get(&self, key: &str) -> Option<AlbumPage> {
+        let path = self.path(key);
+        let content = match tokio::fs::read(&path).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!("read album cache {} error: {:?}", path.display(), err);
+                return None;
+            }
+        };
+        let cached: CachedPage = match serde_json::from_slice(&content) {
+            Ok(cached) => cached,
+            Err(err) => {
+                warn!("parse album cache {} error: {:?}", path.display(), err);
+                return None;
+            }
+        };
+        if cached.key != key || Self::now().saturating_sub(cached.saved_at) >= self.ttl.as_secs() {
+            debug!("album cache {} is stale or belongs to another query", path.display());
+            return None;
+        }
+        debug!("read {} from album cache {}", key, path.display());
+        Some((cached.albums, cached.page_count))
+    }
+
+    // 写入失败只记录日志，不影响搜索，空结果不缓存
+    async fn put(&self, key: &str, page: &AlbumPage) {
+        if page.0.is_empty() {
+            return;
+        }
+
+        let cached = CachedPage { key: key.to_string(), saved_at: Self::now(), page_count: page.1, albums: page.0.clone() };
+        let path = self.path(key);
+        let ret = async {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(&path, serde_json::to_vec(&cached)?).await?;
+            anyhow::Ok(())
+        }.await;
+        if let Err(err) = ret {
+            warn!("write album cache {} error: {:?}", path.display(), err);
+        }
+    }
+}
+
+pub struct AlbumSearcher {
+    parser: Arc<dyn Parser>,
+    // 创建时记录解析器的代码和名称，便于直接借出
+    parser_code: String,
+    parser_name: String,
+    page: u32,
+    page_count: u32,
+    // 翻页限制在 [start_page, end_page] 之间，end_page 为 None 时没有上限
+    start_page: u32,
+    end_page: Option<u32>,
+    size: u32,
+    keyword: String,
+    // 按分类浏览时为分类代码，此时 keyword 不参与查询
+    category: Option<String>,
+    download_root: PathBuf,
+    download_config: DownloadConfig,
+    // 最近一次下载有失败图片的专辑、保存目录以及下载结果
+    last_failed: Option<(Arc<Album>, PathBuf, DownloadResult)>,
+    albums: LruCache<String, Vec<Album>>,
+    // 内存缓存之后的第二级缓存，为 None 时不使用磁盘缓存
+    album_cache: Option<AlbumCache>,
+    // 翻页后在后台预取相邻的分页，同一时间只保留最近一次的预取
+    prefetch_enabled: bool,
+    prefetch: Option<Prefetch>
+}
+
+// 一页的专辑以及分页总数
+type AlbumPage = (Vec<Album>, u32);
+
+// 后台预取的分页，每获取完一页 done 加一，results 中为已结束的分页，获取失败时为 None
+struct Prefetch {
+    pages: Vec<u32>,
+    results: Arc<Mutex<HashMap<u32, Option<AlbumPage>>>>,
+    done: watch::Receiver<usize>
+}
+
+// LruCache 没有实现 Clone，这里按从旧到新的顺序把缓存逐条复制到新缓存中，克隆出的搜索器拥有独立的缓存。
+// 没有改用 Arc<Mutex<LruCache>> 共享缓存，是因为 AlbumResult 直接返回缓存中数据的引用，加锁后无法再借出。
+impl Clone for AlbumSearcher {
+    fn clone(&self) -> Self {
+        let mut albums = LruCache::new(self.albums.cap());
+        for (key, value) in self.albums.iter().rev() {
+            albums.push(key.clone(), value.clone());
+        }
+
+        Self {
+            parser: self.parser.clone(),
+            parser_code: self.parser_code.clone(),
+            parser_name: self.parser_name.clone(),
+            page: self.page,
+            page_count: self.page_count,
+            start_page: self.start_page,
+            end_page: self.end_page,
+            size: self.size,
+            keyword: self.keyword.clone(),
+            category: self.category.clone(),
+            download_root: self.download_root.clone(),
+            download_config: self.download_config.clone(),
+            last_failed: self.last_failed.clone(),
+            albums,
+            album_cache: self.album_cache.clone(),
+            prefetch_enabled: self.prefetch_enabled,
+            prefetch: None
+        }
+    }
+}
+
+impl std::fmt::Display for AlbumSearcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Page {}/{} | Keyword: {} | Parser: {}", self.page, self.page_count, self.keyword, self.parser_name)
+    }
+}
+
+impl AlbumSearcher {
+
+    pub const DEFAULT_PAGE_SIZE: u32 = 10u32;
+
+    pub const DEFAULT_DOWNLOAD_ROOT: &'static str = "./albums/";
+
+    // 并发获取分页时每批的页数
+    pub const PREFETCH_BATCH_SIZE: usize = 5;
+
+    // 第一次获取数据后预先获取的后续页数
+    const INITIAL_PREFETCH_PAGES: u32 = 3;
+
+    pub fn new(parser: Arc<dyn Parser>, keyword: &str, size: u32) -> Self {
+        // 每页数量按站点的限制调整，与解析器实际返回的数量一致
+        let size = parser.effective_page_size(size);
+        Self {
+            parser_code: parser.parser_code(),
+            parser_name: parser.parser_name(),
+            parser,
+            page: 0,
+            page_count: 0,
+            start_page: 1,
+            end_page: None,
+            size,
+            keyword: keyword.to_string(),
+            category: None,
+            download_root: PathBuf::from(Self::DEFAULT_DOWNLOAD_ROOT),
+            download_config: DownloadConfig::default(),
+            last_failed: None,
+            albums: LruCache::new(NonZeroUsize::new(64).unwrap()),
+            album_cache: None,
+            prefetch_enabled: true,
+            prefetch: None
+        }
+    }
+
+    // 只在指定的页码范围内翻页，便于多个任务分段处理同一个搜索
+    pub fn new_with_range(parser: Arc<dyn Parser>, keyword: &str, size: u32, start_page: u32, end_page: Option<u32>) -> Self {
+        let mut searcher = Self::new(parser, keyword, size);
+        searcher.set_page_range(start_page, end_page);
+        searcher
+    }
+
+    // 浏览指定分类下的专辑，分页等操作与关键字搜索相同
+    pub fn with_category(parser: Arc<dyn Parser>, category: &str, size: u32) -> Self {
+        let mut searcher = Self::new(parser, "", size);
+        searcher.category = Some(category.to_string());
+        searcher
+    }
+
+    pub fn page(&self) -> u32 {
+        self.page
+    }
+
+    pub fn page_count(&self) -> u32 {
+        self.page_count
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn page_range(&self) -> (u32, Option<u32>) {
+        (self.start_page, self.end_page)
+    }
+
+    // 修改页码范围后重新从起始页开始翻页，已缓存的分页仍然有效
+    pub fn set_page_range(&mut self, start_page: u32, end_page: Option<u32>) {
+        self.start_page = start_page.max(1);
+        self.end_page = end_page.map(|end| end.max(self.start_page));
+        self.page = 0;
+        self.prefetch = None;
+    }
+
+    // 页码范围内的最后一页，总页数未知时为 0
+    pub fn last_page(&self) -> u32 {
+        self.end_page.map_or(self.page_count, |end| self.page_count.min(end))
+    }
+
+    // 起始页超过总页数时停留在起始页
+    fn clamp_page(&self, page: u32) -> u32 {
+        page.min(self.last_page()).max(self.start_page)
+    }
+
+    pub fn current_keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    pub fn current_parser_code(&self) -> &str {
+        &self.parser_code
+    }
+
+    pub fn current_parser_name(&self) -> &str {
+        &self.parser_name
+    }
+
+    pub fn category(&self) -> Option<&str> {
+        self.category.as_deref()
+    }
+
+    // 当前页的搜索地址，尚未翻页时为第一页，按分类浏览时没有搜索地址
+    pub fn search_url(&self) -> Result<String> {
+        if let Some(category) = &self.category {
+            return Err(anyhow!("browsing category {} has no search url", category));
+        }
+        self.parser.search_url(&self.keyword, self.page.max(self.start_page))
+    }
+
+    // 已缓存的分页数
+    pub fn cache_len(&self) -> usize {
+        self.albums.len()
+    }
+
+    // 修改每页数量后分页会发生变化，需要重新从第一页获取
+    pub fn set_size(&mut self, size: u32) {
+        self.size = self.parser.effective_page_size(size);
+        self.page = 0;
+        self.page_count = 0;
+        self.albums.clear();
+        self.prefetch = None;
+    }
+
+    // 关闭相邻分页的预取，用于测试以及需要控制请求数的环境
+    pub fn disable_prefetch(&mut self) {
+        self.prefetch_enabled = false;
+        self.prefetch = None;
+    }
+
+    pub fn download_root(&self) -> &Path {
+        &self.download_root
+    }
+
+    pub fn set_download_root<P: AsRef<Path>>(&mut self, root: P) {
+        self.download_root = root.as_ref().to_path_buf();
+    }
+
+    pub fn download_config(&self) -> &DownloadConfig {
+        &self.download_config
+    }
+
+    pub fn set_download_config(&mut self, config: DownloadConfig) {
+        self.download_config = config;
+    }
+
+    pub fn set_album_cache(&mut self, cache: Option<AlbumCache>) {
+        self.album_cache = cache;
+    }
+
+    // 磁盘缓存的键，包含解析器、关键字或分类、页码以及每页数量
+    fn cache_key(&self, page: u32) -> String {
+        let query = match &self.category {
+            Some(category) => format!("category:{}", category),
+            None => format!("keyword:{}", self.keyword)
+        };
+        format!("{}|{}|{}|{}", self.parser_code, query, page, self.size)
+    }
+
+    async fn get_albums(&mut self) -> AlbumResult {
+        // 搜索器创建时页码为 0，解析器的页码从 1 开始
+        self.page = self.page.max(self.start_page);
+        let key = format!("page-{}", &self.page);
+        if self.albums.contains(&key) {
+            Ok(self.albums.get(&key))
+        } else {
+            let cache_key = self.cache_key(self.page);
+            let cached = match &self.album_cache {
+                Some(cache) => cache.get(&cache_key).await,
+                None => None
+            };
+            let (albums, page_count) = match cached {
+                Some(cached) => cached,
+                None => {
+                    // 获取新数据，正在预取当前页时等待预取的结果
+                    let fetched = match self.take_prefetch(self.page).await {
+                        Some(prefetched) => prefetched,
+                        None => Self::fetch_page(self.parser.clone(), self.keyword.clone(), self.category.clone(), self.page, self.size).await?
+                    };
+                    if let Some(cache) = &self.album_cache {
+                        cache.put(&cache_key, &fetched).await;
+                    }
+                    fetched
+                }
+            };
+            self.cache_page(self.page, albums, page_count);
+            Ok(self.albums.get(&key))
+        }
+    }
+
+    fn cache_page(&mut self, page: u32, albums: Vec<Album>, page_count: u32) {
+        // page_count 表示第一次获取数据，总页数没有赋值
+        // 有些网站不能获取到总页数，通过每次获取数据时，更新页码总数，有数据时总页数至少为当前页
+        let page_count = if albums.is_empty() { page_count } else { page_count.max(page) };
+        if self.page_count == 0 || self.page_count < page_count {
+            self.page_count = page_count;
+        }
+
+        self.albums.push(format!("page-{}", page), albums);
+    }
+
+    // 并发获取多个分页放入缓存，每批最多 PREFETCH_BATCH_SIZE 页，已缓存、超出范围的分页跳过，
+    // 部分分页获取失败时其它分页仍然缓存，返回第一个错误
+    pub async fn prefetch_pages(&mut self, pages: &[u32]) -> Result<()> {
+        let pages: Vec<u32> = pages.iter().copied()
+            .filter(|page| *page >= self.start_page && (self.page_count == 0 || *page <= self.last_page()))
+            .filter(|page| !self.albums.contains(&format!("page-{}", page)))
+            .collect();
+        let mut first_err = None;
+        for batch in pages.chunks(Self::PREFETCH_BATCH_SIZE) {
+            let mut fetches = batch.iter().map(|page| {
+                let (parser, keyword, category, size) = (self.parser.clone(), self.keyword.clone(), self.category.clone(), self.size);
+                let page = *page;
+                async move { (page, Self::fetch_page(parser, keyword, category, page, size).await) }
+            }).collect::<FuturesUnordered<_>>();
+            while let Some((page, result)) = fetches.next().await {
+                match result {
+                    Ok((albums, page_count)) => self.cache_page(page, albums, page_count),
+                    Err(err) => {
+                        warn!("prefetch page {} error: {:?}", page, err);
+                        first_err.get_or_insert(err);
+                    }
+                }
+            }
+        }
+        first_err.map_or(Ok(()), Err)
+    }
+
+    async fn fetch_page(parser: Arc<dyn Parser>, keyword: String, category: Option<String>, page: u32, size: u32) -> Result<AlbumPage> {
+        match category {
+            Some(category) => parser.parse_albums_by_category(category, page, size).await,
+            None => parser.parse_albums(keyword, page, size).await
+        }
+    }
+
+    // 在后台获取第 page 页，页码超出范围或者已经缓存时不预取
+    fn start_prefetch(&mut self, page: u32) {
+        self.start_prefetch_pages(&[page]);
+    }
+
+    // 在后台并发获取多个分页，不等待结果，正在预取这些分页时不重复请求
+    fn start_prefetch_pages(&mut self, pages: &[u32]) {
+        if !self.prefetch_enabled {
+            return;
+        }
+        let pages: Vec<u32> = pages.iter().copied()
+            .filter(|page| *page >= self.start_page && *page <= self.last_page())
+            .filter(|page| !self.albums.contains(&format!("page-{}", page)))
+            .collect();
+        if pages.is_empty() || self.prefetch.as_ref().is_some_and(|prefetch| pages.iter().all(|page| prefetch.pages.contains(page))) {
+            return;
+        }
+
+        let results = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, done) = watch::channel(0);
+        let task_results = results.clone();
+        let mut fetches = pages.iter().map(|page| {
+            let (parser, keyword, category, size) = (self.parser.clone(), self.keyword.clone(), self.category.clone(), self.size);
+            let page = *page;
+            async move { (page, Self::fetch_page(parser, keyword, category, page, size).await) }
+        }).collect::<FuturesUnordered<_>>();
+        tokio::spawn(async move {
+            while let Some((page, result)) = fetches.next().await {
+                let result = match result {
+                    Ok(albums) => Some(albums),
+                    Err(err) => {
+                        warn!("prefetch page {} error: {:?}", page, err);
+                        None
+                    }
+                };
+                task_results.lock().unwrap().insert(page, result);
+                sender.send_modify(|done| *done += 1);
+            }
+        });
+        self.prefetch = Some(Prefetch { pages, results, done });
+    }
+
+    // 取出第 page 页的预取结果，预取还没有结束时等待，预取失败时返回 None 由调用方重新获取
+    async fn take_prefetch(&mut self, page: u32) -> Option<AlbumPage> {
+        let prefetch = self.prefetch.as_mut().filter(|prefetch| prefetch.pages.contains(&page))?;
+        let results = prefetch.results.clone();
+        let _ = prefetch.done.wait_for(|_| results.lock().unwrap().contains_key(&page)).await;
+        prefetch.pages.retain(|prefetched| *prefetched != page);
+        if prefetch.pages.is_empty() {
+            self.prefetch = None;
+        }
+        let result = results.lock().unwrap().remove(&page).flatten();
+        result
+    }
+
+    // 搜索器创建后需要先调用 initialize 或 next 获取第一页数据，之后 current 才返回当前页
+    pub async fn initialize(&mut self) -> AlbumResult<'_> {
+        self.page = self.start_page;
+        self.get_albums().await
+    }
+
+    pub async fn current(&mut self) -> AlbumResult {
+        if self.page == 0 {
+            return Err(DownloaderError::NotInitialized.into());
+        }
+
+        self.get_albums().await
+    }
+
+    pub async fn prev(&mut self) -> AlbumResult {
+        if self.page > self.start_page {
+            self.page -= 1;
+        } else {
+            // 当搜索器初始化后，分页总数未被初始化
+            self.page = self.start_page;
+        }
+
+        self.get_albums().await?;
+        self.start_prefetch(self.page - 1);
+        self.get_albums().await
+    }
+
+    pub async fn next(&mut self) -> AlbumResult {
+        let first_load = self.page_count == 0;
+        if self.page_count == 0 || self.page < self.start_page {
+            // 当搜索器初始化后，分页总数未被初始化
+            self.page = self.start_page;
+        } else if self.page < self.last_page() {
+            self.page += 1;
+        } else {
+            self.page_count;
+        }
+
+        // 先获取当前页得到分页总数，再预取下一页，最后从缓存中返回当前页
+        self.get_albums().await?;
+        if first_load {
+            // 第一次搜索时在后台同时获取后面几页，不等待预取完成
+            let pages: Vec<u32> = (self.page + 1..=self.page + Self::INITIAL_PREFETCH_PAGES).collect();
+            self.start_prefetch_pages(&pages);
+        }
+        self.start_prefetch(self.page + 1);
+        self.get_albums().await
+    }
+
+    pub async fn first(&mut self) -> AlbumResult {
+        self.page = self.start_page;
+        self.get_albums().await
+    }
+
+    pub async fn last(&mut self) -> AlbumResult {
+        if self.page_count == 0 {
+            // 解析第一页内容，并获取分页总数
+            self.next().await?;
+        }
+
+        self.page = self.clamp_page(self.page_count);
+        self.get_albums().await
+    }
+
+    pub async fn jump(&mut self, page: &u32) -> AlbumResult {
+        let page = *page;
+        self.page = if page <= self.start_page {
+            self.start_page
+        } else {
+            if self.page_count == 0 {
+                // 解析第一页内容，并获取分页总数
+                self.next().await?;
+            }
+
+            self.clamp_page(page)
+        };
+
+        self.get_albums().await
+    }
+
+    pub async fn download(&mut self, idx: usize) -> Result<DownloadResult> {
+        let (album, path) = self.get_album(idx)?;
+        let album = Arc::new(album);
+        info!("download searcher {} page {} index album, album: {}, path: {:?}", self.page, idx, album.name, path);
+        if let Some(hint) = album.picture_count_hint {
+            info!("downloading album {} (~{} pictures)", album.name, hint);
+        }
+        check_writable(&self.download_root).await?;
+        let parser = self.parser.clone();
+        let client = parser.client();
+        let context = DownloadContext::new(&self.download_config);
+        let ret = album.clone().download_pictures(&client, parser.clone(), &path, &context).await;
+        self.record_failed(album, path, &ret);
+        ret
+    }
+
+    pub async fn download_range(&mut self, idx: usize, start: usize, end: usize) -> Result<DownloadResult> {
+        let (album, path) = self.get_album(idx)?;
+        let album = Arc::new(album);
+        info!("download searcher {} page {} index album pictures {}-{}, album: {}, path: {:?}", self.page, idx, start, end, album.name, path);
+        check_writable(&self.download_root).await?;
+        let parser = self.parser.clone();
+        let client = parser.client();
+        let context = DownloadContext::new(&self.download_config);
+        let ret = album.clone().download_range(&client, parser.clone(), &path, &context, start, end).await;
+        self.record_failed(album, path, &ret);
+        ret
+    }
+
+    // 下载专辑以及其后的系列专辑，结果按下载顺序返回
+    pub async fn download_series(&mut self, idx: usize, max_depth: usize) -> Result<Vec<Result<DownloadResult>>> {
+        let (album, path) = self.get_album(idx)?;
+        info!("download series from searcher {} page {} index album, album: {}, max depth: {}", self.page, idx, album.name, max_depth);
+        check_writable(&self.download_root).await?;
+        let parser = self.parser.clone();
+        let client = parser.client();
+        let context = DownloadContext::new(&self.download_config);
+        Ok(Arc::new(album).download_series(&client, parser, &path, &context, max_depth).await)
+    }
+
+    // 最近一次下载失败的图片数
+    pub fn failed_count(&self) -> usize {
+        self.last_failed.as_ref().map_or(0, |(_, _, result)| result.failed_urls.len())
+    }
+
+    // 重新下载最近一次下载失败的图片，仍然失败的图片可以继续重试
+    pub async fn retry_failed(&mut self) -> Result<DownloadResult> {
+        let (album, path, failed) = self.last_failed.take().ok_or(anyhow!("no failed pictures to retry"))?;
+        check_writable(&self.download_root).await?;
+        let parser = self.parser.clone();
+        let client = parser.client();
+        let context = DownloadContext::new(&self.download_config);
+        let ret = album.clone().retry_pictures(&client, parser.clone(), &path, &context, &failed).await;
+        match &ret {
+            Ok(_) => self.record_failed(album, path, &ret),
+            // 重试出错时保留失败列表，可以再次重试
+            Err(_) => self.last_failed = Some((album, path, failed))
+        }
+        ret
+    }
+
+    // 在搜索器的副本上下载时（如后台任务），下载结束后把失败的图片同步回来，retry_failed 可以继续重试
+    pub fn sync_failed(&mut self, other: &AlbumSearcher) {
+        self.last_failed = other.last_failed.clone();
+    }
+
+    fn record_failed(&mut self, album: Arc<Album>, path: PathBuf, ret: &Result<DownloadResult>) {
+        self.last_failed = match ret {
+            Ok(result) if !result.failed_urls.is_empty() => Some((album, path, result.clone())),
+            _ => None
+        };
+    }
+
+    pub async fn preview_url(&mut self, idx: usize) -> Result<String> {
+        let (album, _) = self.get_album(idx)?;
+        album.preview_url(&*self.parser).await
+    }
+
+    // 下载专辑封面到临时目录，返回保存的文件路径
+    pub async fn preview(&mut self, idx: usize) -> Result<PathBuf> {
+        let (album, _) = self.get_album(idx)?;
+        let url = album.preview_url(&*self.parser).await?;
+        info!("preview searcher {} page {} index album, album: {}, cover: {}", self.page, idx, album.name, url);
+        let path = std::env::temp_dir().join("lmpic_preview");
+        tokio::fs::create_dir_all(&path).await?;
+        let client = self.parser.client();
+        // 不同专辑的封面文件名可能相同
+        let prefix = format!("{}_", short_hash(&album.url));
+        album.download_picture(&client, &*self.parser, &url, path, &prefix, SaveTarget { storage: &LocalStorage, hashes: None, archive: None })
+            .await.map(|(path, _, _)| path)
+    }
+
+    // 下载当前页的所有专辑，同时下载的专辑数以及总连接数由下载配置限制，结果按专辑在页面中的顺序返回
+    pub async fn download_all(&mut self) -> Result<Vec<Result<DownloadResult>>> {
+        let count = self.current().await?.map_or(0, |albums| albums.len());
+        let albums = (1..=count).map(|idx| self.get_album(idx)).collect::<Result<Vec<_>>>()?;
+        info!("download searcher {} page all {} albums", self.page, count);
+        check_writable(&self.download_root).await?;
+
+        let context = Arc::new(DownloadContext::new(&self.download_config));
+        let overall = context.progress_bar(count, ProgressStyle::with_template("专辑 [{bar:40.green/white}] {pos}/{len} {msg}")
+            .unwrap()
+            .progress_chars("#>-"));
+        let start = Instant::now();
+        let completed = Arc::new(AtomicUsize::new(0));
+
+        let semaphore = Arc::new(Semaphore::new(self.download_config.album_concurrency.max(1)));
+        let mut tasks = JoinSet::new();
+        for (index, (album, path)) in albums.into_iter().enumerate() {
+            let permit = semaphore.clone().acquire_owned().await?;
+            let parser = self.parser.clone();
+            let context = context.clone();
+            let overall = overall.clone();
+            let completed = completed.clone();
+            tasks.spawn(async move {
+                let client = parser.client();
+                let album = Arc::new(album);
+                let ret = album.download_pictures(&client, parser, &path, &context).await;
+                // 按已完成专辑的速度估算剩余专辑的用时
+                let done = completed.fetch_add(1, Ordering::SeqCst) + 1;
+                let eta = start.elapsed().mul_f64((count - done) as f64 / done as f64);
+                overall.bar.set_message(format!("剩余约 {}", format_duration(eta)));
+                info!("download albums {}/{}, eta: {:?}", done, count, eta);
+                overall.inc("albums");
+                drop(permit);
+                (index, ret)
+            });
+        }
+
+        let mut results: Vec<Result<DownloadResult>> = (0..count).map(|_| Err(anyhow!("download album task error"))).collect();
+        while let Some(ret) = tasks.join_next().await {
+            match ret {
+                Ok((index, ret)) => results[index] = ret,
+                Err(err) => error!("download album task error: {:?}", err)
+            }
+        }
+        overall.bar.finish();
+        Ok(results)
+    }
+
+    // 返回专辑以及专辑的保存目录
+    fn get_album(&mut self, idx: usize) -> Result<(Album, PathBuf)> {
+        if self.page_count == 0 {
+            return Err(anyhow!("no data"));
+        }
+
+        if self.page == 0 {
+            return Err(anyhow!("no data"));
+        }
+
+        if idx == 0 {
+            return Err(anyhow!("error album index"));
+        }
+
+        let key = format!("page-{}", self.page);
+        let albums = self.albums.get(&key);
+        if let Some(albums) = albums {
+            if idx > albums.len() {
+                return Err(anyhow!("error album index, max index: {}", albums.len()));
+            }
+
+            let index = idx - 1;
+            let album = albums[index].clone();
+            // 同一页中有其它专辑使用相同的目录名时，需要区分开
+            let folder = album.folder_name(false);
+            let duplicated = albums.iter().filter(|a| a.folder_name(false) == folder).count() > 1;
+            let folder = album.folder_name(duplicated);
+
+            let mut path = self.download_root.clone();
+            if self.download_config.parser_code_folder {
+                path.push(self.parser.parser_code());
+            }
+            path.push(folder);
+            Ok((album, path))
+        } else {
+            Err(anyhow!("current page no data"))
+        }
+    }
+}
+
+pub mod retry {
+    use std::collections::hash_map::RandomState;
+    use std::future::Future;
+    use std::hash::BuildHasher;
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use reqwest::StatusCode;
+    use tracing::warn;
+
+    use crate::DownloaderError;
+
+    // 请求专辑页面和下载图片共用的重试策略，attempts 包含第一次请求，默认不重试
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct RetryPolicy {
+        pub attempts: u32,
+        pub base_delay: Duration,
+        pub max_delay: Duration,
+        // 在退避时间的一半到全部之间随机等待，避免并发的请求同时重试
+        pub jitter: bool
+    }
+
+    impl Default for RetryPolicy {
+        fn default() -> Self {
+            Self {
+                attempts: 1,
+                base_delay: Duration::from_millis(500),
+                max_delay: Duration::from_secs(10),
+                jitter: true
+            }
+        }
+    }
+
+    impl RetryPolicy {
+        // 第 retry 次重试前的退避时间，按指数增长，不超过 max_delay
+        pub fn backoff(&self, retry: u32) -> Duration {
+            let factor = 1u32.checked_shl(retry.saturating_sub(1)).unwrap_or(u32::MAX);
+            self.base_delay.saturating_mul(factor).min(self.max_delay)
+        }
+
+        fn delay(&self, retry: u32) -> Duration {
+            let backoff = self.backoff(retry);
+            if !self.jitter {
+                return backoff;
+            }
+
+            let half = backoff / 2;
+            let random = RandomState::new().hash_one(retry) % (half.as_millis() as u64 + 1);
+            half + Duration::from_millis(random)
+        }
+    }
+
+    // 连接、超时等网络错误以及 408、429 和 5xx 响应可以重试，其它错误重试也不会成功
+    pub fn is_retryable(err: &anyhow::Error) -> bool {
+        err.chain().any(|cause| {
+            if let Some(DownloaderError::HttpError { status, .. }) = cause.downcast_ref::<DownloaderError>() {
+                return *status == StatusCode::REQUEST_TIMEOUT || *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+            }
+            if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+                return err.is_timeout() || err.is_connect() || err.is_request() || err.is_body();
+            }
+            false
+        })
+    }
+
+    pub async fn retry_async<T, F, Fut>(policy: &RetryPolicy, f: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>
+    {
+        retry_async_if(policy, is_retryable, f).await
+    }
+
+    // 由 retryable 判断错误是否需要重试
+    pub async fn retry_async_if<T, F, Fut, C>(policy: &RetryPolicy, retryable: C, mut f: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+        C: Fn(&anyhow::Error) -> bool
+    {
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Ok(value) => return Ok(value),
+                Err(err) if attempt < policy.attempts && retryable(&err) => {
+                    let delay = policy.delay(attempt);
+                    warn!("attempt {} failed, retry after {:?}: {:?}", attempt, delay, err);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "s3")]
+pub mod s3 {
+    use std::fmt;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use anyhow::{anyhow, Result};
+    use aws_sdk_s3::Client;
+    use aws_sdk_s3::config::{BehaviorVersion, Builder, Credentials, Region};
+    use aws_sdk_s3::primitives::ByteStream;
+    use tracing::{error, info, warn};
+
+    #[derive(Clone, Default)]
+    pub struct S3Config {
+        pub bucket: String,
+        pub prefix: String,
+        // 自建的 S3 兼容存储的地址，为空时使用 AWS
+        pub endpoint_url: Option<String>,
+        pub access_key_id: String,
+        pub secret_access_key: String,
+        pub region: String
+    }
+
+    // 日志中不输出密钥
+    impl fmt::Debug for S3Config {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("S3Config")
+                .field("bucket", &self.bucket)
+                .field("prefix", &self.prefix)
+                .field("endpoint_url", &self.endpoint_url)
+                .field("access_key_id", &self.access_key_id)
+                .field("secret_access_key", &"***")
+                .field("region", &self.region)
+                .finish()
+        }
+    }
+
+    #[derive(Default)]
+    pub(crate) struct UploadStats {
+        pub(crate) uploaded: AtomicUsize,
+        pub(crate) failed: AtomicUsize
+    }
+
+    pub(crate) struct Uploader {
+        client: Client,
+        bucket: String,
+        prefix: String
+    }
+
+    impl Uploader {
+        const MAX_ATTEMPTS: u64 = 3;
+
+        pub(crate) fn new(config: &S3Config) -> Self {
+            let credentials = Credentials::new(&config.access_key_id, &config.secret_access_key, None, None, "lmpic_downloader");
+            let mut builder = Builder::new()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new(config.region.clone()))
+                .credentials_provider(credentials)
+                // 大部分 S3 兼容存储不支持虚拟主机风格的地址
+                .force_path_style(true);
+            if let Some(endpoint_url) = &config.endpoint_url {
+                builder = builder.endpoint_url(endpoint_url);
+            }
+
+            Self {
+                client: Client::from_conf(builder.build()),
+                bucket: config.bucket.clone(),
+                prefix: config.prefix.clone()
+            }
+        }
+
+        // 上传失败时保留本地文件，不影响图片下载的结果
+        pub(crate) async fn upload_picture(&self, path: &Path, keep_local: bool, stats: &UploadStats) {
+            let key = object_key(&self.prefix, path);
+            match self.upload(&key, path).await {
+                Ok(_) => {
+                    stats.uploaded.fetch_add(1, Ordering::SeqCst);
+                    info!("picture {} uploaded to {}", path.display(), key);
+                    if !keep_local {
+                        let _ = tokio::fs::remove_file(path).await;
+                    }
+                }
+                Err(err) => {
+                    stats.failed.fetch_add(1, Ordering::SeqCst);
+                    error!("upload picture {} error: {:?}", path.display(), err);
+                    println!("上传图片失败，详情请查看日志");
+                }
+            }
+        }
+
+        async fn upload(&self, key: &str, path: &Path) -> Result<()> {
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                let body = ByteStream::from_path(path).await.map_err(|err| {
+                    anyhow!("read {} error: {}", path.display(), err)
+                })?;
+                match self.client.put_object().bucket(&self.bucket).key(key).body(body).send().await {
+                    Ok(_) => return Ok(()),
+                    Err(err) if attempt < Self::MAX_ATTEMPTS => {
+                        warn!("upload {} attempt {} error: {:?}", key, attempt, err);
+                        tokio::time::sleep(Duration::from_secs(attempt)).await;
+                    }
+                    Err(err) => return Err(anyhow!("upload {} error: {:?}", key, err))
+                }
+            }
+        }
+    }
+
+    // 对象路径为 <prefix>/<专辑目录名>/<图片名>
+    pub(crate) fn object_key(prefix: &str, path: &Path) -> String {
+        let name = |name: Option<&std::ffi::OsStr>| name.map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let album = name(path.parent().and_then(|parent| parent.file_name()));
+        let picture = name(path.file_name());
+        [prefix.trim_matches('/').to_string(), album, picture].into_iter()
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+pub mod util {
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+
+    use anyhow::Result;
+    use lazy_static::lazy_static;
+    use regex::Regex;
+    use serde::Serialize;
+    use tracing::warn;
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+
+    lazy_static! {
+        // str 中不会出现代理项 (U+D800-U+DFFF)，来自 UTF-16 或错误字节的代理项在解码时被替换为 U+FFFD，一并去掉
+        static ref RESERVED: Regex =
+            Regex::new("[<>:\"/\\\\|?*\u{0000}-\u{001F}\u{007F}\u{0080}-\u{009F}\u{FFFD}]+").unwrap();
+        // Windows 保留的设备名，带扩展名时同样不能使用，如 con.jpg
+        static ref WINDOWS_RESERVED: Regex = Regex::new("(?i)^(con|prn|aux|nul|com\\d|lpt\\d)(\\..*)?$").unwrap();
+        static ref OUTER_PERIODS: Regex = Regex::new("^\\.+|\\.+$").unwrap();
+        // 搜索结果中的图片数量，如 "共 42 张"、"[42P]"、"42 photos"
+        static ref PICTURE_COUNT: Regex = Regex::new("(?i)(\\d+)\\s*(?:张|p\\b|photos?\\b|pictures?\\b)").unwrap();
+    }
+
+    // 日志过滤规则，RUST_LOG 格式如 info,lmpic_downloader::http=trace，未设置或格式错误时只记录 INFO 及以上的日志
+    pub fn log_filter() -> Targets {
+        std::env::var("RUST_LOG").ok()
+            .and_then(|value| value.parse::<Targets>().map_err(|err| eprintln!("invalid RUST_LOG {}: {}", value, err)).ok())
+            .unwrap_or_else(|| Targets::new().with_default(LevelFilter::INFO))
+    }
+
+    pub(super) fn filenamify<S: AsRef<str>>(input: S, replacement: &str) -> String {
+        let input = RESERVED.replace_all(input.as_ref(), replacement);
+        let input = OUTER_PERIODS.replace_all(input.as_ref(), replacement);
+
+        let mut result = input.into_owned();
+        if let Some(captures) = WINDOWS_RESERVED.captures(result.as_str()) {
+            // 在设备名后插入替换字符，不指定替换字符时使用下划线，con.jpg -> con_.jpg
+            let replacement = if replacement.is_empty() { "_" } else { replacement };
+            let extension = captures.get(2).map_or("", |m| m.as_str());
+            result = format!("{}{}{}", &captures[1], replacement, extension);
+        }
+
+        result
+    }
+
+    // 文件名最大长度，以及 Windows 下未使用 \\?\ 前缀时完整路径的最大长度 (MAX_PATH)
+    const MAX_NAME_LEN: usize = 255;
+    const MAX_PATH_LEN: usize = if cfg!(windows) { 260 } else { 4096 };
 
-        fn get_pagination(&self, html: &str) -> usize {
-            1
+    // Windows 按 UTF-16 编码单元计算长度，其它平台按字节计算
+    fn path_len(s: &str) -> usize {
+        if cfg!(windows) {
+            s.encode_utf16().count()
+        } else {
+            s.len()
         }
+    }
 
-        async fn get_page_pictures(&self, url: String) -> Result<Vec<String>> {
-            self.inner.get_page_pictures(url, ".imgbox>.img>img", None, None).await
-        }
+    // 保存到 dir 目录下的完整路径超出平台限制时截断文件名
+    pub(super) fn fit_file_name(dir: &Path, name: &str) -> String {
+        let dir = std::path::absolute(dir).unwrap_or(dir.to_path_buf());
+        let available = MAX_PATH_LEN.saturating_sub(path_len(&dir.to_string_lossy()) + 1).min(MAX_NAME_LEN);
+        truncate_file_name(name, available)
+    }
 
-        async fn get_all_pictures(&self, url: String) -> Result<Vec<String>> {
-            let pictures = self.get_page_pictures(url).await?;
-            let pictures = pictures.into_iter().map(|picture| {
-                picture.split("@").next().unwrap_or("").to_string()
-            }).collect();
-            Ok(pictures)
+    // 截断文件名使其长度不超过 max_len，保留扩展名，并追加原文件名的短哈希避免截断后重名
+    pub(super) fn truncate_file_name(name: &str, max_len: usize) -> String {
+        if path_len(name) <= max_len {
+            return name.to_string();
         }
 
-        fn get_picture_name(&self,  url: &str) -> Result<String> {
-            self.inner.get_picture_name(url)
+        let (stem, extension) = match name.rfind('.') {
+            // 过长的"扩展名"不是真正的扩展名，整体当作文件名截断
+            Some(i) if i > 0 && path_len(&name[i..]) <= 16 => name.split_at(i),
+            _ => (name, "")
+        };
+        let suffix = format!("_{}{}", short_hash(name), extension);
+        let mut result = String::new();
+        for c in stem.chars() {
+            if path_len(&result) + path_len(c.encode_utf8(&mut [0; 4])) + path_len(&suffix) > max_len {
+                break;
+            }
+            result.push(c);
         }
-
+        result.push_str(&suffix);
+        result
     }
 
-    #[derive(Clone)]
-    struct SFTKParser {
-        inner: InnerParser
+    #[cfg(windows)]
+    pub(super) fn extended_path(path: &Path) -> PathBuf {
+        match std::path::absolute(path) {
+            Ok(path) if !path.to_string_lossy().starts_with("\\\\?\\") => {
+                let mut extended = std::ffi::OsString::from("\\\\?\\");
+                extended.push(path.as_os_str());
+                PathBuf::from(extended)
+            }
+            _ => path.to_path_buf()
+        }
     }
 
-    impl SFTKParser {
-
-        const PARSER_CODE: &'static str = "SFTK";
+    // 按 1024 进位显示大小，如 512 B、1.50 MB
+    pub(super) fn format_size(bytes: f64) -> String {
+        const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+        let mut size = bytes;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        match unit {
+            0 => format!("{:.0} {}", size, UNITS[unit]),
+            _ => format!("{:.2} {}", size, UNITS[unit])
+        }
+    }
 
-        const PARSER_NAME: &'static str = "私房图库";
+    // 显示速度，如 512 B/s、1.50 MB/s
+    pub(super) fn format_rate(bytes_per_second: f64) -> String {
+        format!("{}/s", format_size(bytes_per_second))
+    }
 
-        const BASE_URL: &'static str = "http://www.sftuku.com";
+    // 目录所在磁盘对当前用户可用的空间，非 unix 平台暂不支持
+    #[cfg(unix)]
+    pub(super) fn free_space(path: &Path) -> Option<u64> {
+        use std::os::unix::ffi::OsStrExt;
 
-        fn new() -> Self {
-            Self {
-                inner: InnerParser::new()
-            }
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        // statvfs 只读取 path 并写入 stat，两者在调用期间都有效
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+            return None;
         }
+        // 不同平台上字段的类型不同
+        #[allow(clippy::unnecessary_cast)]
+        Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+    }
 
-        fn keyword_to_pinyin(keyword: &str) -> String {
-            let pinyin: String = keyword.chars()
-                .map(|c| c.to_pinyin().map(|p| p.plain().to_string()).unwrap_or(c.to_string()))
-                .collect::<Vec<String>>()
-                .join("");
-            pinyin
-        }
+    #[cfg(not(unix))]
+    pub(super) fn free_space(_path: &Path) -> Option<u64> {
+        None
+    }
 
-        fn default_headers() -> HeaderMap {
-            let mut default_headers = HeaderMap::new();
-            default_headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("zh-CN,zh-Hans;q=0.9"));
-            default_headers.insert(header::HOST, HeaderValue::from_static("www.sftuku.com"));
-            default_headers
+    // 显示为 1h02m03s、2m03s、45s
+    pub(super) fn format_duration(duration: Duration) -> String {
+        let seconds = duration.as_secs();
+        match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+            (0, 0, s) => format!("{}s", s),
+            (0, m, s) => format!("{}m{:02}s", m, s),
+            (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s)
         }
     }
 
-    #[async_trait]
-    impl Parser for SFTKParser {
+    pub(super) fn picture_count_hint(text: &str) -> Option<u32> {
+        PICTURE_COUNT.captures(text).and_then(|captures| captures[1].parse().ok())
+    }
 
-        fn parser_name(&self) -> String {
-            SFTKParser::PARSER_NAME.to_string()
+    // FNV-1a 哈希，结果在不同的运行环境中保持一致，用于生成稳定的目录名
+    pub(super) fn short_hash(input: &str) -> String {
+        let mut hash: u32 = 0x811c9dc5;
+        for byte in input.as_bytes() {
+            hash ^= *byte as u32;
+            hash = hash.wrapping_mul(0x01000193);
         }
+        format!("{:08x}", hash)
+    }
 
-        fn client(&self) -> Arc<&Client> {
-            Arc::new(&self.inner.client)
+    // 取 URL 路径的最后一段（去掉扩展名）作为名称，如 http://host/album/123.htm -> 123
+    pub(super) fn url_slug(url: &str) -> String {
+        let path = url.split(['?', '#']).next().unwrap_or("");
+        let path = path.split_once("://").map(|(_, rest)| rest).unwrap_or(path);
+        // 去掉主机名部分
+        let path = path.split_once('/').map(|(_, rest)| rest).unwrap_or("");
+        let segment = path.split('/').rfind(|s| !s.is_empty()).unwrap_or("");
+        let stem = segment.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(segment);
+        let slug = filenamify(stem, "");
+        if slug.trim().is_empty() {
+            format!("album_{}", short_hash(url))
+        } else {
+            slug
         }
+    }
 
-        fn parse_page_count(&self, document: &Html) -> Result<u32> {
-            let selector = Selector::parse(".pagelist>p>select>option").map_err(|err| {
-                anyhow!("parse selector error: {err:?}")
-            })?;
+    // 判断解码后的内容是否像 HTML：包含 html/body 标签，或者可打印字符占绝大多数
+    pub(super) fn looks_like_html(content: &str) -> bool {
+        let head: String = content.chars().take(4096).collect::<String>().to_lowercase();
+        if head.contains("<html") || head.contains("<body") {
+            return true;
+        }
 
-            let elements: Vec<ElementRef> = document.select(&selector).into_iter().collect();
-            Ok((elements.len() / 2) as u32)
+        let total = head.chars().count();
+        if total == 0 {
+            return false;
         }
 
-        async fn parse_albums(&self, keyword: String, page: u32, size: u32) -> Result<(Vec<Album>, u32)> {
-            let pinyin = Self::keyword_to_pinyin(&keyword);
-            let url = format!("{}/chis/{}/{}.html", Self::BASE_URL, &pinyin, page);
-            let html = get_url_content(&self.inner.client, &url, Some("GBK".to_string()), Some(Self::default_headers())).await?;
-            let document = Html::parse_document(&html);
-            let selector = Selector::parse("#list>ul>li").map_err(|err| {
-                anyhow!("parse selector error: {err:?}")
-            })?;
-            let albums = self.inner.default_get_albums(&document, selector, ".Title>a", "a>img");
-            let albums = albums.into_iter().map(|album| {
-                Album {
-                    name: album.name,
-                    cover: album.cover,
-                    url: format!("{}{}", Self::BASE_URL, album.url)
-                }
-            }).collect();
-            let page_count = if self.inner.page_count == 0 {
-                self.parse_page_count(&document)?
-            } else {
-                self.inner.page_count
-            };
+        let printable = head.chars()
+            .filter(|c| *c != char::REPLACEMENT_CHARACTER && (!c.is_control() || c.is_whitespace()))
+            .count();
+        printable * 10 >= total * 9
+    }
 
-            Ok((albums, page_count))
-        }
+    // robots.txt 中适用于当前 User-Agent 的规则，只支持 Allow/Disallow 以及 * 和 $ 通配符
+    #[derive(Debug, Default)]
+    pub(super) struct Robots {
+        // (是否允许, 路径规则)
+        rules: Vec<(bool, String)>
+    }
 
-        fn get_pagination(&self, html: &str) -> usize {
-            let ret = Selector::parse(".pagelist>a");
-            if ret.is_err() {
-                error!("parse selector error: {:?}", ret.err());
-                return 0;
+    impl Robots {
+        // 优先使用名称包含在 user_agent 中的分组，没有时使用 * 分组
+        pub(super) fn parse(content: &str, user_agent: &str) -> Self {
+            let user_agent = user_agent.to_lowercase();
+            let mut specific = vec![];
+            let mut wildcard = vec![];
+            let mut agents: Vec<String> = vec![];
+            let mut in_rules = false;
+            let mut matched = false;
+            for line in content.lines() {
+                let line = line.split('#').next().unwrap_or("").trim();
+                let Some((key, value)) = line.split_once(':') else {
+                    continue;
+                };
+                let value = value.trim();
+                match key.trim().to_lowercase().as_str() {
+                    "user-agent" => {
+                        // 规则之后出现的 User-agent 开始新的分组
+                        if in_rules {
+                            agents.clear();
+                            in_rules = false;
+                        }
+                        let agent = value.to_lowercase();
+                        matched |= agent != "*" && user_agent.contains(agent.as_str());
+                        agents.push(agent);
+                    }
+                    directive @ ("allow" | "disallow") => {
+                        in_rules = true;
+                        // 空的 Disallow 表示允许所有
+                        if value.is_empty() {
+                            continue;
+                        }
+                        let rule = (directive == "allow", value.to_string());
+                        if agents.iter().any(|agent| agent != "*" && user_agent.contains(agent.as_str())) {
+                            specific.push(rule.clone());
+                        }
+                        if agents.iter().any(|agent| agent == "*") {
+                            wildcard.push(rule);
+                        }
+                    }
+                    _ => {}
+                }
             }
 
-            let selector = ret.unwrap();
-            let document = Html::parse_document(&html);
-            let elements: Vec<ElementRef> = document.select(&selector).into_iter().collect();
-            elements.len()
+            Self {
+                rules: if matched { specific } else { wildcard }
+            }
         }
 
-        async fn get_page_pictures(&self, url: String) -> Result<Vec<String>> {
-            self.inner.get_page_pictures(url, "#picg>.slide>a>img", Some("GBK".to_string()), Some(Self::default_headers())).await
+        // 最长匹配的规则生效，长度相同时 Allow 优先
+        pub(super) fn is_allowed(&self, path: &str) -> bool {
+            self.rules.iter()
+                .filter(|(_, rule)| Self::matches(rule, path))
+                .max_by_key(|(allow, rule)| (rule.len(), *allow))
+                .is_none_or(|(allow, _)| *allow)
         }
 
-        async fn get_all_pictures(&self, url: String) -> Result<Vec<String>> {
-            let html = get_url_content(&self.inner.client, &url, Some("GBK".to_string()), Some(Self::default_headers())).await?;
-            let page_count = self.get_pagination(&html);
-            let mut all_pictures = vec![];
-            for i in 1..=page_count {
-                let page_url = match i {
-                    1 => url.to_string(),
-                    n => {
-                        let base_url = &url[0..url.len() - 5];
-                        format!("{}_{}.html", base_url, n)
-                    }
-                };
-                let mut pictures = self.get_page_pictures(page_url).await?;
-                all_pictures.append(&mut pictures);
+        fn matches(rule: &str, path: &str) -> bool {
+            let (rule, anchored) = match rule.strip_suffix('$') {
+                Some(rule) => (rule, true),
+                None => (rule, false)
+            };
+            let parts: Vec<&str> = rule.split('*').collect();
+            let Some(mut rest) = path.strip_prefix(parts[0]) else {
+                return false;
+            };
+            if parts.len() == 1 {
+                return !anchored || rest.is_empty();
             }
 
-            Ok(all_pictures)
+            let last = parts[parts.len() - 1];
+            for part in &parts[1..parts.len() - 1] {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false
+                }
+            }
+            if anchored {
+                rest.ends_with(last)
+            } else {
+                rest.contains(last)
+            }
         }
+    }
 
-        fn get_picture_name(&self, url: &str) -> Result<String> {
-            self.inner.get_picture_name(url)
-        }
+    // 下载目录中已保存的专辑
+    #[derive(Clone, Debug, Serialize)]
+    pub struct AlbumSummary {
+        pub name: String,
+        pub path: PathBuf,
+        pub picture_count: usize,
+        // 专辑中最近保存的图片的修改时间，没有图片时为目录的修改时间
+        pub download_date: Option<SystemTime>
     }
 
-    pub fn parse(parser_code: &str) -> Result<Arc<dyn Parser>> {
-        match parser_code.to_uppercase().as_str() {
-            DiLi360Parser::PARSER_CODE => {
-                Ok(Arc::new(DiLi360Parser::new()))
+    // 扫描 dir 下的专辑目录，统计每个专辑已保存的图片，不需要重新搜索即可查看已下载的专辑
+    // 以 . 开头的文件和目录（如缩略图目录 .thumbs）不计入
+    pub async fn scan_albums(dir: &Path) -> Result<Vec<AlbumSummary>> {
+        let mut albums = vec![];
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || !entry.file_type().await?.is_dir() {
+                continue;
             }
-            SFTKParser::PARSER_CODE => {
-                Ok(Arc::new(SFTKParser::new()))
+
+            match scan_album(entry.path(), name).await {
+                Ok(album) => albums.push(album),
+                Err(err) => warn!("scan album {:?} error: {:?}", entry.path(), err)
             }
-            _ => Err(anyhow!("不支持的解析器: {}", parser_code))
         }
+        albums.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(albums)
     }
 
-    pub fn default_parser() -> Arc<dyn Parser> {
-        Arc::new(DiLi360Parser::new())
-    }
+    async fn scan_album(path: PathBuf, name: String) -> Result<AlbumSummary> {
+        let mut picture_count = 0;
+        let mut download_date = tokio::fs::metadata(&path).await?.modified().ok();
+        let mut latest = None;
+        let mut entries = tokio::fs::read_dir(&path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if entry.file_name().to_string_lossy().starts_with('.') || !metadata.is_file() {
+                continue;
+            }
 
-    pub fn parsers() -> Vec<(String, String)> {
-        let mut parsers = vec![];
-        parsers.push((DiLi360Parser::PARSER_CODE.to_string(), DiLi360Parser::PARSER_NAME.to_string()));
-        parsers.push((SFTKParser::PARSER_CODE.to_string(), SFTKParser::PARSER_NAME.to_string()));
-        parsers
+            picture_count += 1;
+            latest = latest.max(metadata.modified().ok());
+        }
+        if latest.is_some() {
+            download_date = latest;
+        }
+        Ok(AlbumSummary { name, path, picture_count, download_date })
     }
-
 }
 
-pub struct AlbumSearcher {
-    parser: Arc<dyn Parser>,
-    page: u32,
-    page_count: u32,
-    size: u32,
-    keyword: String,
-    albums: LruCache<String, Vec<Album>>
-}
+#[cfg(test)]
+mod tests {
+    use std::io::{Error, ErrorKind};
+    use std::path::Path;
 
-impl AlbumSearcher {
+    use super::*;
 
-    pub const DEFAULT_PAGE_SIZE: u32 = 10u32;
+    #[test]
+    fn test_storage_error_kind() {
+        let path = Path::new("albums/01.jpg");
+        let err = DownloaderError::from_io(Error::from(ErrorKind::StorageFull), path);
+        assert!(matches!(err, DownloaderError::DiskFull(_)));
+        assert!(err.is_storage_fatal());
+        assert!(err.to_string().contains("磁盘空间不足"));
 
-    pub fn new(parser: Arc<dyn Parser>, keyword: &str, size: u32) -> Self {
-        let mut size = size;
-        if size < 1 {
-            size = Self::DEFAULT_PAGE_SIZE;
-        }
+        let err = DownloaderError::from_io(Error::from(ErrorKind::PermissionDenied), path);
+        assert!(matches!(err, DownloaderError::PermissionDenied(_)));
+        assert!(err.is_storage_fatal());
 
-        Self {
-            parser,
-            page: 0,
-            page_count: 0,
-            size,
-            keyword: keyword.to_string(),
-            albums: LruCache::new(NonZeroUsize::new(64).unwrap())
-        }
+        let err = DownloaderError::from_io(Error::from(ErrorKind::UnexpectedEof), path);
+        assert!(matches!(err, DownloaderError::Write { .. }));
+        assert!(!err.is_storage_fatal());
     }
 
-    pub fn page(&self) -> u32 {
-        self.page
+    #[test]
+    fn test_filenamify_strips_control_characters() {
+        assert_eq!(util::filenamify("云南\u{0000}大理\u{001F}风光\u{007F}", ""), "云南大理风光");
+        // 代理项解码后为 U+FFFD
+        let name = String::from_utf16_lossy(&[0x96EA, 0xD800, 0x5C71]);
+        assert_eq!(util::filenamify(&name, ""), "雪山");
+        assert_eq!(util::filenamify("a<b>c", "_"), "a_b_c");
     }
 
-    pub fn page_count(&self) -> u32 {
-        self.page_count
+    #[test]
+    fn test_filenamify_windows_reserved() {
+        assert_eq!(util::filenamify("con", ""), "con_");
+        assert_eq!(util::filenamify("con.jpg", ""), "con_.jpg");
+        assert_eq!(util::filenamify("LPT1.tar.gz", "!"), "LPT1!.tar.gz");
+        assert_eq!(util::filenamify("console.jpg", ""), "console.jpg");
+        assert_eq!(util::filenamify("0001_con.jpg", ""), "0001_con.jpg");
     }
 
-    async fn get_albums(&mut self) -> AlbumResult {
-        let key = format!("page-{}", &self.page);
-        if self.albums.contains(&key) {
-            Ok(self.albums.get(&key))
-        } else {
-            // 获取新数据
-            let (albums, page_count) = self.parser.parse_albums(
-                self.keyword.clone(), self.page, self.size).await?;
-            // page_count 表示第一次获取数据，总页数没有赋值
-            // 有些网站不能获取到总页数，通过每次获取数据时，更新页码总数
-            if self.page_count == 0 || self.page_count < page_count {
-                self.page_count = page_count;
-            }
+    #[test]
+    fn test_truncate_long_file_name() {
+        let name = format!("{}.jpg", "a".repeat(300));
+        let truncated = util::truncate_file_name(&name, 255);
+        assert_eq!(truncated.len(), 255);
+        assert!(truncated.ends_with(&format!("_{}.jpg", util::short_hash(&name))));
+        // 前缀相同的长文件名截断后不会重名
+        assert_ne!(truncated, util::truncate_file_name(&format!("{}b.jpg", "a".repeat(300)), 255));
+        assert_eq!(util::truncate_file_name("01.jpg", 255), "01.jpg");
 
-            self.albums.push(key.clone(), albums);
-            Ok(self.albums.get(&key))
-        }
+        // 不在字符中间截断
+        let truncated = util::truncate_file_name(&format!("{}.jpg", "雪".repeat(100)), 100);
+        assert!(truncated.len() <= 100);
+        assert!(truncated.starts_with("雪"));
     }
 
-    pub async fn current(&mut self) -> AlbumResult {
-        if self.page_count == 0 {
-            // 当搜索器初始化后，分页总数未被初始化
-            self.page = 1;
-        }
-
-        self.get_albums().await
+    #[cfg(windows)]
+    #[test]
+    fn test_fit_file_name_max_path() {
+        let dir = std::env::temp_dir().join("a".repeat(200));
+        let name = format!("{}.jpg", "b".repeat(100));
+        let fitted = util::fit_file_name(&dir, &name);
+        assert!(fitted.ends_with(".jpg"));
+        assert!(std::path::absolute(dir.join(fitted)).unwrap().to_string_lossy().encode_utf16().count() <= 260);
+        assert!(util::extended_path(&dir).to_string_lossy().starts_with("\\\\?\\"));
     }
 
-    pub async fn prev(&mut self) -> AlbumResult {
-        if self.page > 1 {
-            self.page -= 1;
-        } else {
-            // 当搜索器初始化后，分页总数未被初始化
-            self.page = 1;
-        }
+    #[test]
+    fn test_download_throughput() {
+        let result = DownloadResult { bytes: 5 * 1024 * 1024, elapsed: Duration::from_secs(2), ..DownloadResult::default() };
+        assert_eq!(result.throughput(), 2.5 * 1024.0 * 1024.0);
+        assert_eq!(result.throughput_display(), "2.50 MB/s");
+        assert_eq!(DownloadResult { bytes: 1536, elapsed: Duration::from_secs(1), ..DownloadResult::default() }.throughput_display(), "1.50 KB/s");
+        assert_eq!(DownloadResult { bytes: 100, ..DownloadResult::default() }.throughput(), 0.0);
+        assert_eq!(util::format_rate(512.0), "512 B/s");
+        assert_eq!(util::format_size(1536.0), "1.50 KB");
 
-        self.get_albums().await
+        assert_eq!(util::format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(util::format_duration(Duration::from_secs(123)), "2m03s");
+        assert_eq!(util::format_duration(Duration::from_secs(3723)), "1h02m03s");
     }
 
-    pub async fn next(&mut self) -> AlbumResult {
-        if self.page_count == 0 {
-            // 当搜索器初始化后，分页总数未被初始化
-            self.page = 1;
-        } else if self.page < self.page_count {
-            self.page += 1;
-        } else {
-            self.page_count;
-        }
-
-        self.get_albums().await
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy { attempts: 6, base_delay: Duration::from_millis(100), max_delay: Duration::from_secs(1), jitter: false };
+        let delays: Vec<Duration> = (1..=5).map(|retry| policy.backoff(retry)).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000].map(Duration::from_millis));
+        assert_eq!(policy.backoff(64), Duration::from_secs(1));
     }
 
-    pub async fn first(&mut self) -> AlbumResult {
-        self.page = 1;
-        self.get_albums().await
-    }
+    #[tokio::test]
+    async fn test_retry_until_success() {
+        let policy = RetryPolicy { attempts: 3, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(4), jitter: true };
+        let http_error = |status: u16| -> anyhow::Error {
+            DownloaderError::HttpError { status: reqwest::StatusCode::from_u16(status).unwrap(), url: "http://localhost/01.jpg".to_string() }.into()
+        };
 
-    pub async fn last(&mut self) -> AlbumResult {
-        if self.page_count == 0 {
-            // 解析第一页内容，并获取分页总数
-            self.next().await?;
-        }
+        let calls = AtomicUsize::new(0);
+        let ret = retry::retry_async(&policy, || async {
+            match calls.fetch_add(1, Ordering::Relaxed) {
+                0 => Err(http_error(503)),
+                1 => Err(http_error(429)),
+                n => Ok(n)
+            }
+        }).await;
+        assert_eq!(ret.unwrap(), 2);
 
-        self.page = self.page_count;
-        self.get_albums().await
+        // 超过次数后返回最后一次的错误
+        calls.store(0, Ordering::Relaxed);
+        let ret: Result<()> = retry::retry_async(&policy, || async {
+            calls.fetch_add(1, Ordering::Relaxed);
+            Err(http_error(500))
+        }).await;
+        assert!(ret.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+        // 404 不会重试
+        calls.store(0, Ordering::Relaxed);
+        let ret: Result<()> = retry::retry_async(&policy, || async {
+            calls.fetch_add(1, Ordering::Relaxed);
+            Err(http_error(404))
+        }).await;
+        assert!(ret.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert!(!retry::is_retryable(&anyhow!("parse error")));
     }
 
-    pub async fn jump(&mut self, page: &u32) -> AlbumResult {
-        let page = *page;
-        self.page = if page <= 1 {
-            1
-        } else {
-            if self.page_count == 0 {
-                // 解析第一页内容，并获取分页总数
-                self.next().await?;
-            }
+    #[test]
+    fn test_select_preferred_formats() {
+        let pictures = |urls: &[&str]| urls.iter().map(|url| url.to_string()).collect::<Vec<_>>();
+        let urls = pictures(&["http://localhost/1.jpg", "http://localhost/2.png", "http://localhost/1.webp?w=1024",
+            "http://localhost/3", "http://localhost/2.avif", "http://localhost/thumb/1.gif"]);
 
-           if self.page_count < page {
-                self.page_count
-            }
-            else {
-                page
-            }
-        };
+        // 默认优先 JPEG，与原来一样下载 JPEG，不同目录下的同名图片不是同一张图片
+        assert_eq!(select_preferred_formats(urls.clone(), &ImageFormat::DEFAULT_PREFERENCE), pictures(&[
+            "http://localhost/1.jpg", "http://localhost/2.png", "http://localhost/3", "http://localhost/thumb/1.gif"]));
+        // 优先 WebP、AVIF 节省流量，位置与第一次出现的格式相同
+        assert_eq!(select_preferred_formats(urls, &[ImageFormat::WebP, ImageFormat::Avif, ImageFormat::Jpeg]), pictures(&[
+            "http://localhost/1.webp?w=1024", "http://localhost/2.avif", "http://localhost/3", "http://localhost/thumb/1.gif"]));
 
-        self.get_albums().await
+        assert_eq!("JPG".parse::<ImageFormat>().unwrap(), ImageFormat::Jpeg);
+        assert!("bmp".parse::<ImageFormat>().is_err());
     }
 
-    pub async fn download(&mut self, idx: usize) -> Result<()> {
-        if self.page_count == 0 {
-            return Err(anyhow!("no data"));
-        }
+    #[test]
+    fn test_with_media_extension() {
+        assert_eq!(with_media_extension("canyon".to_string(), Some("video/mp4; codecs=avc1"), b""), "canyon.mp4");
+        assert_eq!(with_media_extension("canyon".to_string(), Some("Video/WebM"), b""), "canyon.webm");
+        assert_eq!(with_media_extension("canyon.mp4".to_string(), Some("video/webm"), b""), "canyon.mp4");
+        assert_eq!(with_media_extension("01".to_string(), Some("image/avif"), b""), "01.avif");
+        assert_eq!(with_media_extension("01".to_string(), Some("image/heif"), b""), "01.heic");
+        // 图片以及未知类型保持原来的名称
+        assert_eq!(with_media_extension("01".to_string(), Some("image/jpeg"), b""), "01");
+        assert_eq!(with_media_extension("01".to_string(), None, b""), "01");
+    }
 
-        if self.page == 0 {
-            return Err(anyhow!("no data"));
-        }
+    #[test]
+    fn test_image_format_from_magic() {
+        let fixture = |name: &str| std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)).unwrap();
+        let (avif, heic) = (fixture("picture.avif"), fixture("picture.heic"));
+        assert_eq!(ImageFormat::from_magic(&avif), Some(ImageFormat::Avif));
+        assert_eq!(ImageFormat::from_magic(&heic), Some(ImageFormat::Heic));
+        // 主品牌为 mif1 时按兼容品牌识别
+        assert_eq!(ImageFormat::from_magic(b"\0\0\0\x18ftypmif1\0\0\0\0mif1heic"), Some(ImageFormat::Heic));
+        assert_eq!(ImageFormat::from_magic(b"\0\0\0\x18ftypisom\0\0\0\0isommp41"), None);
+        assert_eq!(ImageFormat::from_magic(b"\xFF\xD8\xFF\xE0"), None);
 
-        if idx == 0 {
-            return Err(anyhow!("error album index"));
-        }
+        // 文件头优先于 Content-Type，扩展名是其它图片格式时改正
+        assert_eq!(with_media_extension("01".to_string(), Some("application/octet-stream"), &avif), "01.avif");
+        assert_eq!(with_media_extension("01.jpg".to_string(), Some("image/jpeg"), &heic), "01.heic");
+        assert_eq!(with_media_extension("01.avif".to_string(), None, &avif), "01.avif");
+        assert_eq!(with_media_extension("01.bin".to_string(), None, &avif), "01.bin");
+        assert_eq!("HEIF".parse::<ImageFormat>().unwrap(), ImageFormat::Heic);
+    }
 
-        let key = format!("page-{}", self.page);
-        let albums = self.albums.get(&key);
-        if let Some(albums) = albums {
-            if idx > albums.len() {
-                return Err(anyhow!("error album index, max index: {}", albums.len()));
-            }
+    #[tokio::test]
+    async fn test_create_album_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let config = DownloadConfig::default();
+        let album = root.path().join("云南大理");
+        create_album_dir(&album, &config).await.unwrap();
+        assert!(album.is_dir());
 
-            let index = idx - 1;
-            let album = &albums[index];
-            info!("download searcher {} page {} index album, album: {}", self.page, idx, album.name);
-            let parser = self.parser.clone();
-            let client = parser.client();
-            let a = Arc::new(album.clone());
-            a.download_pictures(*client, parser.clone(), "./albums/").await
-        } else {
-            Err(anyhow!("current page no data"))
-        }
+        // 下载目录不存在时不自动创建
+        let missing = root.path().join("missing").join("云南大理");
+        let err = create_album_dir(&missing, &config).await.err().unwrap();
+        assert!(matches!(err.downcast_ref::<DownloaderError>(), Some(DownloaderError::InvalidConfig(_))));
+        assert!(!missing.parent().unwrap().exists());
+
+        // 按解析器分目录时，解析器目录可以不存在
+        let config = DownloadConfig { parser_code_folder: true, ..DownloadConfig::default() };
+        create_album_dir(&root.path().join("DILI360").join("云南大理"), &config).await.unwrap();
+
+        // 同名文件占用了专辑目录
+        std::fs::write(root.path().join("西藏"), b"").unwrap();
+        let err = create_album_dir(&root.path().join("西藏"), &DownloadConfig::default()).await.err().unwrap();
+        assert!(err.to_string().contains("failed to create album directory"));
+        assert!(err.downcast_ref::<DownloaderError>().is_some());
     }
-}
 
-mod util {
-    use lazy_static::lazy_static;
-    use regex::Regex;
+    #[tokio::test]
+    async fn test_scan_albums() {
+        let root = tempfile::tempdir().unwrap();
+        let album = root.path().join("云南大理");
+        std::fs::create_dir_all(album.join(THUMBS_DIR)).unwrap();
+        std::fs::write(album.join("01.jpg"), b"jpg").unwrap();
+        std::fs::write(album.join("02.jpg"), b"jpg").unwrap();
+        std::fs::write(album.join(THUMBS_DIR).join("01.jpg.jpg"), b"jpg").unwrap();
+        std::fs::create_dir(root.path().join("西藏风景")).unwrap();
+        std::fs::write(root.path().join("notes.txt"), b"").unwrap();
+        std::fs::create_dir(root.path().join(".cache")).unwrap();
 
-    lazy_static! {
-        static ref RESERVED: Regex =
-            Regex::new("[<>:\"/\\\\|?*\u{0000}-\u{001F}\u{007F}\u{0080}-\u{009F}]+").unwrap();
-        static ref WINDOWS_RESERVED: Regex = Regex::new("^(con|prn|aux|nul|com\\d|lpt\\d)$").unwrap();
-        static ref OUTER_PERIODS: Regex = Regex::new("^\\.+|\\.+$").unwrap();
+        let albums = util::scan_albums(root.path()).await.unwrap();
+        assert_eq!(albums.iter().map(|album| (album.name.as_str(), album.picture_count)).collect::<Vec<_>>(),
+                   vec![("云南大理", 2), ("西藏风景", 0)]);
+        assert_eq!(albums[0].path, album);
+        assert!(albums.iter().all(|album| album.download_date.is_some()));
+        assert!(util::scan_albums(&root.path().join("missing")).await.is_err());
     }
 
-    pub(super) fn filenamify<S: AsRef<str>>(input: S, replacement: &str) -> String {
-        let input = RESERVED.replace_all(input.as_ref(), replacement);
-        let input = OUTER_PERIODS.replace_all(input.as_ref(), replacement);
+    #[test]
+    fn test_picture_count_hint() {
+        assert_eq!(util::picture_count_hint("云南大理风光，共 42 张图片"), Some(42));
+        assert_eq!(util::picture_count_hint("山水风景 第1期 [36P]"), Some(36));
+        assert_eq!(util::picture_count_hint("12 Photos"), Some(12));
+        assert_eq!(util::picture_count_hint("山水风景 第1期"), None);
+        assert_eq!(util::picture_count_hint("2024 pages"), None);
+    }
 
-        let mut result = input.into_owned();
-        if WINDOWS_RESERVED.is_match(result.as_str()) {
-            result.push_str(replacement);
-        }
+    #[test]
+    fn test_robots_rules() {
+        let content = "User-agent: *\nDisallow: /private/\nAllow: /private/public\nDisallow: /*.gif$\n\n\
+            User-agent: BadBot\nDisallow: /\n";
+        let robots = util::Robots::parse(content, "Mozilla/5.0 Chrome/91.0");
+        assert!(robots.is_allowed("/pictures/01.jpg"));
+        assert!(!robots.is_allowed("/private/01.jpg"));
+        assert!(robots.is_allowed("/private/public/01.jpg"));
+        assert!(!robots.is_allowed("/pictures/01.gif"));
+        assert!(robots.is_allowed("/pictures/01.gif.jpg"));
 
-        result
+        let robots = util::Robots::parse(content, "BadBot/1.0");
+        assert!(!robots.is_allowed("/pictures/01.jpg"));
+        assert!(util::Robots::parse("", "BadBot/1.0").is_allowed("/"));
     }
 
-}
+    #[test]
+    fn test_progress_without_terminal() {
+        // 非终端环境下进度条不绘制，不会输出任何控制字符，进度改为写日志
+        let context = DownloadContext::with_terminal(&DownloadConfig::default(), false);
+        let pb = context.progress_bar(10, context.picture_style());
+        assert!(context.progress.is_hidden());
+        assert!(pb.bar.is_hidden());
+        assert!(pb.log);
+        pb.inc("云南");
+        assert_eq!(pb.bar.position(), 1);
 
-#[cfg(test)]
-mod tests {
-    use tokio;
+        let config = DownloadConfig { quiet: true, ..DownloadConfig::default() };
+        let context = DownloadContext::with_terminal(&config, true);
+        assert!(context.progress.is_hidden());
+        assert!(!context.progress_bar(10, context.picture_style()).log);
 
-    use super::*;
+        // 模板错误时使用默认模板
+        let config = DownloadConfig { progress_template: Some("{bar".to_string()), ..DownloadConfig::default() };
+        let context = DownloadContext::with_terminal(&config, true);
+        assert!(!context.log_progress);
+        context.picture_style();
+    }
 
+    #[cfg(feature = "s3")]
     #[test]
-    fn test_download_album() {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async {
-            let parser = parser::default_parser();
-            let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
-            let ret = searcher.next().await;
-            let ret = searcher.next().await;
-            assert!(ret.is_ok());
-
-            let opt = ret.unwrap();
-            assert!(opt.is_some());
-
-            let albums = opt.unwrap();
-            assert_eq!(albums.len(), 10usize);
-
-            match searcher.download(6).await {
-                Ok(_) => {
-                    println!("album downloaded.");
-                }
-                Err(err) => {
-                    println!("download album error: {err:?}");
-                }
-            }
-        });
+    fn test_s3_object_key() {
+        let path = Path::new("albums/云南大理/0001_01.jpg");
+        assert_eq!(s3::object_key("/backup/", path), "backup/云南大理/0001_01.jpg");
+        assert_eq!(s3::object_key("", path), "云南大理/0001_01.jpg");
     }
-
 }
@@ -1,22 +1,65 @@
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use anyhow::anyhow;
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
 use tokio::fs::create_dir_all;
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
 use tracing::{error, info};
 use tracing_appender::non_blocking::NonBlocking;
 use tracing_subscriber::{Layer, registry};
 use tracing_subscriber::fmt::layer;
 use tracing_subscriber::layer::SubscriberExt;
 
-use lmpic_downloader::{Album, AlbumSearcher, parser};
+use lmpic_downloader::{Album, AlbumCache, AlbumSearcher, ArchiveFormat, DEFAULT_MIN_FREE_SPACE, DEFAULT_THUMB_SIZE, DownloadConfig, DownloaderError, DownloadResult, ImageFormat, parser, util};
+use lmpic_downloader::util::AlbumSummary;
+#[cfg(feature = "s3")]
+use lmpic_downloader::s3::S3Config;
 
-#[derive(Debug)]
+// 输出命令结果的一行，交互模式下直接打印，后台服务模式下边执行边发送给客户端
+macro_rules! outln {
+    ($out:expr, $($arg:tt)*) => {
+        $out.line(format!($($arg)*))
+    };
+}
+
+#[derive(Clone)]
+enum Output {
+    Stdout,
+    Channel(UnboundedSender<String>)
+}
+
+impl Output {
+    fn line(&self, line: String) {
+        match self {
+            Self::Stdout => println!("{}", line),
+            // 接收端已关闭（如客户端断开）时丢弃输出
+            Self::Channel(sender) => {
+                let _ = sender.send(line);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 enum Command {
-    HELP, CURRENT, FIRST, LAST, NEXT, PREV, QUIT, UNKNOWN, NONE,
-    SWITCH(Option<String>), SEARCH(String), JUMP(u32), DOWNLOAD(usize), ArgumentErr(String)
+    HELP, CURRENT, FIRST, LAST, NEXT, PREV, QUIT, UNKNOWN(String), NONE,
+    SWITCH(Option<String>), CATEGORIES(Option<String>), SEARCH(String), SearchRange(u32, Option<u32>), JUMP(u32), DOWNLOAD(usize, Option<(usize, usize)>), DownloadAll, SERIES(usize, usize), RETRY, JOBS, PREVIEW(usize), SETDIR(Option<String>), LIST, ParseFile(String), INFO, ArgumentErr(String)
 }
 
+// 下载系列专辑时默认最多跟随的专辑数
+const DEFAULT_SERIES_DEPTH: usize = 10;
+
 impl FromStr for Command {
     type Err = anyhow::Error;
 
@@ -29,6 +72,9 @@ impl FromStr for Command {
                 "HELP" | "H" => {
                     Self::HELP
                 }
+                "INFO" | "I" => {
+                    Self::INFO
+                }
                 "CURRENT" | "C" => {
                     Self::CURRENT
                 }
@@ -69,7 +115,17 @@ impl FromStr for Command {
                         Some(idx) => {
                             match usize::from_str(idx) {
                                 Ok(idx) => {
-                                    Command::DOWNLOAD(idx)
+                                    match cmd_line.next() {
+                                        Some(range) => {
+                                            match parse_range(range) {
+                                                Some(range) => Command::DOWNLOAD(idx, Some(range)),
+                                                None => Self::ArgumentErr("图片范围格式错误，例如: 2-5".to_string())
+                                            }
+                                        }
+                                        None => {
+                                            Command::DOWNLOAD(idx, None)
+                                        }
+                                    }
                                 }
                                 Err(_) => {
                                     Self::ArgumentErr("参数必须为数字".to_string())
@@ -81,9 +137,62 @@ impl FromStr for Command {
                         }
                     }
                 }
+                "DOWNLOAD_ALL" | "DA" => {
+                    Self::DownloadAll
+                }
+                "SERIES" | "DS" => {
+                    let idx = cmd_line.next().map(usize::from_str);
+                    let depth = cmd_line.next().map(usize::from_str).unwrap_or(Ok(DEFAULT_SERIES_DEPTH));
+                    match (idx, depth) {
+                        (Some(Ok(idx)), Ok(depth)) => Self::SERIES(idx, depth),
+                        (None, _) => Self::ArgumentErr("缺少专辑索引参数".to_string()),
+                        _ => Self::ArgumentErr("参数必须为数字".to_string())
+                    }
+                }
+                "RETRY" | "RY" => {
+                    Self::RETRY
+                }
+                "JOBS" | "JB" => {
+                    Self::JOBS
+                }
+                "PREVIEW" | "PV" => {
+                    match cmd_line.next() {
+                        Some(idx) => {
+                            match usize::from_str(idx) {
+                                Ok(idx) => {
+                                    Command::PREVIEW(idx)
+                                }
+                                Err(_) => {
+                                    Self::ArgumentErr("参数必须为数字".to_string())
+                                }
+                            }
+                        }
+                        None => {
+                            Self::ArgumentErr("缺少专辑索引参数".to_string())
+                        }
+                    }
+                }
+                "PARSEFILE" | "PF" => {
+                    // 路径区分大小写，并且可能包含空格，使用原始输入
+                    match s.trim().split_once(char::is_whitespace).map(|(_, path)| path.trim()) {
+                        Some(path) if !path.is_empty() => Self::ParseFile(path.to_string()),
+                        _ => Self::ArgumentErr("缺少文件路径参数".to_string())
+                    }
+                }
+                "LIST" | "LS" => {
+                    Self::LIST
+                }
+                "SETDIR" | "SD" => {
+                    // 路径区分大小写，并且可能包含空格，使用原始输入
+                    let path = s.trim().split_once(char::is_whitespace).map(|(_, path)| path.trim().to_string());
+                    Self::SETDIR(path.filter(|path| !path.is_empty()))
+                }
                 "SWITCH" | "T" => {
                     Self::SWITCH(cmd_line.next().map(|argument|argument.to_string()))
                 }
+                "CATEGORIES" | "CAT" => {
+                    Self::CATEGORIES(cmd_line.next().map(|argument| argument.to_string()))
+                }
                 "SEARCH" | "S" => {
                     match cmd_line.next() {
                         Some(keyword) => {
@@ -94,44 +203,351 @@ impl FromStr for Command {
                         }
                     }
                 }
+                "SEARCH_RANGE" | "SR" => {
+                    // 不指定结束页时没有上限
+                    let start = cmd_line.next().map(u32::from_str);
+                    let end = cmd_line.next().map(u32::from_str).transpose();
+                    match (start, end) {
+                        (Some(Ok(start)), Ok(end)) if start >= 1 && end.is_none_or(|end| end >= start) => Self::SearchRange(start, end),
+                        (Some(Ok(_)), Ok(_)) => Self::ArgumentErr("页码范围错误，起始页从 1 开始并且不能大于结束页".to_string()),
+                        (None, _) => Self::ArgumentErr("缺少页码参数".to_string()),
+                        _ => Self::ArgumentErr("参数必须为数字".to_string())
+                    }
+                }
                 _ => {
-                    Self::UNKNOWN
+                    Self::UNKNOWN(s.trim().to_string())
                 }
             }
         }))
     }
 }
 
-fn print_albums(albums: Option<&Vec<Album>>) {
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let (from, to) = range.split_once('-')?;
+    let from = usize::from_str(from).ok()?;
+    let to = usize::from_str(to).ok()?;
+    Some((from, to))
+}
+
+// --upload-s3 开启上传，其它 --s3-* 参数为对象存储的配置，密钥也可以通过 AWS 的环境变量传入
+#[cfg(feature = "s3")]
+fn parse_s3_args(args: &[String]) -> anyhow::Result<Option<S3Config>> {
+    if !args.iter().any(|arg| arg == "--upload-s3") {
+        return Ok(None);
+    }
+
+    let value = |name: &str| args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).cloned();
+    let required = |name: &str, env: &str| {
+        value(name).or_else(|| std::env::var(env).ok()).ok_or(anyhow!("缺少参数 {}", name))
+    };
+    Ok(Some(S3Config {
+        bucket: required("--s3-bucket", "S3_BUCKET")?,
+        prefix: value("--s3-prefix").unwrap_or_default(),
+        endpoint_url: value("--s3-endpoint-url"),
+        access_key_id: required("--s3-access-key-id", "AWS_ACCESS_KEY_ID")?,
+        secret_access_key: required("--s3-secret-access-key", "AWS_SECRET_ACCESS_KEY")?,
+        region: required("--s3-region", "AWS_REGION").unwrap_or("us-east-1".to_string())
+    }))
+}
+
+// --quiet 不显示下载进度，--progress-template 自定义专辑进度条的样式，--rate-limit 限制每个域名每秒请求的图片数，
+// --dedup 专辑中内容相同的图片只保存一张，--min-free-space 下载前磁盘至少保留的可用空间 (MB)，0 表示不检查，
+// --prefer-formats 同一张图片有多种格式时优先下载的格式，如 webp,avif,jpeg 优先下载体积更小的 WebP，
+// --thumbnails 为下载的图片生成缩略图，--thumb-size 缩略图的宽度 (像素)，--archive zip|tar 将专辑直接保存为压缩包
+fn download_config() -> anyhow::Result<DownloadConfig> {
+    let args: Vec<String> = std::env::args().collect();
+    let value = |name: &str| args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).cloned();
+    let rate_limit = match value("--rate-limit") {
+        Some(rate) => match f64::from_str(&rate) {
+            Ok(rate) if rate.is_finite() && rate > 0.0 => Some(rate),
+            _ => return Err(anyhow!("--rate-limit 需要大于 0 的数字: {}", rate))
+        },
+        None => None
+    };
+    let min_free_space = match value("--min-free-space") {
+        Some(size) => match u64::from_str(&size) {
+            Ok(0) => None,
+            Ok(size) => Some(size.saturating_mul(1024 * 1024)),
+            Err(_) => return Err(anyhow!("--min-free-space 需要不小于 0 的整数 (MB): {}", size))
+        },
+        None => Some(DEFAULT_MIN_FREE_SPACE)
+    };
+    let preferred_formats = match value("--prefer-formats") {
+        Some(formats) => formats.split(',').map(ImageFormat::from_str).collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|err| anyhow!("--prefer-formats 格式错误，可选 jpeg,png,webp,avif,heic,gif: {}", err))?,
+        None => ImageFormat::DEFAULT_PREFERENCE.to_vec()
+    };
+    let thumb_size = match value("--thumb-size") {
+        Some(size) => match u32::from_str(&size) {
+            Ok(size) if size > 0 => size,
+            _ => return Err(anyhow!("--thumb-size 需要大于 0 的整数: {}", size))
+        },
+        None => DEFAULT_THUMB_SIZE
+    };
+    let archive_format = match value("--archive") {
+        Some(format) => ArchiveFormat::from_str(&format).map_err(|_| anyhow!("--archive 格式错误，可选 zip,tar: {}", format))?,
+        None => ArchiveFormat::None
+    };
+    #[allow(unused_mut)]
+    let mut config = DownloadConfig {
+        quiet: args.iter().any(|arg| arg == "--quiet"),
+        progress_template: value("--progress-template"),
+        requests_per_second_per_domain: rate_limit,
+        dedup: args.iter().any(|arg| arg == "--dedup"),
+        min_free_space,
+        preferred_formats,
+        archive_format,
+        generate_thumbnails: args.iter().any(|arg| arg == "--thumbnails"),
+        thumb_size,
+        ..DownloadConfig::default()
+    };
+    #[cfg(feature = "s3")]
+    {
+        config.upload_s3 = parse_s3_args(&args)?;
+        config.keep_local = !args.iter().any(|arg| arg == "--s3-delete-local");
+    }
+    Ok(config)
+}
+
+const SESSION_FILE: &str = "./session.json";
+
+// 需要在重启后保留的设置
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Session {
+    download_root: Option<PathBuf>
+}
+
+impl Session {
+    fn load() -> Self {
+        let content = match std::fs::read_to_string(SESSION_FILE) {
+            Ok(content) => content,
+            Err(_) => return Self::default()
+        };
+        serde_json::from_str(&content).unwrap_or_else(|err| {
+            error!("parse session file {} error: {:?}", SESSION_FILE, err);
+            Self::default()
+        })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        std::fs::write(SESSION_FILE, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+// 将开头的 ~ 展开为用户目录
+fn expand_home(path: &str) -> PathBuf {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+    match (path.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            PathBuf::from(home).join(rest.trim_start_matches(['/', '\\']))
+        }
+        _ => PathBuf::from(path)
+    }
+}
+
+fn print_info(parser: &dyn parser::Parser, searcher: Option<&AlbumSearcher>, download_root: &Path, download_config: &DownloadConfig, out: &Output) {
+    outln!(out, "解析器: {}({})", parser.parser_name(), parser.parser_code());
+    match searcher {
+        Some(searcher) => {
+            match searcher.category() {
+                Some(category) => outln!(out, "分类: {}", category),
+                None => {
+                    outln!(out, "关键字: {}", searcher.current_keyword());
+                    if let Ok(url) = searcher.search_url() {
+                        outln!(out, "搜索地址: {}", url);
+                    }
+                }
+            }
+            outln!(out, "页码: {}/{}", searcher.page(), searcher.page_count());
+            match searcher.page_range() {
+                (1, None) => {}
+                (start, Some(end)) => outln!(out, "页码范围: {}-{}", start, end),
+                (start, None) => outln!(out, "页码范围: {}-", start)
+            }
+            outln!(out, "已缓存页数: {}", searcher.cache_len());
+        }
+        None => outln!(out, "尚未搜索专辑")
+    }
+    outln!(out, "下载目录: {}", download_root.display());
+    outln!(out, "并发: 专辑 {}，每个专辑图片 {}，总连接数 {}",
+             download_config.album_concurrency, download_config.picture_concurrency, download_config.max_connections);
+}
+
+// 没有图形界面时无法打开图片查看器
+fn is_headless() -> bool {
+    if cfg!(any(target_os = "windows", target_os = "macos")) {
+        return false;
+    }
+    std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none()
+}
+
+// 使用系统默认的图片查看器打开
+fn open_picture(path: &Path) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    command.arg(path).spawn().map(|_| ())
+}
+
+fn print_albums(albums: Option<&Vec<Album>>, out: &Output) {
     match albums {
+        Some(albums) if albums.is_empty() => {
+            outln!(out, "没有搜索到匹配的专辑");
+        }
         Some(albums) => {
             for (i, album) in albums.iter().enumerate() {
-                println!("{}: {}", i + 1, album.name);
+                outln!(out, "{}: {}", i + 1, album);
             }
         }
         None => {
-            println!("没有专辑");
+            outln!(out, "没有专辑");
         }
     }
 }
 
-fn print_commands() {
-    println!("quit(q): quit tool");
-    println!("current(c): print current page's albums");
-    println!("switch(t): switch album parser(MZT, DiLi360)");
-    println!("next(n): goto next page");
-    println!("prev(p): goto prev page");
-    println!("first(f): goto first page");
-    println!("last(l): goto last page");
-    println!("jump(j): jump to page");
-    println!("download [idx](d [idx]): download album");
-    println!("search [keyword](s [keyword]): search albums with keyword");
+// 按显示宽度补齐空格，中文等宽字符占两列
+fn pad(text: &str, width: usize) -> String {
+    let len: usize = text.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum();
+    format!("{}{}", text, " ".repeat(width.saturating_sub(len)))
+}
+
+fn print_parsers(out: &Output) {
+    let parsers = parser::parsers();
+    let widths = [
+        parsers.iter().map(|parser| parser.code.len()).max().unwrap_or(0).max(4),
+        parsers.iter().map(|parser| parser.name.chars().count() * 2).max().unwrap_or(0).max(4),
+        parsers.iter().map(|parser| parser.homepage.len()).max().unwrap_or(0).max(4)
+    ];
+    outln!(out, "{}  {}  {}  说明", pad("代码", widths[0]), pad("名称", widths[1]), pad("主页", widths[2]));
+    for parser in parsers {
+        outln!(out, "{}  {}  {}  {}", pad(parser.code, widths[0]), pad(parser.name, widths[1]), pad(parser.homepage, widths[2]), parser.description);
+    }
+}
+
+fn print_commands(out: &Output) {
+    outln!(out, "quit(q): quit tool");
+    outln!(out, "current(c): print current page's albums");
+    outln!(out, "info(i): print parser, keyword, page and download settings");
+    outln!(out, "switch(t): switch album parser(MZT, DiLi360)");
+    outln!(out, "next(n): goto next page");
+    outln!(out, "prev(p): goto prev page");
+    outln!(out, "first(f): goto first page");
+    outln!(out, "last(l): goto last page");
+    outln!(out, "jump(j): jump to page");
+    outln!(out, "download [idx] [from-to](d [idx] [from-to]): download album, or only pictures from-to of album");
+    outln!(out, "download_all(da): download all albums of current page");
+    outln!(out, "series [idx] [depth](ds [idx] [depth]): download album and the following albums of its series, at most depth(default 10) more albums");
+    outln!(out, "retry(ry): download the failed pictures of last album again");
+    outln!(out, "jobs(jb): show progress of downloads running in daemon");
+    outln!(out, "preview [idx](pv [idx]): open cover of album");
+    outln!(out, "setdir [path](sd [path]): change download directory, or print current directory");
+    outln!(out, "list(ls): list albums already downloaded in download directory");
+    outln!(out, "parsefile [path](pf [path]): print picture urls parsed from a saved album html file with current parser");
+    outln!(out, "search [keyword](s [keyword]): search albums with keyword");
+    outln!(out, "search_range [start] [end](sr [start] [end]): only turn pages from start to end(no limit if omitted) in current search");
+    outln!(out, "categories [code](cat [code]): list categories, or browse albums of category");
+}
+
+// 本地保存的页面可能是 UTF-8 或 GBK 编码
+async fn read_html_file(path: &Path) -> anyhow::Result<String> {
+    let bytes = tokio::fs::read(path).await?;
+    match String::from_utf8(bytes) {
+        Ok(html) => Ok(html),
+        Err(err) => encoding::label::encoding_from_whatwg_label("gbk").unwrap()
+            .decode(err.as_bytes(), encoding::DecoderTrap::Replace)
+            .map_err(|err| anyhow!("文件解码错误: {}", err))
+    }
+}
+
+fn print_album_summaries(albums: &[AlbumSummary], out: &Output) {
+    outln!(out, "{}  {}  名称", pad("图片数", 8), pad("下载时间", 10));
+    let now = SystemTime::now();
+    for album in albums {
+        let date = match album.download_date.map(|date| now.duration_since(date).unwrap_or_default()) {
+            Some(elapsed) if elapsed.as_secs() < 86400 => "今天".to_string(),
+            Some(elapsed) => format!("{} 天前", elapsed.as_secs() / 86400),
+            None => "-".to_string()
+        };
+        outln!(out, "{}  {}  {}", pad(&album.picture_count.to_string(), 8), pad(&date, 10), album.name);
+    }
+    outln!(out, "共 {} 个专辑", albums.len());
+}
+
+fn print_download_result(ret: anyhow::Result<DownloadResult>, failed_count: usize, out: &Output) {
+    match ret {
+        Ok(result) => {
+            info!("download result: {:?}", result);
+            outln!(out, "下载 {}/{} 张图片，用时 {:.1}s，平均速度 {}", result.downloaded, result.total, result.elapsed.as_secs_f64(), result.throughput_display());
+            if result.duplicates > 0 {
+                outln!(out, "{} 张图片与已下载的图片内容相同，没有保存", result.duplicates);
+            }
+            if result.deadline_exceeded {
+                outln!(out, "超过下载时限，已下载 {}/{}", result.downloaded, result.total);
+            }
+            if failed_count > 0 {
+                outln!(out, "{} 张图片下载失败，输入 retry 重新下载", failed_count);
+            }
+            if let Some(archive) = &result.archive {
+                outln!(out, "已保存到压缩包: {}", archive.display());
+            }
+        }
+        Err(err) => {
+            error!("download error: {:?}", err);
+            match err.downcast_ref::<DownloaderError>() {
+                Some(err) if err.is_fatal() => outln!(out, "下载失败: {}", err),
+                _ => outln!(out, "下载失败，详情请查看日志")
+            }
+        }
+    }
+}
+
+fn print_albums_result(ret: anyhow::Result<Vec<anyhow::Result<DownloadResult>>>, out: &Output) {
+    match ret {
+        Ok(results) => {
+            let failed = results.iter().filter(|ret| ret.is_err()).count();
+            for (i, ret) in results.iter().enumerate() {
+                match ret {
+                    Ok(result) => info!("download album {} result: {:?}", i + 1, result),
+                    Err(err) => error!("download album {} error: {:?}", i + 1, err)
+                }
+            }
+            outln!(out, "下载完成，成功 {} 个专辑，失败 {} 个专辑", results.len() - failed, failed);
+        }
+        Err(err) => {
+            error!("download albums error: {:?}", err);
+            match err.downcast_ref::<DownloaderError>() {
+                Some(err) if err.is_fatal() => outln!(out, "下载失败: {}", err),
+                _ => outln!(out, "下载失败，详情请查看日志")
+            }
+        }
+    }
+}
+
+// 搜索结果中有图片数量时在下载开始前提示，后台服务模式下在启动后台任务前发送给客户端
+async fn print_download_hint(searcher: &mut AlbumSearcher, idx: usize, out: &Output) {
+    if let Ok(Some(albums)) = searcher.current().await {
+        if let Some(album) = idx.checked_sub(1).and_then(|index| albums.get(index)) {
+            if let Some(count) = album.picture_count_hint {
+                outln!(out, "下载专辑 {} (约 {} 张图片)", album.name, count);
+            }
+        }
+    }
 }
 
 async fn get_albums(searcher: &mut Option<AlbumSearcher>,
-                    prompt_context: &mut PromptContext, command: Command) {
+                    prompt_context: &mut PromptContext, out: &Output, command: Command) {
     match searcher {
         Some(ref mut searcher) => {
+            // 等待搜索结果时显示动画，stderr 不是终端时（如后台服务）不会绘制
+            let spinner = ProgressBar::new_spinner().with_message("Searching…");
+            spinner.enable_steady_tick(Duration::from_millis(100));
             let ret = match &command {
                 Command::CURRENT => searcher.current().await,
                 Command::FIRST => searcher.first().await,
@@ -141,26 +557,35 @@ async fn get_albums(searcher: &mut Option<AlbumSearcher>,
                 Command::JUMP(page) => searcher.jump(page).await,
                 _ => Err(anyhow!("not support command: {:?}", &command))
             };
+            // 先清除动画再输出结果，避免输出交错
+            spinner.finish_and_clear();
 
             match ret {
                 Ok(albums) => {
-                    print_albums(albums);
+                    print_albums(albums, out);
+                    // 切换解析器后仍在浏览之前的搜索结果，提示符显示搜索器实际使用的解析器
+                    prompt_context.parser = searcher.current_parser_name().to_string();
                     prompt_context.current = Some(searcher.page());
                     prompt_context.total_page = Some(searcher.page_count());
                 },
                 Err(err) => {
                     error!("get albums error: {:?}", err);
-                    println!("获取专辑失败，详情请查看日志");
+                    match err.downcast_ref::<DownloaderError>() {
+                        // 页面结构变化时搜索结果无法解析，与没有搜索到专辑区分开
+                        Some(err @ DownloaderError::MarkupChanged { .. }) => outln!(out, "获取专辑失败: {}", err),
+                        _ => outln!(out, "获取专辑失败，详情请查看日志")
+                    }
                 }
             }
         }
         None => {
             error!("searcher is init");
-            println!("请先搜索专辑");
+            outln!(out, "请先搜索专辑");
         }
     }
 }
 
+#[derive(Clone)]
 struct PromptContext {
     keyword: Option<String>,
     current: Option<u32>,
@@ -181,6 +606,15 @@ impl PromptContext {
         }
     }
 
+    fn with_default_parser() -> Self {
+        let code = parser::default_parser_code();
+        let name = parser::parsers().into_iter()
+            .find(|info| info.code == code)
+            .map(|info| info.name.to_string())
+            .unwrap_or(code.to_string());
+        Self::new(name)
+    }
+
     fn new(parser: String) -> Self {
         Self {
             keyword: None,
@@ -191,134 +625,951 @@ impl PromptContext {
     }
 }
 
-#[tokio::main]
-async fn main() {
-    create_dir_all("./log").await.unwrap();
-
-    let file_appender = tracing_appender::rolling::never("./log", "downloader.log");
-    let (non_blocking_appender, _guard) = NonBlocking::new(file_appender);
-    let file_layer = layer()
-        .with_writer(non_blocking_appender)
-        .with_ansi(false)
-        .with_filter(tracing_subscriber::filter::LevelFilter::INFO);
-    let subscriber = registry().with(file_layer);
-    tracing::subscriber::set_global_default(subscriber).unwrap();
-
-    let mut searcher_opt = None;
-    let mut searcher = &mut searcher_opt;
-    let mut parser = parser::default_parser();
-    let mut prompt_context = PromptContext::new(parser.parser_name());
-
-    loop {
-        print!("{}", prompt_context.prompt());
-        let _ = std::io::stdout().flush();
+// 命令行的会话状态，交互模式和后台服务模式共用
+#[derive(Clone)]
+struct Cli {
+    download_config: DownloadConfig,
+    // --album-cache 开启时搜索结果缓存到磁盘，重启后不用重新请求
+    album_cache: Option<AlbumCache>,
+    session: Session,
+    download_root: PathBuf,
+    searcher: Option<AlbumSearcher>,
+    parser: Arc<dyn parser::Parser>,
+    prompt_context: PromptContext
+}
 
-        let mut line = String::new();
-        if let Err(err) = std::io::stdin().read_line(&mut line) {
-            error!("get input error: {}", err);
-            println!("获取输入错误");
+impl Cli {
+    fn new(download_config: DownloadConfig) -> Self {
+        let session = Session::load();
+        let download_root = session.download_root.clone().unwrap_or(PathBuf::from(AlbumSearcher::DEFAULT_DOWNLOAD_ROOT));
+        let album_cache = std::env::args().any(|arg| arg == "--album-cache")
+            .then(|| AlbumCache::new(AlbumCache::DEFAULT_DIR, AlbumCache::DEFAULT_TTL));
+        Self {
+            download_config,
+            album_cache,
+            session,
+            download_root,
+            searcher: None,
+            parser: parser::default_parser(),
+            prompt_context: PromptContext::with_default_parser()
         }
+    }
 
-        match line.parse() {
-            Ok(cmd) => {
-                info!("input {:?} command", cmd);
-                match cmd {
-                    Command::HELP => {
-                        print_commands();
-                    }
-                    Command::SWITCH(parser_code) => {
-                        match parser_code {
-                            Some(code) => {
-                                match parser::parse(&code) {
-                                    Ok(new_parser) => {
-                                        parser = new_parser;
-                                        prompt_context = PromptContext::new(parser.parser_name());
-                                        println!("切换到解析器成功");
-                                        info!("switch to {} parser successful", code);
-                                    }
-                                    Err(err) => {
-                                        error!("switch parser error: {:?}", err);
-                                        println!("切换解析器失败，详情请查看日志");
-                                    }
+    // 执行一条命令，输出写入 out，返回是否退出
+    async fn execute(&mut self, cmd: Command, out: &Output) -> bool {
+        let Cli { download_config, album_cache, session, download_root, searcher, parser, prompt_context } = self;
+        match cmd {
+            Command::HELP => {
+                print_commands(out);
+            }
+            Command::INFO => {
+                print_info(&**parser, searcher.as_ref(), download_root, download_config, out);
+            }
+            Command::SWITCH(parser_code) => {
+                match parser_code {
+                    Some(code) => {
+                        match parser::parse(&code, None) {
+                            Ok(new_parser) => {
+                                *parser = new_parser;
+                                *prompt_context = PromptContext::new(parser.parser_name());
+                                outln!(out, "切换到解析器成功");
+                                info!("switch to {} parser successful", code);
+                            }
+                            Err(err) => {
+                                error!("switch parser error: {:?}", err);
+                                match err.downcast_ref::<DownloaderError>() {
+                                    Some(err @ DownloaderError::UnsupportedParser { .. }) => outln!(out, "切换解析器失败: {}", err),
+                                    _ => outln!(out, "切换解析器失败，详情请查看日志")
                                 }
                             }
-                            None => {
-                                let parsers = parser::parsers();
-                                for (i, parser) in parsers.iter().enumerate() {
-                                    println!("{}. {}({})", i, parser.1, parser.0);
+                        }
+                    }
+                    None => {
+                        print_parsers(out);
+                    }
+                }
+            }
+            Command::CATEGORIES(category) => {
+                match category {
+                    Some(category) => {
+                        info!("browse category {}", &category);
+                        let mut new_searcher = AlbumSearcher::with_category(parser.clone(), &category, AlbumSearcher::DEFAULT_PAGE_SIZE);
+                        new_searcher.set_download_config(download_config.clone());
+                        new_searcher.set_download_root(&*download_root);
+                        new_searcher.set_album_cache(album_cache.clone());
+                        *searcher = Some(new_searcher);
+                        prompt_context.keyword = Some(format!("#{}", category.to_lowercase()));
+                        get_albums(searcher, prompt_context, out, Command::NEXT).await;
+                    }
+                    None => {
+                        match parser.list_categories().await {
+                            Ok(categories) if categories.is_empty() => {
+                                outln!(out, "当前解析器不支持分类");
+                            }
+                            Ok(categories) => {
+                                for (code, name) in categories {
+                                    outln!(out, "{}({})", name, code);
                                 }
                             }
+                            Err(err) => {
+                                error!("list categories error: {:?}", err);
+                                outln!(out, "获取分类失败，详情请查看日志");
+                            }
                         }
                     }
-                    Command::SEARCH(keyword) => {
-                        info!("search {}", &keyword);
-                        *searcher = Some(AlbumSearcher::new(parser.clone(), &keyword, AlbumSearcher::DEFAULT_PAGE_SIZE));
-                        prompt_context.keyword = Some(keyword);
-                        get_albums(&mut searcher, &mut prompt_context, Command::NEXT).await;
+                }
+            }
+            Command::SEARCH(keyword) => {
+                info!("search {}", &keyword);
+                let mut new_searcher = AlbumSearcher::new(parser.clone(), &keyword, AlbumSearcher::DEFAULT_PAGE_SIZE);
+                new_searcher.set_download_config(download_config.clone());
+                new_searcher.set_download_root(&*download_root);
+                new_searcher.set_album_cache(album_cache.clone());
+                *searcher = Some(new_searcher);
+                prompt_context.keyword = Some(keyword);
+                get_albums(searcher, prompt_context, out, Command::NEXT).await;
+            }
+            Command::SearchRange(start, end) => {
+                info!("search range {}-{:?}", start, end);
+                if let Some(searcher) = searcher.as_mut() {
+                    searcher.set_page_range(start, end);
+                }
+                get_albums(searcher, prompt_context, out, Command::NEXT).await;
+            }
+            Command::CURRENT => {
+                get_albums(searcher, prompt_context, out, Command::CURRENT).await;
+            }
+            Command::FIRST => {
+                get_albums(searcher, prompt_context, out, Command::FIRST).await;
+            }
+            Command::LAST => {
+                get_albums(searcher, prompt_context, out, Command::LAST).await;
+            }
+            Command::PREV => {
+                get_albums(searcher, prompt_context, out, Command::PREV).await;
+            }
+            Command::NEXT => {
+                get_albums(searcher, prompt_context, out, Command::NEXT).await;
+            }
+            Command::JUMP(page) => {
+                get_albums(searcher, prompt_context, out, Command::JUMP(page)).await;
+            }
+            Command::DOWNLOAD(idx, range) => {
+                match searcher {
+                    Some(searcher) => {
+                        let ret = match range {
+                            Some((from, to)) => searcher.download_range(idx, from, to).await,
+                            None => {
+                                print_download_hint(searcher, idx, out).await;
+                                searcher.download(idx).await
+                            }
+                        };
+                        print_download_result(ret, searcher.failed_count(), out);
+                    }
+                    None =>{
+                        error!("searcher not init");
+                        outln!(out, "请先搜索专辑");
+                    }
+                }
+            }
+            Command::RETRY => {
+                match searcher {
+                    Some(searcher) if searcher.failed_count() > 0 => {
+                        let ret = searcher.retry_failed().await;
+                        print_download_result(ret, searcher.failed_count(), out);
                     }
-                    Command::CURRENT => {
-                        get_albums(&mut searcher, &mut prompt_context, Command::CURRENT).await;
+                    _ => outln!(out, "没有需要重试的图片")
+                }
+            }
+            Command::JOBS => {
+                outln!(out, "下载在前台执行，只有后台服务模式下有后台下载任务");
+            }
+            Command::DownloadAll => {
+                match searcher {
+                    Some(searcher) => {
+                        print_albums_result(searcher.download_all().await, out);
                     }
-                    Command::FIRST => {
-                        get_albums(&mut searcher, &mut prompt_context, Command::FIRST).await;
+                    None =>{
+                        error!("searcher not init");
+                        outln!(out, "请先搜索专辑");
                     }
-                    Command::LAST => {
-                        get_albums(&mut searcher, &mut prompt_context, Command::LAST).await;
+                }
+            }
+            Command::SERIES(idx, depth) => {
+                match searcher {
+                    Some(searcher) => {
+                        print_albums_result(searcher.download_series(idx, depth).await, out);
                     }
-                    Command::PREV => {
-                        get_albums(&mut searcher, &mut prompt_context, Command::PREV).await;
+                    None =>{
+                        error!("searcher not init");
+                        outln!(out, "请先搜索专辑");
                     }
-                    Command::NEXT => {
-                        get_albums(&mut searcher, &mut prompt_context, Command::NEXT).await;
+                }
+            }
+            Command::PREVIEW(idx) => {
+                match searcher {
+                    Some(searcher) => {
+                        if is_headless() {
+                            match searcher.preview_url(idx).await {
+                                Ok(url) => outln!(out, "封面地址: {}", url),
+                                Err(err) => {
+                                    error!("get preview url error: {:?}", err);
+                                    outln!(out, "获取封面失败，详情请查看日志");
+                                }
+                            }
+                        } else {
+                            match searcher.preview(idx).await {
+                                Ok(path) => {
+                                    if let Err(err) = open_picture(&path) {
+                                        error!("open picture {:?} error: {:?}", path, err);
+                                        outln!(out, "打开图片失败，封面已保存到: {}", path.display());
+                                    }
+                                }
+                                Err(err) => {
+                                    error!("preview error: {:?}", err);
+                                    outln!(out, "获取封面失败，详情请查看日志");
+                                }
+                            }
+                        }
                     }
-                    Command::JUMP(page) => {
-                        get_albums(&mut searcher, &mut prompt_context, Command::JUMP(page)).await;
+                    None =>{
+                        error!("searcher not init");
+                        outln!(out, "请先搜索专辑");
                     }
-                    Command::DOWNLOAD(idx) => {
-                        match &mut searcher {
-                            Some(ref mut searcher) => {
-                                if let Err(err) = searcher.download(idx).await {
-                                    error!("download error: {:?}", err);
-                                    println!("下载失败，详情请查看日志");
+                }
+            }
+            Command::SETDIR(path) => {
+                match path {
+                    Some(path) => {
+                        let path = expand_home(&path);
+                        let ret = match create_dir_all(&path).await {
+                            Ok(_) => lmpic_downloader::check_writable(&path).await,
+                            Err(err) => Err(DownloaderError::from_io(err, &path).into())
+                        };
+                        match ret {
+                            Ok(_) => {
+                                info!("set download root to {:?}", path);
+                                *download_root = path;
+                                if let Some(searcher) = searcher.as_mut() {
+                                    searcher.set_download_root(&*download_root);
                                 }
+                                session.download_root = Some(download_root.clone());
+                                if let Err(err) = session.save() {
+                                    error!("save session error: {:?}", err);
+                                    outln!(out, "保存下载目录失败，重启后需要重新设置");
+                                }
+                                outln!(out, "下载目录: {}", download_root.display());
                             }
-                            None =>{
-                                error!("searcher not init");
-                                println!("请先搜索专辑");
+                            Err(err) => {
+                                error!("set download root error: {:?}", err);
+                                outln!(out, "设置下载目录失败: {}", err);
                             }
                         }
                     }
-                    Command::ArgumentErr(err) => {
-                        error!("command argument error: {}", err);
-                        println!("命令参数错误: {}", err);
+                    None => {
+                        outln!(out, "下载目录: {}", download_root.display());
+                    }
+                }
+            }
+            Command::LIST => {
+                match util::scan_albums(download_root).await {
+                    Ok(albums) if albums.is_empty() => outln!(out, "下载目录中没有专辑: {}", download_root.display()),
+                    Ok(albums) => print_album_summaries(&albums, out),
+                    Err(err) => {
+                        error!("scan albums in {:?} error: {:?}", download_root, err);
+                        outln!(out, "读取下载目录失败，详情请查看日志");
                     }
-                    Command::UNKNOWN => {
-                        error!("unknown command: {}", line.trim());
-                        println!("未知的命令: {}", line.trim());
-                        print_commands();
+                }
+            }
+            Command::ParseFile(path) => {
+                let path = expand_home(&path);
+                match read_html_file(&path).await.and_then(|html| parser.parse_pictures_from_html(&html, parser.homepage())) {
+                    Ok(pictures) => {
+                        for picture in &pictures {
+                            outln!(out, "{}", picture);
+                        }
+                        outln!(out, "共 {} 张图片", pictures.len());
                     }
-                    Command::QUIT => {
-                        println!("bye bye.");
-                        return;
+                    Err(err) => {
+                        error!("parse file {:?} error: {:?}", path, err);
+                        outln!(out, "解析文件失败: {}", err);
                     }
-                    Command::NONE => {}
                 }
             }
+            Command::ArgumentErr(err) => {
+                error!("command argument error: {}", err);
+                outln!(out, "命令参数错误: {}", err);
+            }
+            Command::UNKNOWN(input) => {
+                error!("unknown command: {}", input);
+                outln!(out, "未知的命令: {}", input);
+                print_commands(out);
+            }
+            Command::QUIT => {
+                outln!(out, "bye bye.");
+                return true;
+            }
+            Command::NONE => {}
+        }
+
+        false
+    }
+}
+
+// 后台服务返回给客户端的结果，命令的输出每行发送一次，执行结束后发送 Done
+#[derive(Serialize, Deserialize)]
+enum Response {
+    Line(String),
+    Done { prompt: String, quit: bool }
+}
+
+// 默认的后台服务地址，可以通过 --socket 修改
+const DEFAULT_SOCKET: &str = "~/.run/mzt-downloader.sock";
+
+fn socket_path() -> PathBuf {
+    let args: Vec<String> = std::env::args().collect();
+    let socket = args.iter().position(|arg| arg == "--socket").and_then(|i| args.get(i + 1)).cloned();
+    expand_home(socket.as_deref().unwrap_or(DEFAULT_SOCKET))
+}
+
+// fork 后父进程退出，子进程脱离终端运行，需要在启动 tokio 运行时之前调用
+// 标准输出和错误输出重定向到 out，不能与日志共用一个文件，否则会与日志的写入交错
+#[cfg(unix)]
+fn daemonize(out: &Path) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    if let Some(dir) = out.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let out = std::fs::OpenOptions::new().create(true).append(true).open(out)?;
+    let null = std::fs::File::open("/dev/null")?;
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error()),
+        0 => {}
+        pid => {
+            println!("后台服务已启动，进程 ID: {}", pid);
+            std::process::exit(0);
+        }
+    }
+
+    // 子进程只有一个线程，这些调用只操作本进程的文件描述符
+    unsafe {
+        if libc::setsid() == -1
+            || libc::dup2(null.as_raw_fd(), 0) == -1
+            || libc::dup2(out.as_raw_fd(), 1) == -1
+            || libc::dup2(out.as_raw_fd(), 2) == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// 后台服务中的下载任务，在会话的副本上下载，下载期间其它命令不需要等待
+#[cfg(unix)]
+struct Job {
+    description: String,
+    // 最近一次的下载进度 (已完成, 总数)
+    progress: Option<(usize, usize)>,
+    output: Vec<String>,
+    finished: bool
+}
+
+// 各个连接共用同一个会话，会话的锁只在复制和写回时持有，不会在请求网络时持有
+#[cfg(unix)]
+struct Daemon {
+    cli: tokio::sync::Mutex<Cli>,
+    jobs: Arc<std::sync::Mutex<Vec<Job>>>
+}
+
+#[cfg(unix)]
+impl Daemon {
+    fn new(cli: Cli) -> Self {
+        Self {
+            cli: tokio::sync::Mutex::new(cli),
+            jobs: Arc::new(std::sync::Mutex::new(Vec::new()))
+        }
+    }
+
+    // 下载命令作为后台任务执行，其它命令在会话的副本上执行后写回，同时执行的命令以最后结束的为准
+    async fn execute(self: &Arc<Self>, cmd: Command, out: &Output) -> bool {
+        if let Command::JOBS = cmd {
+            self.print_jobs(out);
+            return false;
+        }
+
+        let mut cli = self.cli.lock().await.clone();
+        let background = match (&cmd, cli.searcher.as_ref()) {
+            (Command::DOWNLOAD(..) | Command::DownloadAll | Command::SERIES(..), Some(_)) => true,
+            (Command::RETRY, Some(searcher)) => searcher.failed_count() > 0,
+            _ => false
+        };
+        if background {
+            if let (Command::DOWNLOAD(idx, None), Some(searcher)) = (&cmd, cli.searcher.as_mut()) {
+                print_download_hint(searcher, *idx, out).await;
+            }
+            let id = self.start_job(cli, cmd);
+            outln!(out, "已在后台开始下载，任务 ID: {}，输入 jobs 查看进度", id);
+            return false;
+        }
+
+        let quit = cli.execute(cmd, out).await;
+        *self.cli.lock().await = cli;
+        quit
+    }
+
+    // 返回任务 ID，任务的输出和进度保存在任务中，通过 jobs 命令查看
+    fn start_job(self: &Arc<Self>, mut cli: Cli, cmd: Command) -> usize {
+        let description = match &cmd {
+            Command::DOWNLOAD(idx, None) => format!("下载专辑 {}", idx),
+            Command::DOWNLOAD(idx, Some((from, to))) => format!("下载专辑 {} 的第 {}-{} 张图片", idx, from, to),
+            Command::SERIES(idx, depth) => format!("下载专辑 {} 及其后最多 {} 个系列专辑", idx, depth),
+            Command::DownloadAll => "下载当前页的所有专辑".to_string(),
+            _ => "重新下载失败的图片".to_string()
+        };
+        let id = {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.push(Job { description, progress: None, output: Vec::new(), finished: false });
+            jobs.len()
+        };
+
+        let (progress_sender, mut progress_receiver) = unbounded_channel();
+        if let Some(searcher) = cli.searcher.as_mut() {
+            searcher.set_download_config(DownloadConfig {
+                progress_events: Some(progress_sender),
+                ..searcher.download_config().clone()
+            });
+        }
+        let jobs = self.jobs.clone();
+        tokio::spawn(async move {
+            while let Some(progress) = progress_receiver.recv().await {
+                jobs.lock().unwrap()[id - 1].progress = Some((progress.done, progress.total));
+            }
+        });
+        let (sender, mut receiver) = unbounded_channel();
+        let jobs = self.jobs.clone();
+        let output = tokio::spawn(async move {
+            while let Some(line) = receiver.recv().await {
+                jobs.lock().unwrap()[id - 1].output.push(line);
+            }
+        });
+
+        let daemon = self.clone();
+        tokio::spawn(async move {
+            info!("daemon job {} started: {:?}", id, cmd);
+            cli.execute(cmd, &Output::Channel(sender)).await;
+            let _ = output.await;
+            // 下载失败的图片同步回会话，retry 命令可以重试
+            if let Some(job_searcher) = cli.searcher.as_ref() {
+                if let Some(searcher) = daemon.cli.lock().await.searcher.as_mut() {
+                    searcher.sync_failed(job_searcher);
+                }
+            }
+            daemon.jobs.lock().unwrap()[id - 1].finished = true;
+            info!("daemon job {} finished", id);
+        });
+        id
+    }
+
+    fn print_jobs(&self, out: &Output) {
+        let jobs = self.jobs.lock().unwrap();
+        if jobs.is_empty() {
+            outln!(out, "没有后台下载任务");
+            return;
+        }
+        for (i, job) in jobs.iter().enumerate() {
+            match (job.finished, job.progress) {
+                (true, _) => outln!(out, "{}: {} - 已结束", i + 1, job.description),
+                (false, Some((done, total))) => outln!(out, "{}: {} - 下载中 {}/{}", i + 1, job.description, done, total),
+                (false, None) => outln!(out, "{}: {} - 准备中", i + 1, job.description)
+            }
+            for line in &job.output {
+                outln!(out, "    {}", line);
+            }
+        }
+    }
+}
+
+// 每个连接发送一行 JSON 格式的命令，返回若干行 JSON 格式的结果
+#[cfg(unix)]
+async fn serve(listener: UnixListener, cli: Cli) {
+    let daemon = Arc::new(Daemon::new(cli));
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                error!("accept client error: {:?}", err);
+                continue;
+            }
+        };
+        let daemon = daemon.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_client(stream, daemon).await {
+                error!("handle client error: {:?}", err);
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn send(writer: &mut OwnedWriteHalf, response: &Response) -> anyhow::Result<()> {
+    let mut response = serde_json::to_string(response)?;
+    response.push('\n');
+    writer.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn handle_client(stream: UnixStream, daemon: Arc<Daemon>) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let (sender, mut receiver) = unbounded_channel();
+        let execute = {
+            let daemon = daemon.clone();
+            async move {
+                let out = Output::Channel(sender);
+                match serde_json::from_str::<Command>(&line) {
+                    Ok(cmd) => {
+                        info!("client input {:?} command", cmd);
+                        daemon.execute(cmd, &out).await
+                    }
+                    Err(err) => {
+                        error!("parse client command {} error: {:?}", line, err);
+                        outln!(out, "解析命令失败: {}", err);
+                        false
+                    }
+                }
+            }
+        };
+        // 命令执行结束后发送端被释放，转发随之结束
+        let forward = async {
+            while let Some(line) = receiver.recv().await {
+                send(&mut writer, &Response::Line(line)).await?;
+            }
+            anyhow::Ok(())
+        };
+        let (quit, forwarded) = tokio::join!(execute, forward);
+        forwarded?;
+        let prompt = daemon.cli.lock().await.prompt_context.prompt();
+        send(&mut writer, &Response::Done { prompt, quit }).await?;
+        if quit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// 客户端：发送命令，收到的输出写入 out，返回新的提示符以及是否退出
+#[cfg(unix)]
+async fn request(lines: &mut Lines<BufReader<OwnedReadHalf>>, writer: &mut OwnedWriteHalf, cmd: &Command, out: &Output) -> anyhow::Result<(String, bool)> {
+    let mut request = serde_json::to_string(cmd)?;
+    request.push('\n');
+    writer.write_all(request.as_bytes()).await?;
+    loop {
+        let line = lines.next_line().await?.ok_or(anyhow!("后台服务已断开连接"))?;
+        match serde_json::from_str(&line)? {
+            Response::Line(line) => out.line(line),
+            Response::Done { prompt, quit } => return Ok((prompt, quit))
+        }
+    }
+}
+
+#[cfg(unix)]
+type Connection = (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf);
+
+// --daemon 后台运行并监听 --socket 指定的地址，--client 连接后台服务执行命令
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let daemon = args.iter().any(|arg| arg == "--daemon");
+    #[cfg(unix)]
+    if daemon {
+        let socket = socket_path();
+        if std::os::unix::net::UnixStream::connect(&socket).is_ok() {
+            println!("后台服务已在运行: {}", socket.display());
+            return;
+        }
+        if let Err(err) = daemonize(Path::new("./log/daemon.out")) {
+            println!("启动后台服务失败: {}", err);
+            return;
+        }
+    }
+    #[cfg(not(unix))]
+    if daemon || args.iter().any(|arg| arg == "--client") {
+        println!("当前系统不支持后台服务模式");
+        return;
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(run(daemon, args.iter().any(|arg| arg == "--client")));
+}
+
+#[allow(unused_variables)]
+async fn run(daemon: bool, client: bool) {
+    create_dir_all("./log").await.unwrap();
+
+    let file_appender = tracing_appender::rolling::never("./log", "downloader.log");
+    let (non_blocking_appender, _guard) = NonBlocking::new(file_appender);
+    let file_layer = layer()
+        .with_writer(non_blocking_appender)
+        .with_ansi(false)
+        .with_filter(util::log_filter());
+    let subscriber = registry().with(file_layer);
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    #[cfg(unix)]
+    let mut connection: Option<Connection> = None;
+    #[cfg(unix)]
+    if client {
+        let socket = socket_path();
+        match UnixStream::connect(&socket).await {
+            Ok(stream) => {
+                let (reader, writer) = stream.into_split();
+                connection = Some((BufReader::new(reader).lines(), writer));
+            }
+            Err(err) => {
+                error!("connect to {:?} error: {:?}", socket, err);
+                println!("连接后台服务失败: {}", err);
+                return;
+            }
+        }
+    }
+
+    let download_config = match download_config() {
+        Ok(config) => config,
+        Err(err) => {
+            println!("参数错误: {}", err);
+            return;
+        }
+    };
+    let mut cli = Cli::new(download_config);
+    // 下载时不会自动创建下载目录，默认目录在启动时创建，设置的目录不存在时下载会报错
+    if !client && cli.session.download_root.is_none() {
+        if let Err(err) = create_dir_all(&cli.download_root).await {
+            error!("create download root {:?} error: {:?}", cli.download_root, err);
+        }
+    }
+
+    #[cfg(unix)]
+    if daemon {
+        let socket = socket_path();
+        if let Some(dir) = socket.parent() {
+            let _ = create_dir_all(dir).await;
+        }
+        // 能连接上说明已有后台服务在监听，不能删除它的地址文件，连接失败时是上次异常退出时遗留的地址文件，删除后才能监听
+        if UnixStream::connect(&socket).await.is_ok() {
+            error!("daemon already listening on {:?}", socket);
+            return;
+        }
+        let _ = tokio::fs::remove_file(&socket).await;
+        match UnixListener::bind(&socket) {
+            Ok(listener) => {
+                info!("daemon listening on {:?}", socket);
+                serve(listener, cli).await;
+            }
+            Err(err) => error!("listen on {:?} error: {:?}", socket, err)
+        }
+        return;
+    }
+
+    let mut prompt = cli.prompt_context.prompt();
+    #[cfg(unix)]
+    if let Some((lines, writer)) = connection.as_mut() {
+        match request(lines, writer, &Command::NONE, &Output::Stdout).await {
+            Ok((response_prompt, _)) => prompt = response_prompt,
+            Err(err) => {
+                println!("连接后台服务失败: {}", err);
+                return;
+            }
+        }
+    }
+
+    loop {
+        print!("{}", prompt);
+        let _ = std::io::stdout().flush();
+
+        let mut line = String::new();
+        if let Err(err) = std::io::stdin().read_line(&mut line) {
+            error!("get input error: {}", err);
+            println!("获取输入错误");
+        }
+
+        let cmd = match line.parse::<Command>() {
+            Ok(cmd) => cmd,
             Err(err) => {
                 error!("parse {} command error: {:?}", line, err);
                 println!("解析命令失败: {:?}", err);
+                continue;
             }
+        };
+        info!("input {:?} command", cmd);
+
+        #[cfg(unix)]
+        if let Some((lines, writer)) = connection.as_mut() {
+            match request(lines, writer, &cmd, &Output::Stdout).await {
+                Ok((response_prompt, quit)) => {
+                    prompt = response_prompt;
+                    if quit {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    error!("request daemon error: {:?}", err);
+                    println!("请求后台服务失败: {}", err);
+                    return;
+                }
+            }
+            continue;
         }
-    }
 
+        let quit = cli.execute(cmd, &Output::Stdout).await;
+        if quit {
+            return;
+        }
+        prompt = cli.prompt_context.prompt();
+    }
 }
 
+// 集成测试中的模拟站点
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod common;
+
 #[cfg(test)]
 mod tests {
-    use crate::Command;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use lmpic_downloader::parser::ParserBuilder;
+
+    use crate::{Cli, Command, Output, common};
+
+    fn output() -> (Output, UnboundedReceiver<String>) {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        (Output::Channel(sender), receiver)
+    }
+
+    // 取出已经输出的所有行
+    fn received(receiver: &mut UnboundedReceiver<String>) -> Vec<String> {
+        std::iter::from_fn(|| receiver.try_recv().ok()).collect()
+    }
+
+    // 使用模拟的地理 360 站点，下载到 root
+    fn dili360_cli(server: &wiremock::MockServer, root: &Path) -> Cli {
+        let mut cli = Cli::new(lmpic_downloader::DownloadConfig { quiet: true, ..lmpic_downloader::DownloadConfig::default() });
+        cli.parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+        cli.download_root = root.to_path_buf();
+        cli
+    }
 
     #[test]
     fn test_print_enum() {
         println!("enum {:?}", Command::PREV);
     }
+
+    #[test]
+    fn test_parse_download_range() {
+        assert!(matches!("d 2 3-5".parse::<Command>().unwrap(), Command::DOWNLOAD(2, Some((3, 5)))));
+        assert!(matches!("download 2".parse::<Command>().unwrap(), Command::DOWNLOAD(2, None)));
+        assert!(matches!("d 2 3".parse::<Command>().unwrap(), Command::ArgumentErr(_)));
+        assert!(matches!("da".parse::<Command>().unwrap(), Command::DownloadAll));
+        assert!(matches!("ry".parse::<Command>().unwrap(), Command::RETRY));
+        assert!(matches!("ds 2".parse::<Command>().unwrap(), Command::SERIES(2, 10)));
+        assert!(matches!("series 2 3".parse::<Command>().unwrap(), Command::SERIES(2, 3)));
+        assert!(matches!("ds".parse::<Command>().unwrap(), Command::ArgumentErr(_)));
+    }
+
+    #[test]
+    fn test_parse_search_range() {
+        assert!(matches!("sr 2 5".parse::<Command>().unwrap(), Command::SearchRange(2, Some(5))));
+        assert!(matches!("search_range 51".parse::<Command>().unwrap(), Command::SearchRange(51, None)));
+        assert!(matches!("sr 5 2".parse::<Command>().unwrap(), Command::ArgumentErr(_)));
+        assert!(matches!("sr 0 2".parse::<Command>().unwrap(), Command::ArgumentErr(_)));
+        assert!(matches!("sr a".parse::<Command>().unwrap(), Command::ArgumentErr(_)));
+        assert!(matches!("sr".parse::<Command>().unwrap(), Command::ArgumentErr(_)));
+    }
+
+    #[test]
+    fn test_parse_preview() {
+        assert!(matches!("pv 2".parse::<Command>().unwrap(), Command::PREVIEW(2)));
+        assert!(matches!("preview 2".parse::<Command>().unwrap(), Command::PREVIEW(2)));
+        assert!(matches!("pv".parse::<Command>().unwrap(), Command::ArgumentErr(_)));
+        assert!(matches!("pv a".parse::<Command>().unwrap(), Command::ArgumentErr(_)));
+    }
+
+    #[cfg(feature = "s3")]
+    #[test]
+    fn test_parse_s3_args() {
+        let args: Vec<String> = ["cli", "--upload-s3", "--s3-bucket", "pictures", "--s3-prefix", "backup",
+            "--s3-access-key-id", "id", "--s3-secret-access-key", "secret", "--s3-region", "cn-north-1"]
+            .iter().map(|arg| arg.to_string()).collect();
+        let config = crate::parse_s3_args(&args).unwrap().unwrap();
+        assert_eq!(config.bucket, "pictures");
+        assert_eq!(config.prefix, "backup");
+        assert_eq!(config.region, "cn-north-1");
+        assert!(config.endpoint_url.is_none());
+        assert!(crate::parse_s3_args(&args[..1]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_file_command() {
+        match "pf ~/Saved Pages/Album.html".parse::<Command>().unwrap() {
+            Command::ParseFile(path) => assert_eq!(path, "~/Saved Pages/Album.html"),
+            cmd => panic!("unexpected command: {cmd:?}")
+        }
+        assert!(matches!("parsefile".parse::<Command>().unwrap(), Command::ArgumentErr(_)));
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert!(matches!("ls".parse::<Command>().unwrap(), Command::LIST));
+        assert!(matches!("LIST".parse::<Command>().unwrap(), Command::LIST));
+    }
+
+    #[test]
+    fn test_parse_setdir() {
+        match "sd /tmp/albums".parse::<Command>().unwrap() {
+            Command::SETDIR(Some(path)) => assert_eq!(path, "/tmp/albums"),
+            cmd => panic!("unexpected command: {cmd:?}")
+        }
+        match "setdir /tmp/My Albums ".parse::<Command>().unwrap() {
+            Command::SETDIR(Some(path)) => assert_eq!(path, "/tmp/My Albums"),
+            cmd => panic!("unexpected command: {cmd:?}")
+        }
+        assert!(matches!("sd".parse::<Command>().unwrap(), Command::SETDIR(None)));
+    }
+
+    #[test]
+    fn test_expand_home() {
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(crate::expand_home("~/albums"), std::path::Path::new(&home).join("albums"));
+        assert_eq!(crate::expand_home("~"), std::path::Path::new(&home));
+        assert_eq!(crate::expand_home("/tmp/~albums"), std::path::Path::new("/tmp/~albums"));
+    }
+
+    #[test]
+    fn test_command_json() {
+        let json = serde_json::to_string(&"d 2 3-5".parse::<Command>().unwrap()).unwrap();
+        assert!(matches!(serde_json::from_str::<Command>(&json).unwrap(), Command::DOWNLOAD(2, Some((3, 5)))));
+        match "hello world".parse::<Command>().unwrap() {
+            Command::UNKNOWN(input) => assert_eq!(input, "hello world"),
+            cmd => panic!("unexpected command: {cmd:?}")
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_daemon_client() {
+        use tokio::io::AsyncBufReadExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("daemon.sock");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        tokio::spawn(crate::serve(listener, crate::Cli::new(lmpic_downloader::DownloadConfig::default())));
+
+        let (reader, mut writer) = tokio::net::UnixStream::connect(&socket).await.unwrap().into_split();
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        let (out, mut receiver) = output();
+        let (_, quit) = crate::request(&mut lines, &mut writer, &Command::HELP, &out).await.unwrap();
+        assert!(!quit);
+        assert!(received(&mut receiver).iter().any(|line| line.starts_with("quit(q)")));
+        let (_, quit) = crate::request(&mut lines, &mut writer, &Command::QUIT, &out).await.unwrap();
+        assert!(quit);
+        assert!(lines.next_line().await.unwrap().is_none());
+    }
+
+    // 在后台服务中搜索后下载，下载作为后台任务执行，不等待下载结束就返回
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_daemon_download_job() {
+        use tokio::io::AsyncBufReadExt;
+
+        let server = common::dili360_server_with_pictures(3).await;
+        let root = tempfile::tempdir().unwrap();
+        let socket = root.path().join("daemon.sock");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        tokio::spawn(crate::serve(listener, dili360_cli(&server, root.path())));
+
+        let (reader, mut writer) = tokio::net::UnixStream::connect(&socket).await.unwrap().into_split();
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        let (out, mut receiver) = output();
+        crate::request(&mut lines, &mut writer, &Command::SEARCH("云南".to_string()), &out).await.unwrap();
+        assert!(received(&mut receiver)[0].starts_with("1: 云南大理"));
+        crate::request(&mut lines, &mut writer, &Command::DOWNLOAD(1, None), &out).await.unwrap();
+        assert_eq!(received(&mut receiver), ["下载专辑 云南大理 (约 42 张图片)", "已在后台开始下载，任务 ID: 1，输入 jobs 查看进度"]);
+
+        // 查看进度不等待下载，结束后显示下载结果
+        let finished = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                crate::request(&mut lines, &mut writer, &Command::JOBS, &out).await.unwrap();
+                let jobs = received(&mut receiver);
+                assert!(jobs[0].starts_with("1: 下载专辑 1 - "));
+                if jobs[0].ends_with("已结束") {
+                    return jobs;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        }).await.unwrap();
+        assert!(finished.iter().any(|line| line.starts_with("    下载 3/3 张图片")));
+        assert!(root.path().join("云南大理").join("03.jpg").exists());
+    }
+
+    // 专辑的图片数量在下载开始前输出，不等下载结束
+    #[tokio::test]
+    async fn test_download_hint_before_download() {
+        use wiremock::{Mock, ResponseTemplate};
+        use wiremock::matchers::{method, path};
+
+        let server = common::dili360_server_with_pictures(3).await;
+        let album = common::dili360_album_html(&server.uri(), 3);
+        Mock::given(method("GET"))
+            .and(path("/travel/album/1.htm"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(album, "text/html; charset=utf-8").set_delay(Duration::from_secs(2)))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        let root = tempfile::tempdir().unwrap();
+        let mut cli = dili360_cli(&server, root.path());
+        let (out, mut receiver) = output();
+        cli.execute(Command::SEARCH("云南".to_string()), &out).await;
+        received(&mut receiver);
+
+        let download = tokio::spawn(async move {
+            cli.execute(Command::DOWNLOAD(1, None), &out).await;
+        });
+        let hint = tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await.unwrap().unwrap();
+        assert_eq!(hint, "下载专辑 云南大理 (约 42 张图片)");
+        assert!(!download.is_finished());
+        download.await.unwrap();
+        assert!(receiver.recv().await.unwrap().starts_with("下载 3/3 张图片"));
+    }
+
+    #[test]
+    fn test_print_parsers() {
+        let (out, mut receiver) = output();
+        crate::print_parsers(&out);
+        let lines = received(&mut receiver);
+        assert_eq!(lines.len(), lmpic_downloader::parser::parsers().len() + 1);
+        assert!(lines[1].starts_with("DILI360  中国地理  https://www.dili360.com  "));
+        assert!(lines[2].starts_with("SFTK     私房图库  http://www.sftuku.com    "));
+    }
+
+    #[test]
+    fn test_parse_info() {
+        assert!(matches!("i".parse::<Command>().unwrap(), Command::INFO));
+        assert!(matches!(" info ".parse::<Command>().unwrap(), Command::INFO));
+    }
+
+    #[test]
+    fn test_parse_categories() {
+        assert!(matches!("cat".parse::<Command>().unwrap(), Command::CATEGORIES(None)));
+        match "categories xinggan".parse::<Command>().unwrap() {
+            Command::CATEGORIES(Some(code)) => assert_eq!(code.to_lowercase(), "xinggan"),
+            cmd => panic!("unexpected command: {cmd:?}")
+        }
+    }
 }
@@ -0,0 +1,157 @@
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use wiremock::matchers::{method, path, path_regex};
+
+// 模拟图片内容，只需保证非空即可
+pub const PICTURE_BYTES: &[u8] = b"\xFF\xD8\xFF\xE0mock-picture\xFF\xD9";
+
+pub fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name)
+}
+
+// 读取测试页面，并将其中的 {{base_url}} 替换为模拟服务地址
+pub fn fixture(name: &str, base_url: &str) -> String {
+    let content = std::fs::read_to_string(fixture_path(name))
+        .unwrap_or_else(|err| panic!("read fixture {name} error: {err:?}"));
+    content.replace("{{base_url}}", base_url)
+}
+
+pub async fn mount_html(server: &MockServer, url_path: &str, fixture_name: &str) {
+    let body = fixture(fixture_name, &server.uri());
+    Mock::given(method("GET"))
+        .and(path(url_path))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/html; charset=utf-8"))
+        .mount(server)
+        .await;
+}
+
+pub fn gbk_bytes(content: &str) -> Vec<u8> {
+    encoding::label::encoding_from_whatwg_label("gbk").unwrap()
+        .encode(content, encoding::EncoderTrap::Strict)
+        .unwrap()
+}
+
+// 私房图库的页面使用 GBK 编码
+pub async fn mount_gbk_html(server: &MockServer, url_path: &str, fixture_name: &str) {
+    let body = fixture(fixture_name, &server.uri());
+    Mock::given(method("GET"))
+        .and(path(url_path))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(gbk_bytes(&body), "text/html; charset=gb2312"))
+        .mount(server)
+        .await;
+}
+
+// 生成私房图库专辑的第 page 页，共 pages 页，每页两张图片
+pub fn sftk_album_html(base_url: &str, album: &str, page: usize, pages: usize) -> String {
+    let links: Vec<String> = (1..=pages).map(|i| {
+        match i {
+            1 => format!(r#"<a href="{album}.html">1</a>"#),
+            n => format!(r#"<a href="{album}_{n}.html">{n}</a>"#)
+        }
+    }).collect();
+    let pictures: Vec<String> = (1..=2).map(|i| {
+        format!(r#"<div class="slide"><a href="{album}_{page}.html"><img src="{base_url}/pictures/{page:02}-{i}.jpg"></a></div>"#)
+    }).collect();
+    format!("<html><body><div id=\"picg\">{}</div><div class=\"pagelist\">{}</div></body></html>",
+            pictures.join(""), links.join(""))
+}
+
+// 挂载私房图库的专辑 /chis/shanshui/{album}.html 的所有分页，每页响应延迟 delay
+pub async fn mount_sftk_album(server: &MockServer, album: &str, pages: usize, delay: Duration) {
+    for page in 1..=pages {
+        let url_path = match page {
+            1 => format!("/chis/shanshui/{album}.html"),
+            n => format!("/chis/shanshui/{album}_{n}.html")
+        };
+        let body = sftk_album_html(&server.uri(), album, page, pages);
+        Mock::given(method("GET"))
+            .and(path(url_path))
+            .respond_with(ResponseTemplate::new(200)
+                .set_body_raw(gbk_bytes(&body), "text/html; charset=gb2312")
+                .set_delay(delay))
+            .mount(server)
+            .await;
+    }
+}
+
+// 挂载只有一页的私房图库专辑系列，每个专辑的页面末尾链接到下一个专辑，最后一个专辑链接回第一个
+pub async fn mount_sftk_series(server: &MockServer, albums: &[&str]) {
+    for (i, album) in albums.iter().enumerate() {
+        let next = albums[(i + 1) % albums.len()];
+        let body = sftk_album_html(&server.uri(), album, 1, 1).replace("</body>",
+            &format!(r#"<ul class="pre_next"><li>上一篇：<a href="/chis/shanshui/1000.html">上一期</a></li><li>下一篇：<a href="/chis/shanshui/{next}.html">系列 {next}</a></li></ul></body>"#));
+        Mock::given(method("GET"))
+            .and(path(format!("/chis/shanshui/{album}.html")))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(gbk_bytes(&body), "text/html; charset=gb2312"))
+            .mount(server)
+            .await;
+    }
+}
+
+pub async fn mount_pictures(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/pictures/.+$"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(PICTURE_BYTES, "image/jpeg"))
+        .mount(server)
+        .await;
+}
+
+// 前 times 次请求返回 429，之后交由其它规则处理，用于重试相关的测试
+pub async fn mount_too_many_requests(server: &MockServer, url_path: &str, times: u64) {
+    Mock::given(method("GET"))
+        .and(path(url_path))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(times)
+        .with_priority(1)
+        .mount(server)
+        .await;
+}
+
+// 生成包含 count 张图片的地理 360 专辑页面
+pub fn dili360_album_html(base_url: &str, count: usize) -> String {
+    let pictures: Vec<String> = (1..=count).map(|i| {
+        format!(r#"<div class="imgbox"><div class="img"><img src="{base_url}/pictures/{i:02}.jpg@!rw9"></div></div>"#)
+    }).collect();
+    format!("<html><body><div class=\"content\">{}</div></body></html>", pictures.join("\n"))
+}
+
+// 地理 360 的模拟站点：搜索结果、专辑页面以及图片
+pub async fn dili360_server() -> MockServer {
+    let server = MockServer::start().await;
+    let album = fixture("dili360_album.html", &server.uri());
+    mount_dili360(&server, "dili360_search.html", album).await;
+    server
+}
+
+// 使用指定的搜索结果页面，专辑页面与 dili360_server 相同
+pub async fn dili360_server_with_search(search_fixture: &str) -> MockServer {
+    let server = MockServer::start().await;
+    let album = fixture("dili360_album.html", &server.uri());
+    mount_dili360(&server, search_fixture, album).await;
+    server
+}
+
+// 与 dili360_server 相同，但每个专辑包含 count 张图片
+pub async fn dili360_server_with_pictures(count: usize) -> MockServer {
+    let server = MockServer::start().await;
+    let album = dili360_album_html(&server.uri(), count);
+    mount_dili360(&server, "dili360_search.html", album).await;
+    server
+}
+
+async fn mount_dili360(server: &MockServer, search_fixture: &str, album: String) {
+    mount_html(server, "/cse/site", search_fixture).await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/travel/album/\d+\.htm$"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(album, "text/html; charset=utf-8"))
+        .mount(server)
+        .await;
+    mount_pictures(server).await;
+}
+
+// 未声明压缩方式的压缩数据，模拟服务端错误标注编码的响应
+pub const MISLABELED_BYTES: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03\xb3\xc9\x28\xc9\xcd\xb1\xe3\xb2\x49\xca\x4f\xa9\xb4\x03\x00\x9c\xfe\x12\x8a\x0d\x00\x00\x00";
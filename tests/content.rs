@@ -0,0 +1,35 @@
+mod common;
+
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use wiremock::matchers::{header, method, path};
+
+use lmpic_downloader::AlbumSearcher;
+use lmpic_downloader::parser::ParserBuilder;
+
+#[tokio::test]
+async fn test_mislabeled_encoding_fallback() {
+    let server = MockServer::start().await;
+    let html = common::fixture("dili360_search.html", &server.uri());
+    Mock::given(method("GET"))
+        .and(path("/cse/site"))
+        .and(header("accept-encoding", "identity"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(html, "text/html; charset=utf-8"))
+        .with_priority(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/cse/site"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(common::MISLABELED_BYTES, "text/html; charset=utf-8"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    // 只检查第一页的请求
+    searcher.disable_prefetch();
+    let albums = searcher.next().await.unwrap().unwrap();
+    assert_eq!(albums.len(), 10);
+    assert_eq!(albums[0].name, "云南大理");
+}
@@ -0,0 +1,970 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio_util::sync::CancellationToken;
+use wiremock::{Mock, Request, Respond, ResponseTemplate};
+use wiremock::matchers::{method, path, path_regex};
+
+use lmpic_downloader::{Album, AlbumSearcher, ArchiveFormat, Auth, ClientConfig, DEFAULT_MIN_FREE_SPACE, DEFAULT_PICTURE_ACCEPT, DownloadConfig, DownloaderError, PauseGate, Storage, THUMBS_DIR};
+use lmpic_downloader::parser::ParserBuilder;
+use lmpic_downloader::retry::RetryPolicy;
+
+#[tokio::test]
+async fn test_download_album() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    let _ = searcher.next().await;
+    let ret = searcher.next().await;
+    assert!(ret.is_ok());
+
+    let opt = ret.unwrap();
+    assert!(opt.is_some());
+
+    let albums = opt.unwrap();
+    assert_eq!(albums.len(), 10usize);
+    assert_eq!(searcher.page(), 2);
+    assert_eq!(searcher.page_count(), 5);
+
+    let result = searcher.download(6).await.unwrap();
+    assert_eq!(result.total, 5);
+    assert_eq!(result.downloaded, 5);
+    assert_eq!(result.failed, 0);
+    assert_eq!(result.bytes, 5 * common::PICTURE_BYTES.len() as u64);
+    let album_path = root.path().join("梅里雪山");
+    let files = std::fs::read_dir(&album_path).unwrap().count();
+    assert_eq!(files, 5);
+    assert!(album_path.join("01.jpg").exists());
+}
+
+#[tokio::test]
+async fn test_download_picture_redirect() {
+    let server = common::dili360_server_with_pictures(3).await;
+    // 第 2 张图片重定向到 CDN 上的地址
+    Mock::given(method("GET"))
+        .and(path("/pictures/02.jpg"))
+        .respond_with(ResponseTemplate::new(302).insert_header("Location", "/cdn/abc123.jpg"))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/cdn/abc123.jpg"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(common::PICTURE_BYTES, "image/jpeg"))
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { index_prefix: true, ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+
+    let result = searcher.download(1).await.unwrap();
+    assert_eq!(result.downloaded, 3);
+    assert_eq!(result.redirects, vec![(format!("{}/pictures/02.jpg", server.uri()), format!("{}/cdn/abc123.jpg", server.uri()))]);
+    // 文件名使用最终地址中的名称，仍然保留序号前缀
+    let album_path = root.path().join("云南大理");
+    assert!(album_path.join("0001_01.jpg").exists());
+    assert!(album_path.join("0002_abc123.jpg").exists());
+    assert!(!album_path.join("0002_02.jpg").exists());
+}
+
+#[tokio::test]
+async fn test_download_album_to_archive() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { archive_format: ArchiveFormat::Zip, index_prefix: true, ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+
+    // 图片直接写入压缩包，不保存单独的图片文件
+    let result = searcher.download(1).await.unwrap();
+    assert_eq!(result.downloaded, 5);
+    let archive = root.path().join("云南大理.zip");
+    assert_eq!(result.archive.as_ref(), Some(&archive));
+    assert!(!root.path().join("云南大理").exists());
+    let zip = async_zip::base::read::mem::ZipFileReader::new(std::fs::read(&archive).unwrap()).await.unwrap();
+    let mut names: Vec<String> = zip.file().entries().iter().map(|entry| entry.filename().as_str().unwrap().to_string()).collect();
+    names.sort();
+    assert_eq!(names, vec!["0001_01.jpg", "0002_02.jpg", "0003_03.jpg", "0004_04.jpg", "0005_05.jpg"]);
+    let mut entry = zip.reader_without_entry(0).await.unwrap();
+    let mut bytes = vec![];
+    futures_util::AsyncReadExt::read_to_end(&mut entry, &mut bytes).await.unwrap();
+    assert_eq!(bytes, common::PICTURE_BYTES);
+
+    // 已有同名压缩包时不覆盖
+    searcher.set_download_config(DownloadConfig { archive_format: ArchiveFormat::Tar, ..DownloadConfig::default() });
+    assert_eq!(searcher.download(1).await.unwrap().archive, Some(root.path().join("云南大理.tar")));
+    let result = searcher.download(1).await.unwrap();
+    assert_eq!(result.archive, Some(root.path().join("云南大理_1.tar")));
+    let mut tar = tokio_tar::Archive::new(tokio::fs::File::open(root.path().join("云南大理_1.tar")).await.unwrap());
+    let mut entries = tar.entries().unwrap();
+    let mut count = 0;
+    while let Some(entry) = futures_util::StreamExt::next(&mut entries).await {
+        assert_eq!(entry.unwrap().header().size().unwrap(), common::PICTURE_BYTES.len() as u64);
+        count += 1;
+    }
+    assert_eq!(count, 5);
+}
+
+// 保存在内存中的存储后端，记录写入的对象
+#[derive(Debug, Default)]
+struct MemoryStorage {
+    objects: Mutex<std::collections::BTreeMap<std::path::PathBuf, Vec<u8>>>
+}
+
+#[async_trait::async_trait]
+impl Storage for MemoryStorage {
+    async fn put(&self, path: &std::path::Path, bytes: &[u8]) -> std::io::Result<()> {
+        self.objects.lock().unwrap().insert(path.to_path_buf(), bytes.to_vec());
+        Ok(())
+    }
+
+    async fn exists(&self, path: &std::path::Path) -> bool {
+        self.objects.lock().unwrap().contains_key(path)
+    }
+
+    async fn size(&self, path: &std::path::Path) -> Option<u64> {
+        self.objects.lock().unwrap().get(path).map(|bytes| bytes.len() as u64)
+    }
+}
+
+#[tokio::test]
+async fn test_download_to_storage() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+    let storage = Arc::new(MemoryStorage::default());
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { storage: storage.clone(), ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+
+    // 图片只写入存储后端，本地不创建专辑目录
+    let result = searcher.download(1).await.unwrap();
+    assert_eq!(result.downloaded, 5);
+    assert!(!root.path().join("云南大理").exists());
+    {
+        let objects = storage.objects.lock().unwrap();
+        let names: Vec<String> = objects.keys().map(|path| path.strip_prefix(root.path()).unwrap().to_string_lossy().replace('\\', "/")).collect();
+        assert_eq!(names, vec!["云南大理/01.jpg", "云南大理/02.jpg", "云南大理/03.jpg", "云南大理/04.jpg", "云南大理/05.jpg"]);
+        assert!(objects.values().all(|bytes| bytes == common::PICTURE_BYTES));
+    }
+    assert!(storage.exists(&root.path().join("云南大理").join("01.jpg")).await);
+}
+
+#[tokio::test]
+async fn test_download_picture_to_sink() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let album = Album::new("云南大理".to_string(), format!("{}/travel/album/1.htm", server.uri())).unwrap();
+
+    // 写入内存而不是文件
+    let mut sink = Vec::new();
+    let size = album.download_picture_to(&*parser, &format!("{}/pictures/01.jpg", server.uri()), &mut sink).await.unwrap();
+    assert_eq!(size, common::PICTURE_BYTES.len() as u64);
+    assert_eq!(sink, common::PICTURE_BYTES);
+
+    let err = album.download_picture_to(&*parser, &format!("{}/missing/01.jpg", server.uri()), &mut sink).await.err().unwrap();
+    assert!(matches!(err.downcast_ref::<DownloaderError>(), Some(DownloaderError::HttpError { .. })));
+}
+
+#[tokio::test]
+async fn test_download_thumbnails() {
+    let server = common::dili360_server_with_pictures(2).await;
+    // 第 1 张图片是 400x300 的 PNG，第 2 张无法解码
+    let mut png = std::io::Cursor::new(Vec::new());
+    image::RgbaImage::new(400, 300).write_to(&mut png, image::ImageFormat::Png).unwrap();
+    Mock::given(method("GET"))
+        .and(path("/pictures/01.jpg"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(png.into_inner(), "image/png"))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { generate_thumbnails: true, thumb_size: 100, ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+
+    // 缩略图生成失败不影响下载结果
+    let result = searcher.download(1).await.unwrap();
+    assert_eq!(result.downloaded, 2);
+    assert_eq!(result.failed, 0);
+    let thumbs = root.path().join("云南大理").join(THUMBS_DIR);
+    let thumb = image::open(thumbs.join("01.jpg.jpg")).unwrap();
+    assert_eq!((thumb.width(), thumb.height()), (100, 75));
+    assert!(!thumbs.join("02.jpg.jpg").exists());
+}
+
+#[tokio::test]
+async fn test_download_avif_and_heic() {
+    let server = common::dili360_server_with_pictures(3).await;
+    // 第 1 张按 Accept 协商返回 AVIF，第 2 张返回没有标注类型的 HEIC，第 3 张仍是 JPEG
+    for (name, fixture, content_type) in [("01.jpg", "picture.avif", "image/avif"), ("02.jpg", "picture.heic", "application/octet-stream")] {
+        Mock::given(method("GET"))
+            .and(path(format!("/pictures/{name}")))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(std::fs::read(common::fixture_path(fixture)).unwrap(), content_type))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+    }
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { generate_thumbnails: true, ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+
+    // 按文件头改正扩展名，无法生成缩略图不影响下载结果
+    let result = searcher.download(1).await.unwrap();
+    assert_eq!(result.downloaded, 3);
+    let album_path = root.path().join("云南大理");
+    assert!(album_path.join("01.avif").exists());
+    assert!(album_path.join("02.heic").exists());
+    assert!(album_path.join("03.jpg").exists());
+    assert!(!album_path.join("01.jpg").exists());
+    assert!(!album_path.join(THUMBS_DIR).join("01.avif.jpg").exists());
+}
+
+#[tokio::test]
+async fn test_download_album_of_other_site() {
+    let server = wiremock::MockServer::start().await;
+    // 搜索结果中的专辑在其它站点上
+    let other = server.uri().replace("127.0.0.1", "localhost");
+    let body = common::fixture("dili360_search.html", &other);
+    Mock::given(method("GET"))
+        .and(path("/cse/site"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/html; charset=utf-8"))
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.disable_prefetch();
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+
+    // 下载前检查地址，不请求专辑页面
+    let err = searcher.download(1).await.err().unwrap();
+    assert!(matches!(err.downcast_ref::<DownloaderError>(), Some(DownloaderError::InvalidUrl { .. })), "{err:?}");
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_retry_failed_pictures() {
+    let server = common::dili360_server().await;
+    // 第 3 张图片第一次请求失败
+    common::mount_too_many_requests(&server, "/pictures/03.jpg", 1).await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+    searcher.next().await.unwrap();
+
+    let result = searcher.download(6).await.unwrap();
+    assert_eq!(result.downloaded, 4);
+    assert_eq!(result.failed, 1);
+    assert_eq!(result.failed_urls, vec![(3, format!("{}/pictures/03.jpg", server.uri()))]);
+    assert_eq!(searcher.failed_count(), 1);
+    let album_path = root.path().join("梅里雪山");
+    assert!(!album_path.join("03.jpg").exists());
+
+    let result = searcher.retry_failed().await.unwrap();
+    assert_eq!(result.total, 1);
+    assert_eq!(result.downloaded, 1);
+    assert_eq!(result.failed, 0);
+    assert_eq!(searcher.failed_count(), 0);
+    assert_eq!(std::fs::read_dir(&album_path).unwrap().count(), 5);
+    assert!(album_path.join("03.jpg").exists());
+    assert!(searcher.retry_failed().await.is_err());
+}
+
+#[tokio::test]
+async fn test_download_with_retry_policy() {
+    let server = common::dili360_server().await;
+    common::mount_too_many_requests(&server, "/pictures/03.jpg", 2).await;
+    let config = ClientConfig {
+        retry_policy: RetryPolicy { attempts: 3, base_delay: Duration::from_millis(10), ..RetryPolicy::default() },
+        ..ClientConfig::default()
+    };
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).client_config(config).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+    searcher.next().await.unwrap();
+
+    let result = searcher.download(6).await.unwrap();
+    assert_eq!(result.downloaded, 5);
+    assert_eq!(result.failed, 0);
+    assert!(root.path().join("梅里雪山").join("03.jpg").exists());
+}
+
+#[tokio::test]
+async fn test_download_rate_limit_per_domain() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { requests_per_second_per_domain: Some(20.0), ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+    searcher.next().await.unwrap();
+
+    // 5 张图片都在同一个域名下，第一张之后每 50ms 才能请求一张
+    let start = Instant::now();
+    let result = searcher.download(6).await.unwrap();
+    assert_eq!(result.downloaded, 5);
+    assert!(start.elapsed() >= Duration::from_millis(180), "elapsed: {:?}", start.elapsed());
+}
+
+#[tokio::test]
+async fn test_download_album_dedup() {
+    let server = common::dili360_server().await;
+    // 除 02.jpg 外其它图片的内容都相同
+    Mock::given(method("GET"))
+        .and(path("/pictures/02.jpg"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(b"another picture".to_vec(), "image/jpeg"))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { dedup: true, ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+    searcher.next().await.unwrap();
+
+    let result = searcher.download(6).await.unwrap();
+    assert_eq!(result.downloaded, 2);
+    assert_eq!(result.duplicates, 3);
+    assert_eq!(result.failed, 0);
+    let album_path = root.path().join("梅里雪山");
+    assert_eq!(std::fs::read_dir(&album_path).unwrap().count(), 2);
+    assert!(album_path.join("02.jpg").exists());
+}
+
+#[tokio::test]
+async fn test_download_abort_after_consecutive_failures() {
+    let server = common::dili360_server_with_pictures(50).await;
+    // 模拟下载途中被封禁，所有图片都返回 403
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/pictures/.+$"))
+        .respond_with(ResponseTemplate::new(403))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig {
+        picture_concurrency: 2,
+        max_consecutive_failures: Some(5),
+        ..DownloadConfig::default()
+    });
+    searcher.next().await.unwrap();
+
+    let err = searcher.download(1).await.err().unwrap();
+    assert!(matches!(err.downcast_ref::<DownloaderError>(), Some(DownloaderError::TooManyFailures(5))), "error: {err:?}");
+    let requests = server.received_requests().await.unwrap();
+    let picture_requests = requests.iter().filter(|request| request.url.path().starts_with("/pictures/")).count();
+    assert!(picture_requests < 10, "picture requests: {picture_requests}");
+}
+
+#[tokio::test]
+async fn test_download_series() {
+    let server = wiremock::MockServer::start().await;
+    common::mount_gbk_html(&server, "/chis/yunnan/1.html", "sftk_search.html").await;
+    common::mount_sftk_series(&server, &["1001", "1002", "1003"]).await;
+    common::mount_pictures(&server).await;
+    let parser = ParserBuilder::new("SFTK").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+
+    // 第三个专辑链接回第一个，不会重复下载
+    let results = searcher.download_series(1, 10).await.unwrap();
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().all(|ret| ret.as_ref().unwrap().downloaded == 2));
+    for folder in ["山水风景 第1期", "1002", "1003"] {
+        assert_eq!(std::fs::read_dir(root.path().join(folder)).unwrap().count(), 2, "folder: {folder}");
+    }
+
+    let root = tempfile::tempdir().unwrap();
+    searcher.set_download_root(root.path());
+    let results = searcher.download_series(1, 1).await.unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(!root.path().join("1003").exists());
+}
+
+#[tokio::test]
+async fn test_download_progress_events() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { progress_events: Some(sender), ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+    searcher.next().await.unwrap();
+    searcher.download(6).await.unwrap();
+    drop(searcher);
+
+    let mut events = vec![];
+    while let Some(progress) = receiver.recv().await {
+        events.push(progress);
+    }
+    assert_eq!(events.len(), 5);
+    assert!(events.iter().all(|progress| progress.total == 5));
+    assert_eq!(events.iter().map(|progress| progress.done).max(), Some(5));
+}
+
+#[tokio::test]
+async fn test_search_too_many_requests() {
+    let server = common::dili360_server().await;
+    common::mount_too_many_requests(&server, "/cse/site", 1).await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    let err = searcher.next().await.err().unwrap();
+    match err.downcast_ref::<DownloaderError>() {
+        Some(DownloaderError::HttpError { status, url }) => {
+            assert_eq!(status.as_u16(), 429);
+            assert!(url.contains("/cse/site"), "url: {url}");
+        }
+        other => panic!("unexpected error: {other:?}")
+    }
+    assert!(searcher.next().await.is_ok());
+}
+
+#[tokio::test]
+async fn test_download_album_range() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+
+    assert!(searcher.download_range(1, 4, 6).await.is_err());
+    assert!(searcher.download_range(1, 3, 2).await.is_err());
+
+    searcher.download_range(1, 2, 3).await.unwrap();
+    let album_path = root.path().join("云南大理");
+    let mut files: Vec<String> = std::fs::read_dir(&album_path).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    assert_eq!(files, vec!["02.jpg", "03.jpg"]);
+}
+
+#[tokio::test]
+async fn test_download_album_index_prefix() {
+    let server = common::dili360_server_with_pictures(12).await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { index_prefix: true, ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+    searcher.download(1).await.unwrap();
+
+    let mut files: Vec<String> = std::fs::read_dir(root.path().join("云南大理")).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    let expected: Vec<String> = (1..=12).map(|i| format!("{i:04}_{i:02}.jpg")).collect();
+    assert_eq!(files, expected);
+}
+
+#[tokio::test]
+async fn test_download_album_folder_names() {
+    let server = common::dili360_server_with_search("dili360_search_duplicated.html").await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "雪山", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+    for idx in 1..=3 {
+        searcher.download(idx).await.unwrap();
+    }
+
+    let mut folders: Vec<String> = std::fs::read_dir(root.path()).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    folders.sort();
+    assert_eq!(folders.len(), 3);
+    // 名称为空的专辑使用 URL 生成目录名
+    assert_eq!(folders[0], "7");
+    // 重名的专辑追加 URL 的哈希区分
+    assert!(folders[1].starts_with("雪山_"));
+    assert!(folders[2].starts_with("雪山_"));
+    assert_ne!(folders[1], folders[2]);
+}
+
+#[tokio::test]
+async fn test_download_album_parser_code_folder() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { parser_code_folder: true, ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+    searcher.download(1).await.unwrap();
+
+    assert!(root.path().join("DILI360").join("云南大理").join("01.jpg").exists());
+}
+
+#[tokio::test]
+async fn test_download_album_deadline() {
+    let server = common::dili360_server_with_pictures(12).await;
+    // 前 3 张图片立即返回，其余图片响应很慢
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/pictures/(0[4-9]|1[0-2])\.jpg$"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(common::PICTURE_BYTES, "image/jpeg")
+            .set_delay(Duration::from_secs(30)))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { deadline: Some(Duration::from_secs(1)), ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+
+    let start = Instant::now();
+    let result = searcher.download(1).await.unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(result.deadline_exceeded);
+    assert_eq!(result.total, 12);
+    assert_eq!(result.downloaded, 3);
+
+    let files = std::fs::read_dir(root.path().join("云南大理")).unwrap().count();
+    assert_eq!(files, 3);
+}
+
+#[tokio::test]
+async fn test_download_album_cancel() {
+    let server = common::dili360_server_with_pictures(12).await;
+    // 前 3 张图片立即返回，其余图片响应很慢
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/pictures/(0[4-9]|1[0-2])\.jpg$"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(common::PICTURE_BYTES, "image/jpeg")
+            .set_delay(Duration::from_secs(30)))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let cancel = CancellationToken::new();
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { picture_concurrency: 4, cancel: cancel.clone(), ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+
+    let canceller = cancel.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        canceller.cancel();
+    });
+    let start = Instant::now();
+    let result = searcher.download(1).await.unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(result.is_cancelled());
+    assert_eq!(result.downloaded, 3);
+    assert_eq!(result.cancelled, 9);
+    assert_eq!(result.failed, 0);
+
+    let files = std::fs::read_dir(root.path().join("云南大理")).unwrap().count();
+    assert_eq!(files, 3);
+
+    // 已取消时不再下载任何图片
+    let result = searcher.download(2).await.unwrap();
+    assert_eq!(result.downloaded, 0);
+    assert_eq!(result.cancelled, result.total);
+}
+
+#[tokio::test]
+async fn test_download_album_pause() {
+    let server = common::dili360_server_with_pictures(12).await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let pause = PauseGate::default();
+    pause.pause();
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { pause: pause.clone(), ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+
+    let task = tokio::spawn(async move { searcher.download(1).await.unwrap() });
+    // 暂停期间不下载图片
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let requests = server.received_requests().await.unwrap();
+    assert!(requests.iter().all(|request| !request.url.path().starts_with("/pictures/")));
+    assert!(!task.is_finished());
+
+    pause.resume();
+    let result = tokio::time::timeout(Duration::from_secs(10), task).await.unwrap().unwrap();
+    assert_eq!(result.downloaded, 12);
+    assert!(!pause.is_paused());
+}
+
+#[tokio::test]
+async fn test_download_album_insufficient_space() {
+    let server = common::dili360_server_with_pictures(12).await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    // 可用 100 MB，12 张图片加上 500 MB 的保留空间不够
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig { free_space: |_| Some(100 * 1024 * 1024), ..DownloadConfig::default() });
+    searcher.next().await.unwrap();
+
+    let err = searcher.download(1).await.unwrap_err();
+    match err.downcast_ref::<DownloaderError>() {
+        Some(err @ DownloaderError::InsufficientSpace { available, required, .. }) => {
+            assert_eq!(*available, 100 * 1024 * 1024);
+            assert_eq!(*required, 12 * 1024 * 1024 + DEFAULT_MIN_FREE_SPACE);
+            assert!(err.is_fatal());
+        }
+        _ => panic!("unexpected error: {err:?}")
+    }
+    let requests = server.received_requests().await.unwrap();
+    assert!(requests.iter().all(|request| !request.url.path().starts_with("/pictures/")));
+
+    // 保存为压缩包时空间不足不会留下未完成的压缩包
+    searcher.set_download_config(DownloadConfig { archive_format: ArchiveFormat::Zip, free_space: |_| Some(100 * 1024 * 1024), ..DownloadConfig::default() });
+    let err = searcher.download(1).await.unwrap_err();
+    assert!(matches!(err.downcast_ref::<DownloaderError>(), Some(DownloaderError::InsufficientSpace { .. })));
+    let archives: Vec<_> = std::fs::read_dir(root.path()).unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "zip"))
+        .collect();
+    assert!(archives.is_empty(), "{archives:?}");
+
+    // 不检查或者空间足够时正常下载
+    for min_free_space in [None, Some(10 * 1024 * 1024)] {
+        searcher.set_download_config(DownloadConfig { min_free_space, free_space: |_| Some(100 * 1024 * 1024), ..DownloadConfig::default() });
+        assert_eq!(searcher.download(1).await.unwrap().downloaded, 12);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_download_album_disk_full() {
+    let server = common::dili360_server_with_pictures(12).await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    // 写入 /dev/full 会返回 ENOSPC，模拟磁盘已满
+    let album_path = root.path().join("云南大理");
+    std::fs::create_dir_all(&album_path).unwrap();
+    std::os::unix::fs::symlink("/dev/full", album_path.join("01.jpg")).unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+
+    let err = searcher.download(1).await.unwrap_err();
+    assert!(matches!(err.downcast_ref::<DownloaderError>(), Some(DownloaderError::DiskFull(_))));
+    // 写入失败的文件会被删除
+    assert!(std::fs::symlink_metadata(album_path.join("01.jpg")).is_err());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_download_album_read_only_directory() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let album_path = root.path().join("云南大理");
+    std::fs::create_dir_all(&album_path).unwrap();
+    std::fs::set_permissions(&album_path, std::fs::Permissions::from_mode(0o555)).unwrap();
+    if std::fs::write(album_path.join("probe"), b"").is_ok() {
+        // 以 root 运行时目录权限不生效，无法模拟
+        return;
+    }
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+
+    let err = searcher.download(1).await.unwrap_err();
+    assert!(matches!(err.downcast_ref::<DownloaderError>(), Some(DownloaderError::PermissionDenied(_))));
+    std::fs::set_permissions(&album_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[tokio::test]
+async fn test_download_root_not_writable() {
+    let server = wiremock::MockServer::start().await;
+    common::mount_html(&server, "/cse/site", "dili360_search.html").await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/travel/album/\d+\.htm$"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+
+    // 下载目录不存在时不自动创建
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("albums");
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(&missing);
+    searcher.next().await.unwrap();
+
+    let err = searcher.download(1).await.unwrap_err();
+    assert!(matches!(err.downcast_ref::<DownloaderError>(), Some(DownloaderError::InvalidConfig(_))));
+    assert!(err.to_string().contains(&missing.display().to_string()));
+    assert!(!missing.exists());
+
+    // 写入检查用的临时文件被同名目录占用，以 root 运行时也无法写入
+    let root = dir.path().to_path_buf();
+    let marker = root.join(format!(".write_test_{}", std::process::id()));
+    std::fs::create_dir_all(marker.join("occupied")).unwrap();
+    searcher.set_download_root(&root);
+
+    let err = searcher.download(1).await.unwrap_err();
+    match err.downcast_ref::<DownloaderError>() {
+        Some(DownloaderError::NotWritable { path, .. }) => {
+            assert!(path.is_absolute());
+            assert!(err.to_string().contains(&root.display().to_string()));
+        }
+        other => panic!("unexpected error: {other:?}")
+    }
+}
+
+// 记录每个图片请求到达的时间，响应固定延迟 delay
+struct RecordArrival {
+    arrivals: Arc<Mutex<Vec<Instant>>>,
+    delay: Duration
+}
+
+impl Respond for RecordArrival {
+    fn respond(&self, _request: &Request) -> ResponseTemplate {
+        self.arrivals.lock().unwrap().push(Instant::now());
+        ResponseTemplate::new(200)
+            .set_body_raw(common::PICTURE_BYTES, "image/jpeg")
+            .set_delay(self.delay)
+    }
+}
+
+#[tokio::test]
+async fn test_download_all_bounded_connections() {
+    let server = common::dili360_server_with_pictures(3).await;
+    let arrivals = Arc::new(Mutex::new(vec![]));
+    let delay = Duration::from_millis(300);
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/pictures/.+$"))
+        .respond_with(RecordArrival { arrivals: arrivals.clone(), delay })
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.set_download_config(DownloadConfig {
+        album_concurrency: 3,
+        picture_concurrency: 2,
+        max_connections: 5,
+        ..DownloadConfig::default()
+    });
+    searcher.next().await.unwrap();
+
+    let results = searcher.download_all().await.unwrap();
+    assert_eq!(results.len(), 10);
+    assert!(results.iter().all(|ret| ret.as_ref().unwrap().downloaded == 3));
+
+    // 响应延迟的一半时间内到达的请求一定同时在进行中
+    let arrivals = arrivals.lock().unwrap();
+    assert_eq!(arrivals.len(), 30);
+    let max_in_flight = arrivals.iter()
+        .map(|start| arrivals.iter().filter(|t| **t >= *start && **t < *start + delay / 2).count())
+        .max()
+        .unwrap();
+    assert!(max_in_flight <= 5, "max in flight: {max_in_flight}");
+    // 多个专辑同时下载，超过了单个专辑的并发数
+    assert!(max_in_flight > 2, "max in flight: {max_in_flight}");
+}
+
+#[tokio::test]
+async fn test_preview_album_cover() {
+    let server = common::dili360_server().await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/covers/.+$"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(common::PICTURE_BYTES, "image/jpeg"))
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.next().await.unwrap();
+    assert_eq!(searcher.preview_url(2).await.unwrap(), format!("{}/covers/2.jpg", server.uri()));
+
+    let path = searcher.preview(2).await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), common::PICTURE_BYTES);
+    std::fs::remove_file(path).unwrap();
+
+    assert!(searcher.preview(0).await.is_err());
+    assert!(searcher.preview(11).await.is_err());
+}
+
+#[tokio::test]
+async fn test_preview_album_without_cover() {
+    let server = common::dili360_server_with_pictures(2).await;
+    let search = format!(r#"<html><body><div id="results">
+        <div class="result"><h3><a href="{0}/travel/album/1.htm">云南大理</a></h3></div>
+        <div class="result"><h3><a href="{0}/empty.htm">空专辑</a></h3></div>
+        </div><div id="pageFooter"><a class="pager-normal-foot" href="?p=1">2</a></div></body></html>"#, server.uri());
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/cse/site$"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(search, "text/html; charset=utf-8"))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/empty\.htm$"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("<html><body></body></html>", "text/html; charset=utf-8"))
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.next().await.unwrap();
+    // 没有封面时使用专辑的第一张图片
+    assert_eq!(searcher.preview_url(1).await.unwrap(), format!("{}/pictures/01.jpg@!rw9", server.uri()));
+    assert!(searcher.preview_url(2).await.is_err());
+}
+
+#[tokio::test]
+async fn test_download_album_respect_robots() {
+    let server = common::dili360_server().await;
+    Mock::given(method("GET"))
+        .and(path("/robots.txt"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("User-agent: *\nDisallow: /pictures/02.jpg\n"))
+        .mount(&server)
+        .await;
+    let config = ClientConfig { respect_robots: true, ..ClientConfig::default() };
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).client_config(config).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+    let result = searcher.download(1).await.unwrap();
+
+    assert_eq!(result.total, 5);
+    assert_eq!(result.downloaded, 4);
+    assert_eq!(result.skipped, 1);
+    assert_eq!(result.failed, 0);
+    assert!(!root.path().join("云南大理").join("02.jpg").exists());
+    assert!(root.path().join("云南大理").join("01.jpg").exists());
+
+    // 每个站点的 robots.txt 只请求一次，且被跳过的图片不会发出请求
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.iter().filter(|r| r.url.path() == "/robots.txt").count(), 1);
+    assert!(requests.iter().all(|r| r.url.path() != "/pictures/02.jpg@!rw9"));
+}
+
+#[tokio::test]
+async fn test_download_picture_headers() {
+    let server = common::dili360_server_with_pictures(2).await;
+    let referer = format!("{}/", server.uri());
+    let client_config = ClientConfig { referer: Some(referer.clone()), ..ClientConfig::default() };
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).client_config(client_config).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+    assert_eq!(searcher.download(1).await.unwrap().downloaded, 2);
+
+    // 图片请求使用图片的 Accept 并带上 Referer，页面请求不变
+    let requests = server.received_requests().await.unwrap();
+    let (pictures, pages): (Vec<_>, Vec<_>) = requests.iter().partition(|r| r.url.path().starts_with("/pictures/"));
+    assert_eq!(pictures.len(), 2);
+    for request in pictures {
+        assert_eq!(request.headers.get("accept").unwrap(), DEFAULT_PICTURE_ACCEPT);
+        assert_eq!(request.headers.get("referer").unwrap(), referer.as_str());
+        assert!(request.headers.get("user-agent").is_some());
+    }
+    for request in pages {
+        assert!(request.headers.get("accept").unwrap().to_str().unwrap().starts_with("text/html"));
+    }
+
+    // Accept 可以修改
+    let client_config = ClientConfig { picture_accept: "image/webp".to_string(), ..ClientConfig::default() };
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).client_config(client_config).build().unwrap();
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+    let received = server.received_requests().await.unwrap().len();
+    assert_eq!(searcher.download_range(1, 1, 1).await.unwrap().downloaded, 1);
+    let requests = server.received_requests().await.unwrap();
+    let picture = requests[received..].iter().find(|r| r.url.path().starts_with("/pictures/")).unwrap();
+    assert_eq!(picture.headers.get("accept").unwrap(), "image/webp");
+}
+
+#[tokio::test]
+async fn test_download_album_with_auth() {
+    let server = common::dili360_server().await;
+    let auth = Auth::Basic { username: "user".to_string(), password: Some("pass".to_string()) };
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).auth(auth).build().unwrap();
+    let root = tempfile::tempdir().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.set_download_root(root.path());
+    searcher.next().await.unwrap();
+    searcher.download(1).await.unwrap();
+
+    // 搜索、专辑页面以及图片请求都带上认证信息
+    let requests = server.received_requests().await.unwrap();
+    assert!(requests.iter().any(|r| r.url.path().starts_with("/pictures/")));
+    for request in requests {
+        assert_eq!(request.headers.get("authorization").unwrap(), "Basic dXNlcjpwYXNz", "{}", request.url);
+    }
+}
@@ -0,0 +1,704 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use scraper::Html;
+
+use lmpic_downloader::{AlbumSearcher, Auth, ClientConfig, DEFAULT_USER_AGENTS, DownloaderError, IpPreference, TlsConfig, UserAgentRotation};
+use lmpic_downloader::parser::{self, Media, PaginationScheme, ParserBuilder, ParserFeatures};
+
+#[test]
+fn test_sftk_parse_page_count() {
+    let parser = ParserBuilder::new("SFTK").build().unwrap();
+    let document = Html::parse_document(&common::fixture("sftk_search.html", "http://localhost"));
+    assert_eq!(parser.parse_page_count(&document).unwrap(), 7);
+
+    let document = Html::parse_document("<html><body><div id=\"list\"><ul></ul></div></body></html>");
+    assert_eq!(parser.parse_page_count(&document).unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_sftk_search_page_count() {
+    let server = wiremock::MockServer::start().await;
+    common::mount_gbk_html(&server, "/chis/yunnan/1.html", "sftk_search.html").await;
+    let parser = ParserBuilder::new("SFTK").base_url(&server.uri()).build().unwrap();
+
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    let albums = searcher.next().await.unwrap().unwrap();
+    assert_eq!(albums.len(), 8);
+    assert_eq!(albums[0].name, "山水风景 第1期");
+    assert_eq!(albums[0].url, format!("{}/chis/shanshui/1001.html", server.uri()));
+    assert_eq!(albums[0].picture_count_hint, Some(36));
+    assert_eq!(albums[1].picture_count_hint, None);
+    assert_eq!(searcher.page_count(), 7);
+}
+
+#[tokio::test]
+async fn test_page_count_from_total_count_header() {
+    let server = wiremock::MockServer::start().await;
+    let body = common::fixture("dili360_search.html", &server.uri());
+    wiremock::Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("/cse/site"))
+        .respond_with(wiremock::ResponseTemplate::new(200)
+            .insert_header("X-Total-Count", "35")
+            .set_body_raw(body, "text/html; charset=utf-8"))
+        .mount(&server)
+        .await;
+    let body = common::fixture("sftk_search.html", &server.uri());
+    wiremock::Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("/chis/yunnan/1.html"))
+        .respond_with(wiremock::ResponseTemplate::new(200)
+            .insert_header("X-Total-Count", "17")
+            .set_body_raw(common::gbk_bytes(&body), "text/html; charset=gb2312"))
+        .mount(&server)
+        .await;
+
+    // 响应头优先于页面中的分页
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let (albums, page_count) = parser.parse_albums("云南".to_string(), 1, 10).await.unwrap();
+    assert!(!albums.is_empty());
+    assert_eq!(page_count, 4);
+
+    // 私房图库每页数量固定，按站点的每页数量计算总页数，与请求的数量无关
+    let parser = ParserBuilder::new("SFTK").base_url(&server.uri()).build().unwrap();
+    let (albums, page_count) = parser.parse_albums("云南".to_string(), 1, 20).await.unwrap();
+    assert_eq!(albums.len(), 8);
+    assert_eq!(page_count, 3);
+}
+
+#[tokio::test]
+async fn test_search_keyword_encoding() {
+    let server = wiremock::MockServer::start().await;
+    let body = common::fixture("dili360_search.html", &server.uri());
+    wiremock::Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("/cse/site"))
+        .and(wiremock::matchers::query_param("q", "云南 & 西藏"))
+        .respond_with(wiremock::ResponseTemplate::new(200).set_body_raw(body, "text/html; charset=utf-8"))
+        .expect(2)
+        .mount(&server)
+        .await;
+    common::mount_gbk_html(&server, "/chis/yunnan%26shanshui/1.html", "sftk_search.html").await;
+
+    // 首尾空白被去掉，空格和 & 编码后不会拆分查询参数，已编码的关键字不会重复编码
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    for keyword in ["  云南 & 西藏 ", "%E4%BA%91%E5%8D%97%20%26%20%E8%A5%BF%E8%97%8F"] {
+        let (albums, _) = parser.parse_albums(keyword.to_string(), 1, 10).await.unwrap();
+        assert_eq!(albums.len(), 10);
+    }
+    let requests = server.received_requests().await.unwrap();
+    for request in &requests {
+        let keys: Vec<_> = request.url.query_pairs().map(|(key, _)| key.to_string()).collect();
+        assert_eq!(keys, ["q", "p", "nsid", "cc"]);
+    }
+
+    // SFTK 拼音路径去掉空格，关键字不用多音字，拼音取第一个读音
+    let parser = ParserBuilder::new("SFTK").base_url(&server.uri()).build().unwrap();
+    let (albums, _) = parser.parse_albums(" 云南 & 山水".to_string(), 1, 8).await.unwrap();
+    assert_eq!(albums.len(), 8);
+}
+
+#[test]
+fn test_search_url() {
+    let parser = ParserBuilder::new("DILI360").base_url("http://localhost:8080").build().unwrap();
+    assert_eq!(parser.search_url("云南", 1).unwrap(), "http://localhost:8080/cse/site?q=%E4%BA%91%E5%8D%97&p=0&nsid=&cc=www.dili360.com");
+    assert_eq!(parser.search_url("云南", 3).unwrap(), "http://localhost:8080/cse/site?q=%E4%BA%91%E5%8D%97&p=2&nsid=&cc=www.dili360.com");
+    // 页码为 0 时按第一页处理
+    assert_eq!(parser.search_url("云南", 0).unwrap(), "http://localhost:8080/cse/site?q=%E4%BA%91%E5%8D%97&p=0&nsid=&cc=www.dili360.com");
+
+    let parser = ParserBuilder::new("SFTK").base_url("http://localhost:8080").build().unwrap();
+    assert_eq!(parser.search_url("云南", 1).unwrap(), "http://localhost:8080/chis/yunnan/1.html");
+    assert_eq!(parser.search_url("云南", 3).unwrap(), "http://localhost:8080/chis/yunnan/3.html");
+    assert_eq!(parser.search_url("云南", 0).unwrap(), "http://localhost:8080/chis/yunnan/1.html");
+
+    // 搜索器使用当前页，按分类浏览时没有搜索地址
+    let searcher = AlbumSearcher::new(parser.clone(), "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    assert_eq!(searcher.search_url().unwrap(), "http://localhost:8080/chis/yunnan/1.html");
+    assert!(AlbumSearcher::with_category(parser, "xinggan", AlbumSearcher::DEFAULT_PAGE_SIZE).search_url().is_err());
+}
+
+#[tokio::test]
+async fn test_dili360_parse_albums_page_zero() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+
+    let (albums, _) = parser.parse_albums("云南".to_string(), 0, 10).await.unwrap();
+    assert_eq!(albums.len(), 10);
+    let requests = server.received_requests().await.unwrap();
+    assert!(requests[0].url.query_pairs().any(|(key, value)| key == "p" && value == "0"));
+}
+
+#[tokio::test]
+async fn test_dili360_empty_results() {
+    // 结果列表存在但为空，表示没有搜索到专辑
+    let server = common::dili360_server_with_search("dili360_search_empty.html").await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let (albums, page_count) = parser.parse_albums("不存在的关键字".to_string(), 1, 10).await.unwrap();
+    assert!(albums.is_empty());
+    assert_eq!(page_count, 0);
+
+    // 页面结构变化导致找不到结果列表时返回错误
+    let server = common::dili360_server_with_search("dili360_search_broken.html").await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let err = parser.parse_albums("云南".to_string(), 1, 10).await.err().unwrap();
+    match err.downcast_ref::<DownloaderError>() {
+        Some(DownloaderError::MarkupChanged { selector, .. }) => assert_eq!(selector, "#results"),
+        other => panic!("unexpected error: {:?}", other)
+    }
+}
+
+#[tokio::test]
+async fn test_sftk_empty_results() {
+    let server = wiremock::MockServer::start().await;
+    let html = |body: &str| common::gbk_bytes(&format!("<html><body>{}</body></html>", body));
+    wiremock::Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("/chis/shanshui/1.html"))
+        .respond_with(wiremock::ResponseTemplate::new(200).set_body_raw(html(r#"<div id="list"><ul></ul></div>"#), "text/html"))
+        .mount(&server)
+        .await;
+    wiremock::Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("/chis/shanshui/2.html"))
+        .respond_with(wiremock::ResponseTemplate::new(200).set_body_raw(html(r#"<div class="gallery-list"><ul><li>山水风景</li></ul></div>"#), "text/html"))
+        .mount(&server)
+        .await;
+    let parser = ParserBuilder::new("SFTK").base_url(&server.uri()).build().unwrap();
+
+    let (albums, _) = parser.parse_albums("山水".to_string(), 1, 8).await.unwrap();
+    assert!(albums.is_empty());
+    let err = parser.parse_albums("山水".to_string(), 2, 8).await.err().unwrap();
+    assert!(matches!(err.downcast_ref::<DownloaderError>(), Some(DownloaderError::MarkupChanged { .. })));
+}
+
+#[tokio::test]
+async fn test_dili360_picture_count_hint() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+
+    let (albums, _) = parser.parse_albums("云南".to_string(), 1, 10).await.unwrap();
+    assert_eq!(albums[0].picture_count_hint, Some(42));
+    assert!(albums[1..].iter().all(|album| album.picture_count_hint.is_none()));
+}
+
+#[tokio::test]
+async fn test_sftk_get_all_pictures_concurrently() {
+    let server = wiremock::MockServer::start().await;
+    let delay = Duration::from_millis(300);
+    common::mount_sftk_album(&server, "1001", 6, delay).await;
+    let parser = ParserBuilder::new("SFTK").base_url(&server.uri()).build().unwrap();
+
+    let start = Instant::now();
+    let pictures = parser.get_all_pictures(format!("{}/chis/shanshui/1001.html", server.uri())).await.unwrap();
+    let elapsed = start.elapsed();
+
+    // 首页请求一次获取分页数，之后 6 个分页并发请求，顺序请求至少需要 7 倍延迟
+    assert!(elapsed < delay * 4, "elapsed: {elapsed:?}");
+    let expected: Vec<String> = (1..=6)
+        .flat_map(|page| (1..=2).map(move |i| format!("/pictures/{page:02}-{i}.jpg")))
+        .map(|path| format!("{}{}", server.uri(), path))
+        .collect();
+    assert_eq!(pictures, expected);
+    assert_eq!(parser.pagination_scheme(), PaginationScheme::SuffixUnderscore);
+}
+
+#[tokio::test]
+async fn test_sftk_album_url_without_html_suffix() {
+    let server = wiremock::MockServer::start().await;
+    let parser = ParserBuilder::new("SFTK").base_url(&server.uri()).build().unwrap();
+
+    for url in [format!("{}/chis/shanshui/1001", server.uri()), "htm".to_string(), "云南".to_string()] {
+        let err = parser.get_all_pictures(url.clone()).await.unwrap_err();
+        match err.downcast_ref::<DownloaderError>() {
+            Some(DownloaderError::Parse { url: err_url, .. }) => assert_eq!(err_url, &url),
+            _ => panic!("unexpected error: {err:?}")
+        }
+        assert!(parser.get_album_page(url, 2).await.is_err());
+    }
+    assert!(server.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_sftk_get_all_pictures_query_pagination() {
+    let server = wiremock::MockServer::start().await;
+    for page in 1..=3 {
+        let body = common::sftk_album_html(&server.uri(), "1001", page, 3);
+        let mock = wiremock::Mock::given(wiremock::matchers::method("GET"))
+            .and(wiremock::matchers::path("/chis/shanshui/1001.html"));
+        // 分页的规则优先匹配，没有 page 参数的请求返回第一页
+        let (mock, priority) = match page {
+            1 => (mock, 5),
+            n => (mock.and(wiremock::matchers::query_param("page", n.to_string())), 1)
+        };
+        mock.respond_with(wiremock::ResponseTemplate::new(200).set_body_raw(common::gbk_bytes(&body), "text/html; charset=gb2312"))
+            .with_priority(priority)
+            .mount(&server)
+            .await;
+    }
+    let parser = ParserBuilder::new("SFTK")
+        .base_url(&server.uri())
+        .pagination_scheme(PaginationScheme::QueryParam("page".to_string()))
+        .build()
+        .unwrap();
+
+    let pictures = parser.get_all_pictures(format!("{}/chis/shanshui/1001.html", server.uri())).await.unwrap();
+    let expected: Vec<String> = (1..=3)
+        .flat_map(|page| (1..=2).map(move |i| format!("/pictures/{page:02}-{i}.jpg")))
+        .map(|path| format!("{}{}", server.uri(), path))
+        .collect();
+    assert_eq!(pictures, expected);
+
+    // 只请求其中一个分页
+    let (pictures, pages) = parser.get_album_page(format!("{}/chis/shanshui/1001.html", server.uri()), 2).await.unwrap();
+    assert_eq!(pages, 3);
+    assert_eq!(pictures, expected[2..4]);
+    let (pictures, pages) = parser.get_album_page(format!("{}/chis/shanshui/1001.html", server.uri()), 4).await.unwrap();
+    assert_eq!((pictures.len(), pages), (0, 3));
+}
+
+#[test]
+fn test_unsupported_parser() {
+    let err = parser::parse("UNKNOWN", None).err().unwrap();
+    match err.downcast_ref::<DownloaderError>() {
+        Some(DownloaderError::UnsupportedParser { code, available }) => {
+            assert_eq!(code, "UNKNOWN");
+            assert_eq!(available, &vec!["DILI360", "SFTK"]);
+        }
+        _ => panic!("unexpected error: {err:?}")
+    }
+    assert_eq!(err.to_string(), "Unsupported parser 'UNKNOWN'. Available parsers: DILI360 (中国地理), SFTK (私房图库)");
+}
+
+#[test]
+fn test_validate_url() {
+    let parser = ParserBuilder::new("DILI360").build().unwrap();
+    assert!(parser.validate_url("https://www.dili360.com/travel/album/1.htm").is_ok());
+    let err = parser.validate_url("http://www.sftuku.com/fengjing/1.html").err().unwrap();
+    assert!(matches!(err.downcast_ref::<DownloaderError>(), Some(DownloaderError::InvalidUrl { .. })));
+    assert!(err.to_string().contains("www.sftuku.com"));
+
+    let parser = ParserBuilder::new("SFTK").build().unwrap();
+    assert!(parser.validate_url("http://WWW.SFTUKU.COM/fengjing/1.html").is_ok());
+    assert!(parser.validate_url("https://www.dili360.com/travel/album/1.htm").is_err());
+    assert!(parser.validate_url("ftp://www.sftuku.com/1.html").is_err());
+
+    // 替换站点地址后允许该地址的域名
+    let parser = ParserBuilder::new("SFTK").base_url("http://127.0.0.1:8080").build().unwrap();
+    assert!(parser.validate_url("http://127.0.0.1:8080/fengjing/1.html").is_ok());
+}
+
+#[test]
+fn test_parser_for_url() {
+    let parser = parser::parser_for_url("http://www.sftuku.com/fengjing/1234.html").unwrap();
+    assert_eq!(parser.parser_code(), "SFTK");
+    let parser = parser::parser_for_url("https://www.dili360.com/travel/album/1.htm").unwrap();
+    assert_eq!(parser.parser_code(), "DILI360");
+    assert_eq!(parser::parser_code_for_url("https://DILI360.com/travel/album/1.htm"), Some("DILI360"));
+    assert!(parser::parser_for_url("https://www.example.com/album/1.html").is_none());
+    assert!(parser::parser_for_url("not a url").is_none());
+}
+
+#[test]
+fn test_default_parser_code() {
+    let code = parser::default_parser_code();
+    assert_eq!(code, parser::parsers()[0].code);
+    let parser = parser::parse(code, None).unwrap();
+    assert_eq!(parser.parser_code(), code);
+    assert_eq!(parser::default_parser().parser_code(), code);
+}
+
+#[tokio::test]
+async fn test_picture_selector_override() {
+    let server = wiremock::MockServer::start().await;
+    let body = format!(r#"<html><body><div class="gallery"><img src="{0}/pictures/01.jpg"><img src="{0}/pictures/02.jpg"></div></body></html>"#, server.uri());
+    wiremock::Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("/album/1.htm"))
+        .respond_with(wiremock::ResponseTemplate::new(200).set_body_raw(body, "text/html; charset=utf-8"))
+        .mount(&server)
+        .await;
+    let url = format!("{}/album/1.htm", server.uri());
+
+    // 站点改版后内置的选择器找不到图片
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    assert!(parser.get_all_pictures(url.clone()).await.unwrap().is_empty());
+
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).picture_selector(".gallery>img").build().unwrap();
+    assert_eq!(parser.get_all_pictures(url).await.unwrap(), vec![
+        format!("{}/pictures/01.jpg", server.uri()),
+        format!("{}/pictures/02.jpg", server.uri())
+    ]);
+
+    assert!(ParserBuilder::new("SFTK").picture_selector(">>").build().is_err());
+}
+
+#[tokio::test]
+async fn test_album_selector_override() {
+    let server = wiremock::MockServer::start().await;
+    // 站点改版后专辑标题从 h3 改为 h4
+    let body = common::fixture("dili360_search.html", &server.uri()).replace("<h3", "<h4").replace("</h3>", "</h4>");
+    wiremock::Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("/cse/site"))
+        .respond_with(wiremock::ResponseTemplate::new(200).set_body_raw(body, "text/html; charset=utf-8"))
+        .mount(&server)
+        .await;
+
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    assert!(parser.parse_albums("云南".to_string(), 1, 10).await.unwrap().0.is_empty());
+
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).album_selector("h4>a").build().unwrap();
+    let (albums, _) = parser.parse_albums("云南".to_string(), 1, 10).await.unwrap();
+    assert_eq!(albums.len(), 10);
+    assert_eq!(albums[0].name, "云南大理");
+
+    let err = ParserBuilder::new("SFTK").album_selector("h4>>a").build().err().unwrap();
+    assert!(err.to_string().contains("h4>>a"));
+}
+
+#[tokio::test]
+async fn test_inline_script_pictures() {
+    let server = wiremock::MockServer::start().await;
+    common::mount_html(&server, "/album/1.htm", "inline_js_album.html").await;
+    let url = format!("{}/album/1.htm", server.uri());
+
+    // 图片列表只在内联脚本中，选择器找不到图片
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    assert!(parser.get_all_pictures(url.clone()).await.unwrap().is_empty());
+
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).picture_pattern(r#""([^"]+\.jpg)""#).build().unwrap();
+    assert_eq!(parser.get_all_pictures(url).await.unwrap(), vec![
+        format!("{}/pictures/01.jpg", server.uri()),
+        format!("{}/pictures/02.jpg", server.uri()),
+        format!("{}/pictures/03.jpg", server.uri())
+    ]);
+
+    let err = ParserBuilder::new("SFTK").picture_pattern("(unclosed").build().err().unwrap();
+    assert!(err.to_string().contains("(unclosed"));
+}
+
+#[tokio::test]
+async fn test_album_media_with_videos() {
+    let server = wiremock::MockServer::start().await;
+    common::mount_html(&server, "/travel/album/9.htm", "dili360_video_album.html").await;
+    let url = format!("{}/travel/album/9.htm", server.uri());
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+
+    // <video><source> 中的地址归为视频，相对地址以专辑地址补全
+    assert_eq!(parser.get_all_media(url.clone()).await.unwrap(), vec![
+        Media::Image(format!("{}/pictures/01.jpg", server.uri())),
+        Media::Image(format!("{}/pictures/02.jpg", server.uri())),
+        Media::Video(format!("{}/videos/canyon", server.uri())),
+        Media::Video(format!("{}/videos/canyon.webm", server.uri()))
+    ]);
+    assert_eq!(parser.get_all_pictures(url).await.unwrap().len(), 2);
+}
+
+#[test]
+fn test_parse_pictures_from_html() {
+    // 本地保存的专辑页面，不请求网络
+    let html = std::fs::read_to_string(common::fixture_path("dili360_album.html")).unwrap().replace("{{base_url}}", "");
+    let parser = ParserBuilder::new("DILI360").build().unwrap();
+    let pictures = parser.parse_pictures_from_html(&html, "https://img0.dili360.com/ga/").unwrap();
+    assert_eq!(pictures.len(), 5);
+    assert_eq!(pictures[0], "https://img0.dili360.com/pictures/01.jpg");
+    assert_eq!(parser.parse_pictures_from_html(&html, "").unwrap()[4], "/pictures/05.jpg");
+
+    let html = r##"<div id="picg"><div class="slide"><a href="#"><img src="../uploads/yunnan/01.jpg"></a></div></div>"##;
+    let parser = ParserBuilder::new("SFTK").build().unwrap();
+    assert_eq!(parser.parse_pictures_from_html(html, "http://www.sftuku.com/fengjing/1.html").unwrap(),
+               vec!["http://www.sftuku.com/uploads/yunnan/01.jpg"]);
+    assert!(parser.parse_pictures_from_html(html, "not a url").is_err());
+}
+
+#[tokio::test]
+async fn test_lazy_loaded_pictures() {
+    let server = wiremock::MockServer::start().await;
+    let body = format!(r#"<html><body><div class="imgbox">
+        <div class="img"><img src="data:image/gif;base64,R0lGODlhAQABAAAAACw=" data-src="{0}/pictures/01.jpg"></div>
+        <div class="img"><img src="{0}/placeholder.gif" srcset="{0}/pictures/02-small.jpg 480w, {0}/pictures/02-large.jpg 1280w, {0}/pictures/02-medium.jpg 800w"></div>
+        <div class="img"><img src="{0}/pictures/03.jpg"></div>
+        </div></body></html>"#, server.uri());
+    wiremock::Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("/album/1.htm"))
+        .respond_with(wiremock::ResponseTemplate::new(200).set_body_raw(body, "text/html; charset=utf-8"))
+        .mount(&server)
+        .await;
+    let url = format!("{}/album/1.htm", server.uri());
+
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    assert_eq!(parser.get_all_pictures(url.clone()).await.unwrap(), vec![
+        format!("{}/pictures/01.jpg", server.uri()),
+        format!("{}/pictures/02-large.jpg", server.uri()),
+        format!("{}/pictures/03.jpg", server.uri())
+    ]);
+
+    // 只读取 src 时拿到的是占位图
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).picture_attributes(&["src"]).build().unwrap();
+    assert_eq!(parser.get_all_pictures(url).await.unwrap(), vec![
+        format!("{}/placeholder.gif", server.uri()),
+        format!("{}/pictures/03.jpg", server.uri())
+    ]);
+}
+
+#[tokio::test]
+async fn test_page_pictures_dedup_and_placeholders() {
+    let server = wiremock::MockServer::start().await;
+    let body = format!(r#"<html><body><div class="imgbox">
+        <div class="img"><img src="{0}/pictures/01.jpg"></div>
+        <div class="img"><img src=""></div>
+        <div class="img"><img src="{0}/pictures/02.jpg"></div>
+        <div class="img"><img src="{0}/pictures/01.jpg"></div>
+        <div class="img"><img src="x.gif"></div>
+        <div class="img"><img src="{0}/pictures/03.jpg"></div>
+        <div class="img"><img src="{0}/pictures/02.jpg"></div>
+        </div></body></html>"#, server.uri());
+    wiremock::Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("/album/1.htm"))
+        .respond_with(wiremock::ResponseTemplate::new(200).set_body_raw(body, "text/html; charset=utf-8"))
+        .mount(&server)
+        .await;
+
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    let pictures = parser.get_all_pictures(format!("{}/album/1.htm", server.uri())).await.unwrap();
+    assert_eq!(pictures, vec![
+        format!("{}/pictures/01.jpg", server.uri()),
+        format!("{}/pictures/02.jpg", server.uri()),
+        format!("{}/pictures/03.jpg", server.uri())
+    ]);
+}
+
+#[test]
+fn test_get_picture_name() {
+    let parser = ParserBuilder::new("DILI360").build().unwrap();
+    assert_eq!(parser.get_picture_name("https://img0.dili360.com/ga/M01/48/3C/01.jpg@!rw9").unwrap(), "01.jpg");
+    assert_eq!(parser.get_picture_name("https://img0.dili360.com/ga/M01/48/3C/01.jpg").unwrap(), "01.jpg");
+    assert_eq!(parser.get_picture_name("https://img0.dili360.com/ga/01.jpg?w=800&h=600#top").unwrap(), "01.jpg");
+
+    // 没有文件名时使用地址的哈希，同一地址得到的名称不变
+    let name = parser.get_picture_name("https://img0.dili360.com/ga/M01/").unwrap();
+    assert_eq!(name.len(), 64);
+    assert!(name.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(parser.get_picture_name("https://img0.dili360.com/ga/M01/").unwrap(), name);
+    assert_ne!(parser.get_picture_name("https://img0.dili360.com/ga/M02/").unwrap(), name);
+    assert_eq!(parser.get_picture_name("https://img0.dili360.com").unwrap().len(), 64);
+}
+
+#[test]
+fn test_pagination_scheme_page_url() {
+    let url = "http://localhost/chis/shanshui/1001.html";
+    assert_eq!(PaginationScheme::SuffixUnderscore.page_url(url, 1), url);
+    assert_eq!(PaginationScheme::SuffixUnderscore.page_url(url, 3), "http://localhost/chis/shanshui/1001_3.html");
+    let scheme = PaginationScheme::QueryParam("page".to_string());
+    assert_eq!(scheme.page_url(url, 2), format!("{url}?page=2"));
+    assert_eq!(scheme.page_url(&format!("{url}?id=1"), 2), format!("{url}?id=1&page=2"));
+}
+
+#[test]
+fn test_parser_infos() {
+    let parsers = lmpic_downloader::parser::parsers();
+    assert_eq!(parsers.iter().map(|info| info.code).collect::<Vec<_>>(), vec!["DILI360", "SFTK"]);
+    for info in &parsers {
+        let parser = ParserBuilder::new(info.code).build().unwrap();
+        assert_eq!(parser.parser_name(), info.name);
+        assert_eq!(parser.description(), info.description);
+        assert_eq!(parser.homepage(), info.homepage);
+        assert!(info.supported_features.contains(ParserFeatures::SEARCH | ParserFeatures::DIRECT_URL));
+    }
+    assert!(!parsers[0].supported_features.contains(ParserFeatures::CATEGORIES));
+    assert!(parsers[1].supported_features.contains(ParserFeatures::CATEGORIES));
+
+    let json = serde_json::to_value(&parsers[0]).unwrap();
+    assert_eq!(json["code"], "DILI360");
+    assert_eq!(json["homepage"], "https://www.dili360.com");
+    assert_eq!(json["supported_features"], "SEARCH | PAGINATION | DIRECT_URL | COVER_IMAGE");
+}
+
+#[tokio::test]
+async fn test_sftk_categories() {
+    let server = wiremock::MockServer::start().await;
+    common::mount_gbk_html(&server, "/", "sftk_index.html").await;
+    common::mount_gbk_html(&server, "/shanshui/1.html", "sftk_search.html").await;
+    let parser = ParserBuilder::new("SFTK").base_url(&server.uri()).build().unwrap();
+
+    let categories = parser.list_categories().await.unwrap();
+    assert_eq!(categories, vec![
+        ("fengjing".to_string(), "风景".to_string()),
+        ("shanshui".to_string(), "山水".to_string()),
+        ("renwen".to_string(), "人文".to_string())
+    ]);
+
+    let mut searcher = AlbumSearcher::with_category(parser, "SHANSHUI", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    let albums = searcher.next().await.unwrap().unwrap();
+    assert_eq!(albums.len(), 8);
+    assert_eq!(searcher.page_count(), 7);
+}
+
+#[tokio::test]
+async fn test_dili360_categories_unsupported() {
+    let parser = ParserBuilder::new("DILI360").base_url("http://127.0.0.1:1").build().unwrap();
+    assert!(parser.list_categories().await.unwrap().is_empty());
+    assert!(parser.parse_albums_by_category("travel".to_string(), 1, 10).await.is_err());
+}
+
+async fn received_user_agents(server: &wiremock::MockServer) -> Vec<String> {
+    server.received_requests().await.unwrap().iter()
+        .map(|request| request.headers.get("user-agent").unwrap().to_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_user_agent_rotation() {
+    let server = common::dili360_server().await;
+    let pool = vec!["agent-a".to_string(), "agent-b".to_string()];
+
+    let config = ClientConfig { user_agent_pool: pool.clone(), rotation: UserAgentRotation::RoundRobin, ..ClientConfig::default() };
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).client_config(config).build().unwrap();
+    for page in 1..=3 {
+        parser.parse_albums("云南".to_string(), page, 10).await.unwrap();
+    }
+    assert_eq!(received_user_agents(&server).await, vec!["agent-a", "agent-b", "agent-a"]);
+
+    server.reset().await;
+    common::mount_html(&server, "/cse/site", "dili360_search.html").await;
+    let config = ClientConfig { user_agent_pool: pool.clone(), rotation: UserAgentRotation::Random, ..ClientConfig::default() };
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).client_config(config).build().unwrap();
+    for page in 1..=5 {
+        parser.parse_albums("云南".to_string(), page, 10).await.unwrap();
+    }
+    assert!(received_user_agents(&server).await.iter().all(|ua| pool.contains(ua)));
+}
+
+#[tokio::test]
+async fn test_default_user_agent_is_fixed() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    for page in 1..=2 {
+        parser.parse_albums("云南".to_string(), page, 10).await.unwrap();
+    }
+    assert_eq!(received_user_agents(&server).await, vec![DEFAULT_USER_AGENTS[0]; 2]);
+}
+
+async fn last_search_query(server: &wiremock::MockServer) -> String {
+    let requests = server.received_requests().await.unwrap();
+    requests.last().unwrap().url.query().unwrap_or("").to_string()
+}
+
+#[test]
+fn test_native_page_size() {
+    // 地理 360 可以通过 rn 参数指定每页数量，超过上限时使用上限
+    let parser = ParserBuilder::new("DILI360").build().unwrap();
+    assert_eq!(parser.native_page_size(), 10);
+    assert_eq!(parser.effective_page_size(30), 30);
+    assert_eq!(AlbumSearcher::new(parser.clone(), "云南", 0).size(), 10);
+    assert_eq!(AlbumSearcher::new(parser.clone(), "云南", 20).size(), 20);
+    assert_eq!(AlbumSearcher::new(parser, "云南", 200).size(), 50);
+
+    // 私房图库每页固定 8 个专辑，请求其它数量没有意义
+    let parser = ParserBuilder::new("SFTK").build().unwrap();
+    assert_eq!(parser.native_page_size(), 8);
+    assert_eq!(parser.max_page_size(), 8);
+    assert_eq!(parser.effective_page_size(20), 8);
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    assert_eq!(searcher.size(), 8);
+    searcher.set_size(3);
+    assert_eq!(searcher.size(), 8);
+}
+
+#[tokio::test]
+async fn test_dili360_search_size() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+
+    for (size, rn) in [(10, None), (5, Some("rn=5")), (20, Some("rn=20"))] {
+        let mut searcher = AlbumSearcher::new(parser.clone(), "云南", size);
+        // 预取的请求会影响最后一次请求的检查
+        searcher.disable_prefetch();
+        searcher.next().await.unwrap();
+        let query = last_search_query(&server).await;
+        match rn {
+            Some(rn) => assert!(query.split('&').any(|param| param == rn), "query: {query}"),
+            None => assert!(!query.contains("rn="), "query: {query}")
+        }
+    }
+
+    // 修改每页数量后重新从第一页获取
+    let mut searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    searcher.disable_prefetch();
+    searcher.next().await.unwrap();
+    searcher.next().await.unwrap();
+    searcher.set_size(5);
+    assert_eq!(searcher.size(), 5);
+    searcher.next().await.unwrap();
+    assert_eq!(searcher.page(), 1);
+    assert!(last_search_query(&server).await.contains("rn=5"));
+}
+
+#[tokio::test]
+async fn test_debug_html_dir() {
+    let server = common::dili360_server().await;
+    let dir = tempfile::tempdir().unwrap();
+    let debug_dir = dir.path().join("debug");
+
+    // 未开启时不保存页面
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).build().unwrap();
+    parser.parse_albums("云南".to_string(), 1, 10).await.unwrap();
+    assert!(!debug_dir.exists());
+
+    let parser = ParserBuilder::new("DILI360").base_url(&server.uri()).debug_html_dir(&debug_dir).build().unwrap();
+    let (albums, _) = parser.parse_albums("云南".to_string(), 1, 10).await.unwrap();
+    parser.get_all_pictures(albums[0].url.clone()).await.unwrap();
+    let pages: Vec<String> = std::fs::read_dir(&debug_dir).unwrap()
+        .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    assert_eq!(pages.len(), 2);
+    assert!(pages.iter().any(|page| page.contains("pageFooter")));
+}
+
+#[test]
+fn test_tls_root_certificates() {
+    let tls = TlsConfig { root_certificates: vec![common::fixture_path("test_root_ca.pem")], accept_invalid_certs: false };
+    assert!(tls.build_client().is_ok());
+    assert!(TlsConfig { accept_invalid_certs: true, ..TlsConfig::default() }.build_client().is_ok());
+
+    // 不是证书的文件以及不存在的文件
+    let err = TlsConfig { root_certificates: vec![common::fixture_path("dili360_album.html")], ..TlsConfig::default() }.build_client().err().unwrap();
+    assert!(err.to_string().contains("no certificate found"), "{err:#}");
+    let missing = common::fixture_path("missing.pem");
+    let tls = TlsConfig { root_certificates: vec![missing], ..TlsConfig::default() };
+    assert!(tls.build_client().is_err());
+
+    // 创建解析器时使用 TLS 配置创建 Client
+    let config = ClientConfig { tls, ..ClientConfig::default() };
+    assert!(ParserBuilder::new("SFTK").client_config(config.clone()).build().is_err());
+    assert!(ParserBuilder::new("SFTK").client_config(config).client(reqwest::Client::new()).build().is_ok());
+}
+
+#[test]
+fn test_ip_preference() {
+    assert_eq!("ipv4".parse::<IpPreference>().unwrap(), IpPreference::V4);
+    assert_eq!(" V6 ".parse::<IpPreference>().unwrap(), IpPreference::V6);
+    assert_eq!("".parse::<IpPreference>().unwrap(), IpPreference::Any);
+    assert!("ipv5".parse::<IpPreference>().is_err());
+
+    // 实际连接哪个地址取决于网络环境，这里只检查 Client 绑定的本地地址
+    let builder = IpPreference::V4.apply(reqwest::Client::builder());
+    assert!(format!("{:?}", builder).contains("local_address: 0.0.0.0"));
+    let builder = IpPreference::V6.apply(reqwest::Client::builder());
+    assert!(format!("{:?}", builder).contains("local_address: ::"));
+    let builder = IpPreference::Any.apply(reqwest::Client::builder());
+    assert!(!format!("{:?}", builder).contains("local_address"));
+
+    let config = ClientConfig { ip_preference: IpPreference::V4, ..ClientConfig::default() };
+    assert!(config.build_client().is_ok());
+}
+
+#[tokio::test]
+async fn test_bearer_auth() {
+    let server = common::dili360_server().await;
+    let parser = ParserBuilder::new("DILI360")
+        .base_url(&server.uri())
+        .auth(Auth::Bearer("token".to_string()))
+        .build()
+        .unwrap();
+    parser.parse_albums("云南".to_string(), 1, 10).await.unwrap();
+
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests[0].headers.get("authorization").unwrap(), "Bearer token");
+    assert!(!format!("{:?}", Auth::Bearer("token".to_string())).contains("token"));
+}
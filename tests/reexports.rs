@@ -0,0 +1,24 @@
+use std::sync::Arc;
+
+use lmpic_downloader::{Album, AlbumSearcher, Parser};
+
+fn parser_name(parser: &Arc<dyn Parser>) -> String {
+    parser.parser_name()
+}
+
+#[test]
+fn test_crate_root_reexports() {
+    let parser: Arc<dyn Parser> = lmpic_downloader::parser::parse("SFTK", None).unwrap();
+    assert_eq!(parser_name(&parser), "私房图库");
+
+    let searcher = AlbumSearcher::new(parser, "云南", AlbumSearcher::DEFAULT_PAGE_SIZE);
+    assert_eq!(searcher.page(), 0);
+
+    let album = Album {
+        name: "云南".to_string(),
+        cover: None,
+        url: "https://www.dili360.com/".to_string(),
+        picture_count_hint: None
+    };
+    assert_eq!(album.name, "云南");
+}